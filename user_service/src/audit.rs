@@ -0,0 +1,510 @@
+use crate::auth::Principal;
+use crate::db::{Store, StoreError, TenantStore};
+use crate::models::{AuditAction, AuditCheckpoint, AuditEvent, FieldChange, RequestId};
+use crate::tenant::TenantId;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+/// The `prev_hash` of the first event of an audit log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How long repeated rejections of a client are only recorded once.
+pub const DENIAL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Fields that change with every change, which would only clutter the audit
+/// log.
+const UNAUDITED_FIELDS: [&str; 2] = ["updated_at", "version"];
+
+/// The name a principal is recorded under, `anonymous` for requests without
+/// one.
+pub fn actor(principal: Option<&Principal>) -> String {
+    principal.map_or_else(|| "anonymous".to_string(), |principal| principal.0.clone())
+}
+
+/// An event of the current request, without a target or changes.
+pub fn event(action: AuditAction, actor: String) -> AuditEvent {
+    AuditEvent {
+        id: Uuid::now_v7(),
+        at: SystemTime::now(),
+        actor,
+        action,
+        target: None,
+        target_id: None,
+        changes: BTreeMap::new(),
+        reason: None,
+        request_id: RequestId::current().map(|id| id.0),
+        prev_hash: String::new(),
+        hash: String::new(),
+    }
+}
+
+/// The fields that differ between `before` and `after`. Either is `None` for
+/// something that was created or deleted, so all its fields changed.
+pub fn changes<T: Serialize>(
+    before: Option<&T>,
+    after: Option<&T>,
+) -> BTreeMap<String, FieldChange> {
+    let mut before = fields(before);
+    let mut after = fields(after);
+    let names: BTreeSet<String> = before.keys().chain(after.keys()).cloned().collect();
+
+    names
+        .into_iter()
+        .filter(|name| !UNAUDITED_FIELDS.contains(&name.as_str()))
+        .filter_map(|name| {
+            let change = FieldChange {
+                before: before.remove(&name).unwrap_or_default(),
+                after: after.remove(&name).unwrap_or_default(),
+            };
+            (change.before != change.after).then_some((name, change))
+        })
+        .collect()
+}
+
+fn fields<T: Serialize>(value: Option<&T>) -> Map<String, Value> {
+    match value.map(serde_json::to_value) {
+        Some(Ok(Value::Object(fields))) => fields,
+        _ => Map::new(),
+    }
+}
+
+/// Append an event to the audit log of a tenant. The change the event
+/// records was already made, so a failure is only logged, with the event, so
+/// it can still be recovered from the logs.
+pub async fn record(store: &dyn TenantStore, event: AuditEvent) {
+    let action = event.action;
+    if let Err(err) = store.append_audit_event(event.clone()).await {
+        error!(%err, %action, ?event, "Unable to record an audit event");
+    }
+}
+
+/// Append the event of a rejected request to the audit log of a tenant,
+/// unless the client was rejected the same way during the last
+/// [`DENIAL_INTERVAL`]. Anyone can send requests that are rejected, so
+/// recording every one would let them grow the log without bound.
+pub async fn record_denial(
+    store: &dyn TenantStore,
+    denials: &RecentDenials,
+    tenant: &TenantId,
+    client: IpAddr,
+    event: AuditEvent,
+) {
+    let denial = Denial {
+        tenant: tenant.clone(),
+        client,
+        actor: event.actor.clone(),
+        action: event.action,
+        reason: event.reason.clone(),
+    };
+    if denials.should_record(denial, Instant::now()) {
+        record(store, event).await;
+    }
+}
+
+/// A rejected request, as far as it tells repeated rejections apart.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Denial {
+    pub tenant: TenantId,
+    pub client: IpAddr,
+    pub actor: String,
+    pub action: AuditAction,
+    pub reason: Option<String>,
+}
+
+/// The rejections recorded in the audit log during the last
+/// [`DENIAL_INTERVAL`], so a client that keeps being rejected, like one
+/// guessing API keys, does not add an event for every request.
+pub struct RecentDenials {
+    recorded: Mutex<Recorded>,
+}
+
+struct Recorded {
+    at: HashMap<Denial, Instant>,
+    last_pruned: Instant,
+}
+
+impl Default for RecentDenials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecentDenials {
+    pub fn new() -> Self {
+        Self {
+            recorded: Mutex::new(Recorded {
+                at: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Whether a rejection has to be recorded, because no equal one was
+    /// recorded during the last interval. Rejections recorded before that
+    /// are forgotten once per interval.
+    pub fn should_record(&self, denial: Denial, now: Instant) -> bool {
+        let mut recorded = self.recorded.lock().expect("denials lock is poisoned");
+        if now.saturating_duration_since(recorded.last_pruned) >= DENIAL_INTERVAL {
+            recorded
+                .at
+                .retain(|_, at| now.saturating_duration_since(*at) < DENIAL_INTERVAL);
+            recorded.last_pruned = now;
+        }
+
+        match recorded.at.get(&denial) {
+            Some(at) if now.saturating_duration_since(*at) < DENIAL_INTERVAL => false,
+            _ => {
+                recorded.at.insert(denial, now);
+                true
+            }
+        }
+    }
+}
+
+/// Link an event to the event before it in the audit log, `None` if it is
+/// the first one, and assign its hash. Stores call this when they append an
+/// event, while no other event can be appended.
+pub fn chain(event: &mut AuditEvent, prev: Option<&AuditEvent>) {
+    event.prev_hash = prev.map_or_else(|| GENESIS_HASH.to_string(), |prev| prev.hash.clone());
+    event.hash = hash(event);
+}
+
+/// The hex encoded SHA-256 of the JSON form of an event, leaving out its own
+/// hash. Fields are serialized in a fixed order, so the hash of an event
+/// does not change when it is exported and parsed again.
+pub fn hash(event: &AuditEvent) -> String {
+    let unhashed = AuditEvent {
+        hash: String::new(),
+        ..event.clone()
+    };
+    let json = serde_json::to_vec(&unhashed).expect("audit events are serializable");
+
+    digest(&SHA256, &json)
+        .as_ref()
+        .iter()
+        .fold(String::new(), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+}
+
+/// The first link of an audit log that does not hold. Positions count from
+/// one, in the order the events were appended.
+#[derive(Debug, Error, PartialEq)]
+pub enum BrokenLink {
+    #[error("event {id} at position {position} was altered, its hash does not match its contents")]
+    Altered { position: usize, id: Uuid },
+
+    #[error("event {id} at position {position} does not follow the event before it, events were removed, inserted or reordered")]
+    Unlinked { position: usize, id: Uuid },
+}
+
+/// Walk the events of an audit log, from the first one on, and check that
+/// every event is unchanged and follows the event before it.
+pub fn verify_chain(events: &[AuditEvent]) -> Result<(), BrokenLink> {
+    let mut prev_hash = GENESIS_HASH;
+    for (index, event) in events.iter().enumerate() {
+        let (position, id) = (index + 1, event.id);
+        if hash(event) != event.hash {
+            return Err(BrokenLink::Altered { position, id });
+        }
+        if event.prev_hash != prev_hash {
+            return Err(BrokenLink::Unlinked { position, id });
+        }
+        prev_hash = &event.hash;
+    }
+
+    Ok(())
+}
+
+/// Sign that the audit log of a tenant ends with `event`.
+pub fn sign_checkpoint(
+    key: &Ed25519KeyPair,
+    tenant: &TenantId,
+    event: &AuditEvent,
+    at: SystemTime,
+) -> AuditCheckpoint {
+    let mut checkpoint = AuditCheckpoint {
+        tenant: tenant.to_string(),
+        event_id: event.id,
+        hash: event.hash.clone(),
+        at,
+        signature: String::new(),
+    };
+    checkpoint.signature = BASE64.encode(key.sign(&signed_message(&checkpoint)));
+    checkpoint
+}
+
+/// Whether a checkpoint was signed by the key pair with the given base64
+/// encoded public key.
+pub fn verify_checkpoint(checkpoint: &AuditCheckpoint, public_key: &str) -> bool {
+    let (Ok(public_key), Ok(signature)) = (
+        BASE64.decode(public_key),
+        BASE64.decode(&checkpoint.signature),
+    ) else {
+        return false;
+    };
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&signed_message(checkpoint), &signature)
+        .is_ok()
+}
+
+/// The base64 encoded public key of a key pair, which auditors need to
+/// verify its checkpoints.
+pub fn public_key(key: &Ed25519KeyPair) -> String {
+    BASE64.encode(key.public_key())
+}
+
+fn signed_message(checkpoint: &AuditCheckpoint) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}\n{}",
+        checkpoint.tenant,
+        checkpoint.event_id,
+        checkpoint.hash,
+        humantime::format_rfc3339(checkpoint.at)
+    )
+    .into_bytes()
+}
+
+/// Periodically signs a checkpoint for every tenant whose audit log changed
+/// since its last checkpoint.
+pub struct Checkpointer {
+    store: Arc<dyn Store>,
+    key: Ed25519KeyPair,
+}
+
+impl Checkpointer {
+    pub fn new(store: Arc<dyn Store>, key: Ed25519KeyPair) -> Self {
+        Self { store, key }
+    }
+
+    pub fn public_key(&self) -> String {
+        public_key(&self.key)
+    }
+
+    /// Sign a checkpoint for every tenant with new events, returning how many
+    /// were signed.
+    pub async fn checkpoint(&self) -> Result<usize, StoreError> {
+        let now = SystemTime::now();
+        let mut signed = 0;
+        for tenant in self.store.tenants().await? {
+            let store = self.store.tenant(&tenant);
+            let Some(last) = store.last_audit_event().await? else {
+                continue;
+            };
+            let checkpoints = store.audit_checkpoints().await?;
+            if checkpoints.last().is_some_and(|cp| cp.event_id == last.id) {
+                continue;
+            }
+
+            let checkpoint = sign_checkpoint(&self.key, &tenant, &last, now);
+            store.append_audit_checkpoint(checkpoint).await?;
+            signed += 1;
+        }
+
+        Ok(signed)
+    }
+
+    /// Sign checkpoints at the given interval, forever. Failures are logged
+    /// and retried at the next interval.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match self.checkpoint().await {
+                Ok(signed) => debug!(signed, "Signed audit checkpoints"),
+                Err(err) => error!(%err, "Unable to sign audit checkpoints"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::memory::MemoryStore;
+    use serde_json::json;
+
+    #[derive(Serialize)]
+    struct Thing {
+        name: &'static str,
+        email: Option<&'static str>,
+        version: u64,
+    }
+
+    #[test]
+    fn only_changed_fields_are_recorded() {
+        let before = Thing {
+            name: "Jane",
+            email: None,
+            version: 1,
+        };
+        let after = Thing {
+            name: "Jane",
+            email: Some("jane@example.com"),
+            version: 2,
+        };
+
+        let updated = changes(Some(&before), Some(&after));
+        let deleted = changes(Some(&before), None);
+
+        assert_eq!(
+            BTreeMap::from([(
+                "email".to_string(),
+                FieldChange {
+                    before: Value::Null,
+                    after: json!("jane@example.com"),
+                }
+            )]),
+            updated
+        );
+        assert_eq!(
+            BTreeMap::from([(
+                "name".to_string(),
+                FieldChange {
+                    before: json!("Jane"),
+                    after: Value::Null,
+                }
+            )]),
+            deleted
+        );
+    }
+
+    fn chained(actors: &[&str]) -> Vec<AuditEvent> {
+        let mut events: Vec<AuditEvent> = Vec::new();
+        for actor in actors {
+            let mut event = event(AuditAction::UserUpdated, actor.to_string());
+            chain(&mut event, events.last());
+            events.push(event);
+        }
+        events
+    }
+
+    fn key() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn the_first_broken_link_is_reported() {
+        let events = chained(&["a", "b", "c", "d"]);
+        assert_eq!(Ok(()), verify_chain(&events));
+        assert_eq!(GENESIS_HASH, events[0].prev_hash);
+
+        let mut altered = events.clone();
+        altered[1].actor = "mallory".to_string();
+        assert_eq!(
+            Err(BrokenLink::Altered {
+                position: 2,
+                id: events[1].id
+            }),
+            verify_chain(&altered)
+        );
+
+        // Recomputing the hash of an altered event breaks the next link.
+        altered[1].hash = hash(&altered[1]);
+        assert_eq!(
+            Err(BrokenLink::Unlinked {
+                position: 3,
+                id: events[2].id
+            }),
+            verify_chain(&altered)
+        );
+
+        let mut removed = events.clone();
+        removed.remove(2);
+        assert_eq!(
+            Err(BrokenLink::Unlinked {
+                position: 3,
+                id: events[3].id
+            }),
+            verify_chain(&removed)
+        );
+    }
+
+    #[test]
+    fn checkpoints_are_verified_with_the_public_key() {
+        let events = chained(&["a"]);
+        let tenant = TenantId::default();
+        let checkpoint = sign_checkpoint(&key(), &tenant, &events[0], SystemTime::now());
+        let other = Ed25519KeyPair::from_seed_unchecked(&[8; 32]).unwrap();
+
+        assert!(verify_checkpoint(&checkpoint, &public_key(&key())));
+        assert!(!verify_checkpoint(&checkpoint, &public_key(&other)));
+        assert!(!verify_checkpoint(
+            &AuditCheckpoint {
+                hash: GENESIS_HASH.to_string(),
+                ..checkpoint.clone()
+            },
+            &public_key(&key())
+        ));
+        assert!(!verify_checkpoint(&checkpoint, "not base64!"));
+    }
+
+    #[test]
+    fn repeated_denials_are_recorded_once_per_interval() {
+        let denials = RecentDenials::new();
+        let denial = Denial {
+            tenant: TenantId::parse("globex").unwrap(),
+            client: "192.0.2.1".parse().unwrap(),
+            actor: "anonymous".to_string(),
+            action: AuditAction::AuthFailed,
+            reason: Some("unauthenticated".to_string()),
+        };
+        let other_client = Denial {
+            client: "192.0.2.2".parse().unwrap(),
+            ..denial.clone()
+        };
+        let now = Instant::now();
+
+        assert!(denials.should_record(denial.clone(), now));
+        assert!(!denials.should_record(denial.clone(), now + Duration::from_secs(1)));
+        assert!(denials.should_record(other_client, now + Duration::from_secs(1)));
+        assert!(denials.should_record(denial, now + DENIAL_INTERVAL));
+    }
+
+    #[tokio::test]
+    async fn only_changed_logs_are_checkpointed() {
+        let store = Arc::new(MemoryStore::new());
+        let acme = store.tenant(&TenantId::parse("acme").unwrap());
+        let globex = store.tenant(&TenantId::parse("globex").unwrap());
+        let checkpointer = Checkpointer::new(store.clone(), key());
+        for store in [&acme, &globex] {
+            store
+                .append_audit_event(event(AuditAction::UserCreated, "ci".to_string()))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(2, checkpointer.checkpoint().await.unwrap());
+        assert_eq!(0, checkpointer.checkpoint().await.unwrap());
+
+        acme.append_audit_event(event(AuditAction::UserUpdated, "ci".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(1, checkpointer.checkpoint().await.unwrap());
+
+        let checkpoints = acme.audit_checkpoints().await.unwrap();
+        let last = acme.last_audit_event().await.unwrap().unwrap();
+        assert_eq!(2, checkpoints.len());
+        assert_eq!(last.hash, checkpoints[1].hash);
+        assert_eq!("acme", checkpoints[1].tenant);
+        assert!(verify_checkpoint(
+            &checkpoints[1],
+            &checkpointer.public_key()
+        ));
+    }
+}
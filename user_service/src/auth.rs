@@ -0,0 +1,231 @@
+use crate::config::AuthConfig;
+use crate::models::{AuthError, Role};
+use crate::tenant::TenantId;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The identity on whose behalf a request is made.
+///
+/// Once a request has been authenticated the principal is stored in the
+/// request and response extensions, so that handlers and middleware like the
+/// access log can use it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+/// What an API key identifies: the principal, and the tenant the key belongs
+/// to if it belongs to one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub principal: Principal,
+    pub tenant: Option<TenantId>,
+}
+
+/// Resolves the API key of a request to a principal.
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    required: bool,
+    api_keys: HashMap<String, Credentials>,
+}
+
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the API keys themselves.
+        f.debug_struct("Authenticator")
+            .field("required", &self.required)
+            .field(
+                "principals",
+                &self
+                    .api_keys
+                    .values()
+                    .map(|credentials| &credentials.principal)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|api_key| {
+                let credentials = Credentials {
+                    principal: Principal(api_key.principal.clone()),
+                    tenant: api_key.tenant.clone(),
+                };
+                (api_key.key.expose().to_string(), credentials)
+            })
+            .collect();
+
+        Self {
+            required: config.required,
+            api_keys,
+        }
+    }
+
+    /// Authenticate a request using the bearer token from its `Authorization`
+    /// header, if it has one. Anonymous requests are only allowed if
+    /// authentication is not required.
+    pub fn authenticate(
+        &self,
+        authorization: Option<&str>,
+    ) -> Result<Option<Credentials>, AuthError> {
+        let Some(authorization) = authorization else {
+            return if self.required {
+                Err(AuthError::Unauthenticated)
+            } else {
+                Ok(None)
+            };
+        };
+
+        let key = authorization
+            .strip_prefix("Bearer ")
+            .ok_or(AuthError::Unauthenticated)?;
+
+        self.api_keys
+            .get(key.trim())
+            .cloned()
+            .map(Some)
+            .ok_or(AuthError::Unauthenticated)
+    }
+}
+
+/// Decides whether a principal may make a change, from the roles of its API
+/// key and the roles of its groups.
+#[derive(Clone, Debug, Default)]
+pub struct Authorizer {
+    enforced: bool,
+    roles: HashMap<String, Vec<Role>>,
+}
+
+impl Authorizer {
+    pub fn new(config: &AuthConfig) -> Self {
+        let mut roles: HashMap<String, Vec<Role>> = HashMap::new();
+        for api_key in &config.api_keys {
+            roles
+                .entry(api_key.principal.clone())
+                .or_default()
+                .extend(&api_key.roles);
+        }
+
+        Self {
+            enforced: config.enforce_roles,
+            roles,
+        }
+    }
+
+    /// Whether roles are checked at all. If not, every change is allowed and
+    /// the roles of groups do not have to be looked up.
+    pub fn is_enforced(&self) -> bool {
+        self.enforced
+    }
+
+    /// Whether the principal may make a change that requires the given
+    /// role, with `group_roles` granted by its groups. Anonymous requests
+    /// have no roles.
+    pub fn allows(
+        &self,
+        principal: Option<&Principal>,
+        group_roles: &[Role],
+        required: Role,
+    ) -> bool {
+        if !self.enforced {
+            return true;
+        }
+        let Some(principal) = principal else {
+            return false;
+        };
+
+        self.roles
+            .get(&principal.0)
+            .into_iter()
+            .flatten()
+            .chain(group_roles)
+            .any(|role| role.grants(required))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ApiKeyConfig, Secret};
+
+    fn authenticator(required: bool) -> Authenticator {
+        Authenticator::new(&AuthConfig {
+            required,
+            enforce_roles: false,
+            api_keys: vec![ApiKeyConfig {
+                principal: "ci".to_string(),
+                key: Secret::new("s3cr3t"),
+                roles: Vec::new(),
+                tenant: None,
+            }],
+        })
+    }
+
+    #[test]
+    fn authenticate() {
+        let tests = vec![
+            (false, None, Ok(None)),
+            (true, None, Err(AuthError::Unauthenticated)),
+            (
+                false,
+                Some("Bearer s3cr3t"),
+                Ok(Some(Credentials {
+                    principal: Principal("ci".to_string()),
+                    tenant: None,
+                })),
+            ),
+            (false, Some("Bearer wrong"), Err(AuthError::Unauthenticated)),
+            (false, Some("Basic s3cr3t"), Err(AuthError::Unauthenticated)),
+        ];
+
+        for (required, authorization, expected) in tests {
+            let actual = authenticator(required).authenticate(authorization);
+
+            assert_eq!(expected, actual, "{authorization:?}");
+        }
+    }
+
+    #[test]
+    fn authorize() {
+        let config = |enforce_roles| AuthConfig {
+            required: false,
+            enforce_roles,
+            api_keys: vec![ApiKeyConfig {
+                principal: "ci".to_string(),
+                key: Secret::new("s3cr3t"),
+                roles: vec![Role::UserAdmin],
+                tenant: None,
+            }],
+        };
+        let ci = Principal("ci".to_string());
+        let jane = Principal("jane".to_string());
+        let tests = vec![
+            (false, None, vec![], Role::Admin, true),
+            (true, None, vec![Role::Admin], Role::UserAdmin, false),
+            (true, Some(&ci), vec![], Role::UserAdmin, true),
+            (true, Some(&ci), vec![], Role::GroupAdmin, false),
+            (true, Some(&jane), vec![], Role::UserAdmin, false),
+            (
+                true,
+                Some(&jane),
+                vec![Role::GroupAdmin],
+                Role::GroupAdmin,
+                true,
+            ),
+            (true, Some(&jane), vec![Role::Admin], Role::UserAdmin, true),
+        ];
+
+        for (enforce_roles, principal, group_roles, required, expected) in tests {
+            let authorizer = Authorizer::new(&config(enforce_roles));
+
+            assert_eq!(
+                expected,
+                authorizer.allows(principal, &group_roles, required),
+                "{principal:?} {group_roles:?} {required}"
+            );
+        }
+    }
+}
@@ -1,38 +1,127 @@
-use crate::models::{CreateUserError, GetUserError, NewUser, User};
-use http::{header::CONTENT_TYPE, Method, StatusCode};
+use crate::models::{
+    AuditCheckpoint, AuditError, AuditEvent, AuditFilter, AuthError, ChangeStatusError,
+    CreateUserError, GetUserError, Group, GroupError, GroupMember, GroupUpdate, IdempotencyError,
+    JsonBodyError, ListUsersError, NewGroup, NewUser, Page, RenameUserError, StatusChange,
+    StatusTransition, UpdateUserError, User, UserFilter, UserStatus, UserUpdate, UsernameChange,
+    IDEMPOTENCY_KEY_HEADER, REQUEST_ID_HEADER, TENANT_HEADER,
+};
+use http::{
+    header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, RETRY_AFTER},
+    HeaderMap, HeaderValue, Method, StatusCode,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
 
 pub struct Client {
     base_url: url::Url,
     client: reqwest::Client,
+    api_key: Option<String>,
+    tenant: Option<String>,
 }
 
 impl Client {
     pub fn new(base_url: url::Url) -> Self {
         let client = reqwest::Client::new();
-        Self { base_url, client }
+        Self {
+            base_url,
+            client,
+            api_key: None,
+            tenant: None,
+        }
+    }
+
+    /// Authenticate all requests with the given API key.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Make all requests for the given tenant, named in the `X-Tenant-Id`
+    /// header. Servers that take the tenant from the subdomain or the API
+    /// key ignore this.
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
     }
 
     async fn do_req<T, E>(
         &self,
         method: Method,
         path: impl AsRef<str>,
-        query: Option<String>, // Probably needs a different type
+        query: &[(&str, String)],
         payload: Option<Vec<u8>>,
+        headers: HeaderMap,
     ) -> Result<T, ClientError<E>>
     where
         T: DeserializeOwned,
         E: DeserializeOwned,
+    {
+        let response = self.send(method, path, query, payload, headers).await?;
+        response.json().await.map_err(map_to_client_err)
+    }
+
+    /// Like `do_req`, but also returns the entity tag of the response, and
+    /// `None` if the server responded with 304 Not Modified.
+    async fn do_tagged_req<T, E>(
+        &self,
+        method: Method,
+        path: impl AsRef<str>,
+        payload: Option<Vec<u8>>,
+        headers: HeaderMap,
+    ) -> Result<Option<Tagged<T>>, ClientError<E>>
+    where
+        T: DeserializeOwned,
+        E: DeserializeOwned,
+    {
+        let response = self.send(method, path, &[], payload, headers).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        let value = response.json().await.map_err(map_to_client_err)?;
+
+        Ok(Some(Tagged { value, etag }))
+    }
+
+    /// Send a request, turning every response that is neither successful nor
+    /// 304 Not Modified into an error.
+    async fn send<E>(
+        &self,
+        method: Method,
+        path: impl AsRef<str>,
+        query: &[(&str, String)],
+        payload: Option<Vec<u8>>,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response, ClientError<E>>
+    where
+        E: DeserializeOwned,
     {
         // Make request -> DNS lookup, TCP connection, TLS invalid, timeout
         // Get response -> unauthorized, unauthenticated, invalid json result
         let url = self.base_url.join(path.as_ref()).unwrap();
 
-        let mut request = self.client.request(method, url);
+        let mut request = self
+            .client
+            .request(method, url)
+            .header(REQUEST_ID_HEADER, Uuid::now_v7().to_string())
+            .headers(headers);
+
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        if let Some(tenant) = &self.tenant {
+            request = request.header(TENANT_HEADER, tenant);
+        }
 
-        if let Some(query) = query {
-            request = request.query(&query);
+        if !query.is_empty() {
+            request = request.query(query);
         }
 
         if let Some(payload) = payload {
@@ -43,26 +132,78 @@ impl Client {
 
         let response = request.send().await.map_err(map_to_client_err)?;
         let status_code = response.status();
-        if !status_code.is_success() {
+        if !status_code.is_success() && status_code != StatusCode::NOT_MODIFIED {
+            // The server echoes the request id, which is needed to find the
+            // request in its logs.
+            let request_id = response
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string);
+
             if status_code == StatusCode::UNAUTHORIZED {
-                return Err(ClientError::Unauthenticated);
+                return Err(ClientError::Unauthenticated { request_id });
             }
 
             if status_code == StatusCode::FORBIDDEN {
-                return Err(ClientError::Unauthorized);
+                let body = response.bytes().await.map_err(map_to_client_err)?;
+                return Err(match serde_json::from_slice(&body) {
+                    Ok(AuthError::InactiveAccount { status }) => {
+                        ClientError::InactiveAccount { status, request_id }
+                    }
+                    Ok(error @ AuthError::TenantNotAllowed) => {
+                        ClientError::InvalidTenant { error, request_id }
+                    }
+                    _ => ClientError::Unauthorized { request_id },
+                });
+            }
+
+            // Retry-After is only sent in seconds by the service.
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+
+            if status_code == StatusCode::TOO_MANY_REQUESTS {
+                return Err(ClientError::RateLimited {
+                    retry_after,
+                    request_id,
+                });
+            }
+
+            if status_code == StatusCode::SERVICE_UNAVAILABLE {
+                return Err(ClientError::Unavailable {
+                    retry_after,
+                    request_id,
+                });
             }
 
             // looks like an execeptional status was returned. The body _could_
-            // contain a service error.
-            if let Ok(err) = response.json::<E>().await {
-                return Err(ClientError::ServiceError(err));
+            // contain a service error, or describe why the request body was
+            // rejected.
+            let body = response.bytes().await.map_err(map_to_client_err)?;
+            if let Ok(error) = serde_json::from_slice::<E>(&body) {
+                return Err(ClientError::ServiceError { error, request_id });
+            }
+            if let Ok(error) = serde_json::from_slice::<JsonBodyError>(&body) {
+                return Err(ClientError::InvalidRequest { error, request_id });
+            }
+            if let Ok(error) = serde_json::from_slice::<IdempotencyError>(&body) {
+                return Err(ClientError::IdempotencyConflict { error, request_id });
+            }
+            if let Ok(error @ (AuthError::MissingTenant | AuthError::InvalidTenant { .. })) =
+                serde_json::from_slice(&body)
+            {
+                return Err(ClientError::InvalidTenant { error, request_id });
             }
 
-            return Err(ClientError::UnknownError);
+            return Err(ClientError::UnknownError {
+                status: Some(status_code.as_u16()),
+                request_id,
+            });
         }
 
-        let response = response.json().await.map_err(map_to_client_err)?;
-
         Ok(response)
     }
 
@@ -71,22 +212,447 @@ impl Client {
         username: impl AsRef<str>,
     ) -> Result<User, ClientError<GetUserError>> {
         self.do_req(
+            Method::GET,
+            format!("users/{username}", username = username.as_ref()),
+            &[],
+            None,
+            HeaderMap::new(),
+        )
+        .await
+    }
+
+    /// Get a user together with its entity tag, which can be used for
+    /// conditional requests. If `etag` is given and still is the entity tag
+    /// of the user, the user was not modified and `None` is returned.
+    pub async fn get_user_if_none_match(
+        &self,
+        username: impl AsRef<str>,
+        etag: Option<&str>,
+    ) -> Result<Option<Tagged<User>>, ClientError<GetUserError>> {
+        let headers = precondition(IF_NONE_MATCH, etag)?;
+        self.do_tagged_req(
             Method::GET,
             format!("users/{username}", username = username.as_ref()),
             None,
+            headers,
+        )
+        .await
+    }
+
+    /// Get a user by its id, which never changes. Like
+    /// [`Client::get_user_if_none_match`], this returns `None` if `etag` is
+    /// given and still is the entity tag of the user.
+    pub async fn get_user_by_id(
+        &self,
+        id: Uuid,
+        etag: Option<&str>,
+    ) -> Result<Option<Tagged<User>>, ClientError<GetUserError>> {
+        let headers = precondition(IF_NONE_MATCH, etag)?;
+        self.do_tagged_req(Method::GET, format!("users/by-id/{id}"), None, headers)
+            .await
+    }
+
+    /// One page of the users that match `filter`, ordered by id.
+    pub async fn list_users(
+        &self,
+        filter: &UserFilter,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Page<User>, ClientError<ListUsersError>> {
+        let attributes = filter.to_query();
+        let mut query: Vec<(&str, String)> = attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        query.extend(page_query(cursor, limit));
+        self.do_req(Method::GET, "users", &query, None, HeaderMap::new())
+            .await
+    }
+
+    /// Change the fields of a user that are set in `update`. If `etag` is
+    /// given, the user is only changed if that still is its entity tag,
+    /// otherwise this fails with [`UpdateUserError::PreconditionFailed`].
+    pub async fn update_user_if_match(
+        &self,
+        username: impl AsRef<str>,
+        update: UserUpdate,
+        etag: Option<&str>,
+    ) -> Result<Tagged<User>, ClientError<UpdateUserError>> {
+        let headers = precondition(IF_MATCH, etag)?;
+        let payload = serde_json::to_vec(&update).unwrap();
+        self.do_tagged_req(
+            Method::PATCH,
+            format!("users/{username}", username = username.as_ref()),
+            Some(payload),
+            headers,
+        )
+        .await?
+        .ok_or_else(ClientError::unknown)
+    }
+
+    /// Change the username of a user, which keeps its id. If `etag` is given,
+    /// the user is only renamed if that still is its entity tag.
+    pub async fn rename_user_if_match(
+        &self,
+        username: impl AsRef<str>,
+        new_username: impl Into<String>,
+        etag: Option<&str>,
+    ) -> Result<Tagged<User>, ClientError<RenameUserError>> {
+        let headers = precondition(IF_MATCH, etag)?;
+        let change = UsernameChange {
+            username: new_username.into(),
+        };
+        let payload = serde_json::to_vec(&change).unwrap();
+        self.do_tagged_req(
+            Method::POST,
+            format!("users/{username}/rename", username = username.as_ref()),
+            Some(payload),
+            headers,
+        )
+        .await?
+        .ok_or_else(ClientError::unknown)
+    }
+
+    /// Change the status of a user, like suspending or reactivating it. If
+    /// `etag` is given, the status is only changed if that still is the
+    /// entity tag of the user.
+    pub async fn change_user_status_if_match(
+        &self,
+        username: impl AsRef<str>,
+        change: StatusChange,
+        etag: Option<&str>,
+    ) -> Result<Tagged<User>, ClientError<ChangeStatusError>> {
+        let headers = precondition(IF_MATCH, etag)?;
+        let payload = serde_json::to_vec(&change).unwrap();
+        self.do_tagged_req(
+            Method::POST,
+            format!("users/{username}/status", username = username.as_ref()),
+            Some(payload),
+            headers,
+        )
+        .await?
+        .ok_or_else(ClientError::unknown)
+    }
+
+    /// The status transitions of a user, oldest first.
+    pub async fn get_status_transitions(
+        &self,
+        username: impl AsRef<str>,
+    ) -> Result<Vec<StatusTransition>, ClientError<GetUserError>> {
+        self.do_req(
+            Method::GET,
+            format!(
+                "users/{username}/status-transitions",
+                username = username.as_ref()
+            ),
+            &[],
             None,
+            HeaderMap::new(),
         )
         .await
     }
 
+    /// Report a failed login of a user, which locks the user after too many
+    /// failed logins in a row.
+    pub async fn record_failed_login(
+        &self,
+        username: impl AsRef<str>,
+    ) -> Result<Tagged<User>, ClientError<GetUserError>> {
+        self.do_tagged_req(
+            Method::POST,
+            format!(
+                "users/{username}/failed-logins",
+                username = username.as_ref()
+            ),
+            None,
+            HeaderMap::new(),
+        )
+        .await?
+        .ok_or_else(ClientError::unknown)
+    }
+
+    /// Create a user. If an idempotency key is given, the request can safely
+    /// be retried with the same key: the user is only created once, and every
+    /// retry gets the original response.
     pub async fn create_user(
         &self,
         new_user: NewUser,
+        idempotency_key: Option<&str>,
     ) -> Result<User, ClientError<CreateUserError>> {
+        let mut headers = HeaderMap::new();
+        if let Some(key) = idempotency_key {
+            let value = HeaderValue::from_str(key).map_err(|_| ClientError::unknown())?;
+            headers.insert(IDEMPOTENCY_KEY_HEADER, value);
+        }
         let payload = serde_json::to_vec(&new_user).unwrap();
-        self.do_req(Method::POST, "users", None, Some(payload))
+        self.do_req(Method::POST, "users", &[], Some(payload), headers)
+            .await
+    }
+
+    /// One page of all groups, ordered by name. Pass the `next_cursor` of a
+    /// page to get the page after it.
+    pub async fn list_groups(
+        &self,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Page<Group>, ClientError<GroupError>> {
+        self.do_req(
+            Method::GET,
+            "groups",
+            &page_query(cursor, limit),
+            None,
+            HeaderMap::new(),
+        )
+        .await
+    }
+
+    pub async fn create_group(
+        &self,
+        new_group: NewGroup,
+    ) -> Result<Tagged<Group>, ClientError<GroupError>> {
+        let payload = serde_json::to_vec(&new_group).unwrap();
+        self.do_tagged_req(Method::POST, "groups", Some(payload), HeaderMap::new())
+            .await?
+            .ok_or_else(ClientError::unknown)
+    }
+
+    /// Get a group together with its entity tag. Like
+    /// [`Client::get_user_if_none_match`], this returns `None` if `etag` is
+    /// given and still is the entity tag of the group.
+    pub async fn get_group(
+        &self,
+        name: impl AsRef<str>,
+        etag: Option<&str>,
+    ) -> Result<Option<Tagged<Group>>, ClientError<GroupError>> {
+        let headers = precondition(IF_NONE_MATCH, etag)?;
+        self.do_tagged_req(
+            Method::GET,
+            format!("groups/{name}", name = name.as_ref()),
+            None,
+            headers,
+        )
+        .await
+    }
+
+    /// Change the description or roles of a group. If `etag` is given, the
+    /// group is only changed if that still is its entity tag.
+    pub async fn update_group_if_match(
+        &self,
+        name: impl AsRef<str>,
+        update: GroupUpdate,
+        etag: Option<&str>,
+    ) -> Result<Tagged<Group>, ClientError<GroupError>> {
+        let headers = precondition(IF_MATCH, etag)?;
+        let payload = serde_json::to_vec(&update).unwrap();
+        self.do_tagged_req(
+            Method::PATCH,
+            format!("groups/{name}", name = name.as_ref()),
+            Some(payload),
+            headers,
+        )
+        .await?
+        .ok_or_else(ClientError::unknown)
+    }
+
+    /// Delete a group and all its memberships. If `etag` is given, the group
+    /// is only deleted if that still is its entity tag.
+    pub async fn delete_group_if_match(
+        &self,
+        name: impl AsRef<str>,
+        etag: Option<&str>,
+    ) -> Result<(), ClientError<GroupError>> {
+        let headers = precondition(IF_MATCH, etag)?;
+        self.send(
+            Method::DELETE,
+            format!("groups/{name}", name = name.as_ref()),
+            &[],
+            None,
+            headers,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// One page of the members of a group, ordered by id.
+    pub async fn list_group_members(
+        &self,
+        name: impl AsRef<str>,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Page<GroupMember>, ClientError<GroupError>> {
+        self.do_req(
+            Method::GET,
+            format!("groups/{name}/members", name = name.as_ref()),
+            &page_query(cursor, limit),
+            None,
+            HeaderMap::new(),
+        )
+        .await
+    }
+
+    /// Add a user to a group. Adding a member again changes nothing.
+    pub async fn add_group_member(
+        &self,
+        name: impl AsRef<str>,
+        username: impl AsRef<str>,
+    ) -> Result<(), ClientError<GroupError>> {
+        self.send(
+            Method::PUT,
+            format!(
+                "groups/{name}/members/{username}",
+                name = name.as_ref(),
+                username = username.as_ref()
+            ),
+            &[],
+            None,
+            HeaderMap::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Remove a user from a group. Removing a user that is not a member
+    /// changes nothing.
+    pub async fn remove_group_member(
+        &self,
+        name: impl AsRef<str>,
+        username: impl AsRef<str>,
+    ) -> Result<(), ClientError<GroupError>> {
+        self.send(
+            Method::DELETE,
+            format!(
+                "groups/{name}/members/{username}",
+                name = name.as_ref(),
+                username = username.as_ref()
+            ),
+            &[],
+            None,
+            HeaderMap::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// One page of the groups of a user, ordered by name.
+    pub async fn list_user_groups(
+        &self,
+        username: impl AsRef<str>,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Page<Group>, ClientError<GroupError>> {
+        self.do_req(
+            Method::GET,
+            format!("users/{username}/groups", username = username.as_ref()),
+            &page_query(cursor, limit),
+            None,
+            HeaderMap::new(),
+        )
+        .await
+    }
+
+    /// One page of the events of the audit log that match `filter`, in the
+    /// order they were recorded.
+    pub async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Page<AuditEvent>, ClientError<AuditError>> {
+        let mut query = audit_query(filter);
+        query.extend(page_query(cursor, limit));
+        self.do_req(Method::GET, "audit", &query, None, HeaderMap::new())
             .await
     }
+
+    /// All events of the audit log that match `filter`, in the order they
+    /// were recorded. Fails with [`ClientError::DeserializationError`] if the
+    /// export was cut off.
+    pub async fn export_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, ClientError<AuditError>> {
+        let response = self
+            .send(
+                Method::GET,
+                "audit/export",
+                &audit_query(filter),
+                None,
+                HeaderMap::new(),
+            )
+            .await?;
+        let body = response.bytes().await.map_err(map_to_client_err)?;
+
+        body.split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).map_err(|_| ClientError::DeserializationError))
+            .collect()
+    }
+
+    /// The signed checkpoints of the audit log, oldest first.
+    pub async fn list_audit_checkpoints(
+        &self,
+    ) -> Result<Vec<AuditCheckpoint>, ClientError<AuditError>> {
+        self.do_req(
+            Method::GET,
+            "audit/checkpoints",
+            &[],
+            None,
+            HeaderMap::new(),
+        )
+        .await
+    }
+}
+
+/// The query parameters of a request for a page of a list.
+fn page_query(cursor: Option<&str>, limit: Option<usize>) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if let Some(cursor) = cursor {
+        query.push(("cursor", cursor.to_string()));
+    }
+    if let Some(limit) = limit {
+        query.push(("limit", limit.to_string()));
+    }
+    query
+}
+
+/// The query parameters of a request for events of the audit log.
+fn audit_query(filter: &AuditFilter) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if let Some(actor) = &filter.actor {
+        query.push(("actor", actor.clone()));
+    }
+    if let Some(action) = filter.action {
+        query.push(("action", action.to_string()));
+    }
+    if let Some(target) = &filter.target {
+        query.push(("target", target.clone()));
+    }
+    if let Some(since) = filter.since {
+        query.push(("since", humantime::format_rfc3339(since).to_string()));
+    }
+    if let Some(until) = filter.until {
+        query.push(("until", humantime::format_rfc3339(until).to_string()));
+    }
+    query
+}
+
+/// A resource together with the entity tag the server returned for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tagged<T> {
+    pub value: T,
+    pub etag: Option<String>,
+}
+
+fn precondition<E>(
+    name: http::HeaderName,
+    etag: Option<&str>,
+) -> Result<HeaderMap, ClientError<E>> {
+    let mut headers = HeaderMap::new();
+    if let Some(etag) = etag {
+        let value = HeaderValue::from_str(etag).map_err(|_| ClientError::unknown())?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
 }
 
 fn map_to_client_err<E>(err: reqwest::Error) -> ClientError<E> {
@@ -97,7 +663,7 @@ fn map_to_client_err<E>(err: reqwest::Error) -> ClientError<E> {
     } else if err.is_decode() {
         ClientError::DeserializationError
     } else {
-        ClientError::UnknownError
+        ClientError::unknown()
     }
 }
 
@@ -106,7 +672,85 @@ fn map_to_client_err<E>(err: reqwest::Error) -> ClientError<E> {
 pub enum ClientError<E> {
     ConnectionError,
     TimeoutError,
-    UnknownError,
+    /// Anything else went wrong. If the service responded, with a status it
+    /// does not describe or a body the client does not understand, its
+    /// status and the request id are kept.
+    UnknownError {
+        status: Option<u16>,
+        request_id: Option<String>,
+    },
     DeserializationError,
-    ServiceError(E),
+    Unauthenticated {
+        request_id: Option<String>,
+    },
+    Unauthorized {
+        request_id: Option<String>,
+    },
+    /// The principal of the client is a user that is not active, like a
+    /// suspended or locked user.
+    InactiveAccount {
+        status: UserStatus,
+        request_id: Option<String>,
+    },
+    /// The client made too many requests, and may retry after the given
+    /// number of seconds.
+    RateLimited {
+        retry_after: Option<u64>,
+        request_id: Option<String>,
+    },
+    /// The service is overloaded or the request timed out. If a delay is
+    /// given, the request may be retried after that many seconds.
+    Unavailable {
+        retry_after: Option<u64>,
+        request_id: Option<String>,
+    },
+    /// The service could not parse the request body.
+    InvalidRequest {
+        error: JsonBodyError,
+        request_id: Option<String>,
+    },
+    /// The idempotency key of the request is invalid, or is in use for
+    /// another request.
+    IdempotencyConflict {
+        error: IdempotencyError,
+        request_id: Option<String>,
+    },
+    /// The request does not name a valid tenant, or one the API key does not
+    /// belong to.
+    InvalidTenant {
+        error: AuthError,
+        request_id: Option<String>,
+    },
+    ServiceError {
+        error: E,
+        request_id: Option<String>,
+    },
+}
+
+impl<E> ClientError<E> {
+    /// An unknown error without a response of the service.
+    fn unknown() -> Self {
+        ClientError::UnknownError {
+            status: None,
+            request_id: None,
+        }
+    }
+
+    /// The id the server assigned to the failed request, if a response was
+    /// received. Include this when reporting a problem with the service.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            ClientError::UnknownError { request_id, .. }
+            | ClientError::Unauthenticated { request_id }
+            | ClientError::Unauthorized { request_id }
+            | ClientError::InactiveAccount { request_id, .. }
+            | ClientError::RateLimited { request_id, .. }
+            | ClientError::Unavailable { request_id, .. }
+            | ClientError::InvalidRequest { request_id, .. }
+            | ClientError::IdempotencyConflict { request_id, .. }
+            | ClientError::InvalidTenant { request_id, .. }
+            | ClientError::ServiceError { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,178 @@
+use crate::audit;
+use crate::commands::client::{new_client, report_audit_error, report_error};
+use crate::models::{AuditCheckpoint, AuditEvent, AuditFilter};
+use crate::tenant::DEFAULT_TENANT;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+
+#[derive(Clone, Parser)]
+pub struct Args {
+    #[clap(
+        short,
+        long,
+        env,
+        default_value = "http://127.0.0.1:3000",
+        global = true
+    )]
+    endpoint: url::Url,
+
+    /// API key used to authenticate with the server, with the auditor role
+    #[clap(long, env, global = true)]
+    api_key: Option<String>,
+
+    /// Tenant whose audit log is verified, sent in the X-Tenant-Id header
+    #[clap(long, env, global = true)]
+    tenant: Option<String>,
+
+    #[command(subcommand)]
+    command: SubCommand,
+}
+
+#[derive(Clone, Subcommand)]
+pub enum SubCommand {
+    /// Check that no event of the audit log was altered, removed or inserted,
+    /// reporting the first broken link, and that the log matches its signed
+    /// checkpoints
+    Verify(VerifyArgs),
+}
+
+pub async fn handle_command(args: Args) -> Result<()> {
+    match args.command {
+        SubCommand::Verify(ref verify) => handle_verify(&args, verify).await,
+    }
+}
+
+#[derive(Clone, Parser)]
+pub struct VerifyArgs {
+    /// Base64 encoded public key of the checkpoints, as logged by the server
+    /// on startup. Without it, the signatures of the checkpoints are not
+    /// verified
+    #[clap(long, env = "AUDIT_PUBLIC_KEY")]
+    pub public_key: Option<String>,
+}
+
+async fn handle_verify(args: &Args, verify: &VerifyArgs) -> Result<()> {
+    let client = new_client(
+        args.endpoint.clone(),
+        args.api_key.clone(),
+        args.tenant.clone(),
+    );
+    let events = match client.export_audit_events(&AuditFilter::default()).await {
+        Ok(events) => events,
+        Err(err) => {
+            report_error(err, report_audit_error);
+            bail!("unable to export the audit log");
+        }
+    };
+    let checkpoints = match client.list_audit_checkpoints().await {
+        Ok(checkpoints) => checkpoints,
+        Err(err) => {
+            report_error(err, report_audit_error);
+            bail!("unable to read the audit checkpoints");
+        }
+    };
+
+    audit::verify_chain(&events).context("the audit log is broken")?;
+    let tenant = args.tenant.as_deref().unwrap_or(DEFAULT_TENANT);
+    let checked = verify_checkpoints(&events, &checkpoints, tenant, verify.public_key.as_deref())?;
+
+    println!(
+        "The audit log is intact: {} events, {} checkpoints",
+        events.len(),
+        checkpoints.len()
+    );
+    if checked < events.len() {
+        println!(
+            "{} events were recorded after the last checkpoint",
+            events.len() - checked
+        );
+    }
+    if verify.public_key.is_none() && !checkpoints.is_empty() {
+        println!(
+            "The signatures of the checkpoints were not verified, pass --public-key to verify them"
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that every checkpoint belongs to `tenant` and points into the log,
+/// so the log was not cut short or rewritten since it was signed, and that
+/// it has a valid signature if a public key is given. Returns how many events
+/// are covered by the checkpoints.
+fn verify_checkpoints(
+    events: &[AuditEvent],
+    checkpoints: &[AuditCheckpoint],
+    tenant: &str,
+    public_key: Option<&str>,
+) -> Result<usize> {
+    let mut checked = 0;
+    for checkpoint in checkpoints {
+        let at = humantime::format_rfc3339(checkpoint.at);
+        if checkpoint.tenant != tenant {
+            bail!(
+                "the checkpoint of {at} belongs to tenant {}, not {tenant}",
+                checkpoint.tenant
+            );
+        }
+        let Some(position) = events
+            .iter()
+            .position(|event| event.id == checkpoint.event_id)
+        else {
+            bail!(
+                "the checkpoint of {at} signed event {}, which is missing from the audit log",
+                checkpoint.event_id
+            );
+        };
+        if events[position].hash != checkpoint.hash {
+            bail!(
+                "event {} at position {} does not match the checkpoint of {at}",
+                checkpoint.event_id,
+                position + 1
+            );
+        }
+        if let Some(public_key) = public_key {
+            if !audit::verify_checkpoint(checkpoint, public_key) {
+                bail!("the checkpoint of {at} does not have a valid signature");
+            }
+        }
+        checked = position + 1;
+    }
+
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::AuditAction;
+    use crate::tenant::TenantId;
+    use ring::signature::Ed25519KeyPair;
+    use std::time::SystemTime;
+
+    #[test]
+    fn checkpoints_of_other_tenants_are_rejected() {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let public_key = audit::public_key(&key);
+        let mut event = audit::event(AuditAction::UserCreated, "ci".to_string());
+        audit::chain(&mut event, None);
+        let events = vec![event];
+        let sign = |tenant| {
+            let tenant = TenantId::parse(tenant).unwrap();
+            audit::sign_checkpoint(&key, &tenant, &events[0], SystemTime::now())
+        };
+
+        let acme = vec![sign("acme")];
+        assert_eq!(
+            1,
+            verify_checkpoints(&events, &acme, "acme", Some(&public_key)).unwrap()
+        );
+        // The signature is valid, but for the log of another tenant.
+        let globex = vec![sign("globex")];
+        let err = verify_checkpoints(&events, &globex, "acme", Some(&public_key)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("belongs to tenant globex, not acme"));
+        assert!(verify_checkpoints(&events, &globex, "acme", None).is_err());
+    }
+}
@@ -1,10 +1,18 @@
-use crate::client::{Client, ClientError};
-use crate::models::{CreateUserError, GetUserError, NewUser};
+use crate::client::{Client, ClientError, Tagged};
+use crate::models::{
+    Attributes, AuditAction, AuditError, AuditFilter, ChangeStatusError, CreateUserError,
+    GetUserError, GroupError, GroupUpdate, InvalidNewUserReason, ListUsersError, NewGroup, NewUser,
+    Page, RenameUserError, Role, StatusChange, UpdateUserError, User, UserFilter, UserStatus,
+    UserUpdate,
+};
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use serde_json::Value;
+use std::time::SystemTime;
 use tracing::error;
+use uuid::Uuid;
 
-#[derive(Parser)]
+#[derive(Clone, Parser)]
 pub struct Args {
     #[clap(
         short,
@@ -15,111 +23,1028 @@ pub struct Args {
     )]
     endpoint: url::Url,
 
+    /// API key used to authenticate with the server
+    #[clap(long, env, global = true)]
+    api_key: Option<String>,
+
+    /// Tenant to make requests for, sent in the X-Tenant-Id header
+    #[clap(long, env, global = true)]
+    tenant: Option<String>,
+
     #[command(subcommand)]
     command: SubCommand,
 }
 
-#[derive(Subcommand)]
+#[derive(Clone, Subcommand)]
 pub enum SubCommand {
     Get(GetArgs),
+    GetById(GetByIdArgs),
+    /// List the users, oldest first
+    List(ListUsersArgs),
     Create(CreateArgs),
+    Update(UpdateArgs),
+    Rename(RenameArgs),
+    /// Suspend an active or locked user
+    Suspend(StatusArgs),
+    /// Reactivate a suspended or locked user
+    Reactivate(StatusArgs),
+    /// Lock an active user
+    Lock(StatusArgs),
+    /// Print the status transitions of a user
+    StatusHistory(UsernameArgs),
+    /// Report a failed login of a user
+    FailedLogin(UsernameArgs),
+    /// List the groups of a user
+    Groups(UserGroupsArgs),
+    /// Manage groups and their members
+    #[command(subcommand)]
+    Group(GroupSubCommand),
+    /// Read the audit log
+    #[command(subcommand)]
+    Audit(AuditSubCommand),
+}
+
+#[derive(Clone, Subcommand)]
+pub enum GroupSubCommand {
+    /// List all groups
+    List(ListGroupsArgs),
+    Get(GetGroupArgs),
+    Create(CreateGroupArgs),
+    Update(UpdateGroupArgs),
+    Delete(DeleteGroupArgs),
+    /// List the members of a group
+    Members(GroupMembersArgs),
+    /// Add a user to a group
+    AddMember(MemberArgs),
+    /// Remove a user from a group
+    RemoveMember(MemberArgs),
+}
+
+#[derive(Clone, Subcommand)]
+pub enum AuditSubCommand {
+    /// List the events of the audit log, oldest first
+    List(ListAuditArgs),
+    /// Print all events of the audit log as newline-delimited JSON
+    Export(ExportAuditArgs),
 }
 
 pub async fn handle_command(args: Args) -> Result<()> {
     match args.command {
         SubCommand::Get(args) => handle_get(args).await,
+        SubCommand::GetById(args) => handle_get_by_id(args).await,
+        SubCommand::List(args) => handle_list_users(args).await,
         SubCommand::Create(args) => handle_create(args).await,
+        SubCommand::Update(args) => handle_update(args).await,
+        SubCommand::Rename(args) => handle_rename(args).await,
+        SubCommand::Suspend(args) => handle_status(args, UserStatus::Suspended).await,
+        SubCommand::Reactivate(args) => handle_status(args, UserStatus::Active).await,
+        SubCommand::Lock(args) => handle_status(args, UserStatus::Locked).await,
+        SubCommand::StatusHistory(args) => handle_status_history(args).await,
+        SubCommand::FailedLogin(args) => handle_failed_login(args).await,
+        SubCommand::Groups(args) => handle_user_groups(args).await,
+        SubCommand::Group(command) => match command {
+            GroupSubCommand::List(args) => handle_list_groups(args).await,
+            GroupSubCommand::Get(args) => handle_get_group(args).await,
+            GroupSubCommand::Create(args) => handle_create_group(args).await,
+            GroupSubCommand::Update(args) => handle_update_group(args).await,
+            GroupSubCommand::Delete(args) => handle_delete_group(args).await,
+            GroupSubCommand::Members(args) => handle_group_members(args).await,
+            GroupSubCommand::AddMember(args) => handle_add_member(args).await,
+            GroupSubCommand::RemoveMember(args) => handle_remove_member(args).await,
+        },
+        SubCommand::Audit(command) => match command {
+            AuditSubCommand::List(args) => handle_list_audit(args).await,
+            AuditSubCommand::Export(args) => handle_export_audit(args).await,
+        },
     }
 }
 
-#[derive(Parser)]
+pub(crate) fn new_client(
+    endpoint: url::Url,
+    api_key: Option<String>,
+    tenant: Option<String>,
+) -> Client {
+    let mut client = Client::new(endpoint);
+    if let Some(api_key) = api_key {
+        client = client.with_api_key(api_key);
+    }
+    if let Some(tenant) = tenant {
+        client = client.with_tenant(tenant);
+    }
+    client
+}
+
+#[derive(Clone, Parser)]
 pub struct GetArgs {
     pub username: String,
 
+    /// Only print the user if its entity tag differs from this one
+    #[clap(long)]
+    pub if_none_match: Option<String>,
+
     #[clap(from_global)]
     pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
 }
 
 async fn handle_get(args: GetArgs) -> Result<()> {
-    let client = Client::new(args.endpoint);
-    match client.get_user(&args.username).await {
-        Ok(user) => println!("{:#?}", user),
-        Err(err) => match err {
-            ClientError::ConnectionError => error!("Connection error"),
-            ClientError::TimeoutError => error!("Timeout occurred"),
-            ClientError::UnknownError => error!("Unknown error"),
-            ClientError::DeserializationError => {
-                error!("Unable to deserialize response")
-            }
-            ClientError::Unauthenticated => error!("Unauthenticated"),
-            ClientError::Unauthorized => error!("Unauthorized"),
-            ClientError::ServiceError(err) => match err {
-                GetUserError::UserNotFound { username } => {
-                    error!(%username, "User not found");
-                }
-            },
-        },
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let result = match &args.if_none_match {
+        Some(etag) => {
+            client
+                .get_user_if_none_match(&args.username, Some(etag))
+                .await
+        }
+        None => client.get_user(&args.username).await.map(|user| {
+            Some(Tagged {
+                value: user,
+                etag: None,
+            })
+        }),
     };
+    report_get(result);
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct GetByIdArgs {
+    pub id: Uuid,
+
+    /// Only print the user if its entity tag differs from this one
+    #[clap(long)]
+    pub if_none_match: Option<String>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_get_by_id(args: GetByIdArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let result = client
+        .get_user_by_id(args.id, args.if_none_match.as_deref())
+        .await;
+    report_get(result);
 
     Ok(())
 }
 
-#[derive(Parser)]
+fn report_get(result: Result<Option<Tagged<User>>, ClientError<GetUserError>>) {
+    match result {
+        Ok(Some(user)) => print_tagged(user),
+        Ok(None) => println!("Not modified"),
+        Err(err) => report_error(err, report_get_user_error),
+    }
+}
+
+fn report_get_user_error(error: GetUserError, request_id: Option<String>) {
+    match error {
+        GetUserError::UserNotFound { username } => {
+            error!(%username, ?request_id, "User not found");
+        }
+        GetUserError::UserIdNotFound { id } => {
+            error!(%id, ?request_id, "User not found");
+        }
+    }
+}
+
+#[derive(Clone, Parser)]
+pub struct ListUsersArgs {
+    /// Only list users with this custom attribute, as `key=value`. Values
+    /// match string attributes as they are, and other attributes in their
+    /// JSON form, like `42`
+    #[clap(long = "attribute", value_parser = parse_attribute_filter)]
+    pub attributes: Vec<(String, String)>,
+
+    #[clap(flatten)]
+    pub page: PageArgs,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_list_users(args: ListUsersArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let filter = UserFilter {
+        attributes: args.attributes.into_iter().collect(),
+    };
+    match client
+        .list_users(&filter, args.page.cursor.as_deref(), args.page.limit)
+        .await
+    {
+        Ok(page) => {
+            println!("{:#?}", page.items);
+            if let Some(cursor) = page.next_cursor {
+                println!("Next cursor: {cursor}");
+            }
+        }
+        Err(err) => report_error(err, |error, request_id| match error {
+            ListUsersError::InvalidPage { reason } => {
+                error!(%reason, ?request_id, "Invalid page");
+            }
+            ListUsersError::InvalidFilter { reason } => {
+                error!(%reason, ?request_id, "Invalid filter");
+            }
+        }),
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
 pub struct CreateArgs {
     pub username: String,
     pub name: String,
 
+    #[clap(long)]
+    pub email: Option<String>,
+
+    /// Custom attribute of the user, as `key=value`. Values that are valid
+    /// JSON, like `42` or `["a"]`, are used as such, others as strings
+    #[clap(long = "attribute", value_parser = parse_attribute)]
+    pub attributes: Vec<(String, Value)>,
+
+    /// Key that makes it safe to retry the command: the user is only created
+    /// once for all requests with the same key
+    #[clap(long)]
+    pub idempotency_key: Option<String>,
+
     #[clap(from_global)]
     pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
 }
 
 async fn handle_create(args: CreateArgs) -> Result<()> {
     let user = NewUser {
         username: args.username,
         name: args.name,
+        email: args.email,
+        attributes: Attributes::from_iter(args.attributes),
     };
-    let client = Client::new(args.endpoint);
-    match client.create_user(user).await {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .create_user(user, args.idempotency_key.as_deref())
+        .await
+    {
         Ok(user) => println!("{:#?}", user),
-        Err(err) => match err {
-            ClientError::ConnectionError => error!("Connection error"),
-            ClientError::TimeoutError => error!("Timeout occurred"),
-            ClientError::UnknownError => error!("Unknown error"),
-            ClientError::DeserializationError => {
-                error!("Unable to deserialize response")
-            }
-            ClientError::Unauthenticated => error!("Unauthenticated"),
-            ClientError::Unauthorized => error!("Unauthorized"),
-            ClientError::ServiceError(err) => match err {
-                CreateUserError::UsernameAlreadyExists => {
-                    error!("Username already exists")
-                }
-                CreateUserError::InvalidUsername(reason) => {
-                    error!("Invalid username: {:?}", reason)
-                }
-                CreateUserError::InvalidName(reason) => error!("Invalid name: {:?}", reason),
-            },
+        Err(err) => report_error(err, |error, request_id| match error {
+            CreateUserError::UsernameAlreadyExists => {
+                error!(?request_id, "Username already exists")
+            }
+            CreateUserError::InvalidNewUser { violations } => {
+                error!(?request_id, "Invalid user");
+                report_violations(violations);
+            }
+        }),
+    };
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct UpdateArgs {
+    pub username: String,
+
+    #[clap(long)]
+    pub name: Option<String>,
+
+    #[clap(long)]
+    pub email: Option<String>,
+
+    /// Remove the email address of the user
+    #[clap(long, conflicts_with = "email")]
+    pub remove_email: bool,
+
+    /// Custom attribute of the user, as `key=value`. The attributes that are
+    /// given replace all attributes of the user
+    #[clap(long = "attribute", value_parser = parse_attribute)]
+    pub attributes: Vec<(String, Value)>,
+
+    /// Remove all custom attributes of the user
+    #[clap(long, conflicts_with = "attributes")]
+    pub remove_attributes: bool,
+
+    /// Only update the user if its entity tag is still this one
+    #[clap(long)]
+    pub if_match: Option<String>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_update(args: UpdateArgs) -> Result<()> {
+    let update = UserUpdate {
+        name: args.name,
+        email: if args.remove_email {
+            Some(None)
+        } else {
+            args.email.map(Some)
+        },
+        attributes: if args.remove_attributes {
+            Some(Attributes::new())
+        } else if args.attributes.is_empty() {
+            None
+        } else {
+            Some(Attributes::from_iter(args.attributes))
+        },
+    };
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .update_user_if_match(&args.username, update, args.if_match.as_deref())
+        .await
+    {
+        Ok(user) => print_tagged(user),
+        Err(err) => report_error(err, |error, request_id| match error {
+            UpdateUserError::UserNotFound { username } => {
+                error!(%username, ?request_id, "User not found");
+            }
+            UpdateUserError::PreconditionFailed { etag } => {
+                error!(%etag, ?request_id, "User was modified in the meantime");
+            }
+            UpdateUserError::InvalidUserUpdate { violations } => {
+                error!(?request_id, "Invalid update");
+                report_violations(violations);
+            }
+        }),
+    };
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct RenameArgs {
+    pub username: String,
+    pub new_username: String,
+
+    /// Only rename the user if its entity tag is still this one
+    #[clap(long)]
+    pub if_match: Option<String>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_rename(args: RenameArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .rename_user_if_match(&args.username, args.new_username, args.if_match.as_deref())
+        .await
+    {
+        Ok(user) => print_tagged(user),
+        Err(err) => report_error(err, |error, request_id| match error {
+            RenameUserError::UserNotFound { username } => {
+                error!(%username, ?request_id, "User not found");
+            }
+            RenameUserError::PreconditionFailed { etag } => {
+                error!(%etag, ?request_id, "User was modified in the meantime");
+            }
+            RenameUserError::UsernameAlreadyExists => {
+                error!(?request_id, "Username already exists")
+            }
+            RenameUserError::InvalidUsername { violations } => {
+                error!(?request_id, "Invalid username");
+                report_violations(violations);
+            }
+        }),
+    };
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct StatusArgs {
+    pub username: String,
+
+    /// Why the status is changed, recorded with the change
+    #[clap(long)]
+    pub reason: String,
+
+    /// Only change the status if the entity tag of the user is still this one
+    #[clap(long)]
+    pub if_match: Option<String>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_status(args: StatusArgs, status: UserStatus) -> Result<()> {
+    let change = StatusChange {
+        status,
+        reason: args.reason,
+    };
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .change_user_status_if_match(&args.username, change, args.if_match.as_deref())
+        .await
+    {
+        Ok(user) => print_tagged(user),
+        Err(err) => report_error(err, |error, request_id| match error {
+            ChangeStatusError::UserNotFound { username } => {
+                error!(%username, ?request_id, "User not found");
+            }
+            ChangeStatusError::PreconditionFailed { etag } => {
+                error!(%etag, ?request_id, "User was modified in the meantime");
+            }
+            ChangeStatusError::InvalidTransition { from, to } => {
+                error!(%from, %to, ?request_id, "User cannot change to this status");
+            }
+            ChangeStatusError::InvalidStatusChange { violations } => {
+                error!(?request_id, "Invalid status change");
+                report_violations(violations);
+            }
+        }),
+    };
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct UsernameArgs {
+    pub username: String,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_status_history(args: UsernameArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client.get_status_transitions(&args.username).await {
+        Ok(transitions) => println!("{:#?}", transitions),
+        Err(err) => report_error(err, report_get_user_error),
+    };
+
+    Ok(())
+}
+
+async fn handle_failed_login(args: UsernameArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client.record_failed_login(&args.username).await {
+        Ok(user) => print_tagged(user),
+        Err(err) => report_error(err, report_get_user_error),
+    };
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct PageArgs {
+    /// Continue with the page after the one that returned this cursor
+    #[clap(long)]
+    pub cursor: Option<String>,
+
+    /// Maximum number of items to print
+    #[clap(long)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Parser)]
+pub struct UserGroupsArgs {
+    pub username: String,
+
+    #[clap(flatten)]
+    pub page: PageArgs,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_user_groups(args: UserGroupsArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let result = client
+        .list_user_groups(&args.username, args.page.cursor.as_deref(), args.page.limit)
+        .await;
+    report_page(result);
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct ListGroupsArgs {
+    #[clap(flatten)]
+    pub page: PageArgs,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_list_groups(args: ListGroupsArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let result = client
+        .list_groups(args.page.cursor.as_deref(), args.page.limit)
+        .await;
+    report_page(result);
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct GetGroupArgs {
+    pub name: String,
+
+    /// Only print the group if its entity tag differs from this one
+    #[clap(long)]
+    pub if_none_match: Option<String>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_get_group(args: GetGroupArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .get_group(&args.name, args.if_none_match.as_deref())
+        .await
+    {
+        Ok(Some(group)) => print_tagged(group),
+        Ok(None) => println!("Not modified"),
+        Err(err) => report_error(err, report_group_error),
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct CreateGroupArgs {
+    pub name: String,
+
+    #[clap(long)]
+    pub description: Option<String>,
+
+    /// Role granted to the members of the group, like `user_admin`
+    #[clap(long = "role", value_parser = parse_role)]
+    pub roles: Vec<Role>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_create_group(args: CreateGroupArgs) -> Result<()> {
+    let group = NewGroup {
+        name: args.name,
+        description: args.description,
+        roles: args.roles,
+    };
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client.create_group(group).await {
+        Ok(group) => print_tagged(group),
+        Err(err) => report_error(err, report_group_error),
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct UpdateGroupArgs {
+    pub name: String,
+
+    #[clap(long)]
+    pub description: Option<String>,
+
+    /// Remove the description of the group
+    #[clap(long, conflicts_with = "description")]
+    pub remove_description: bool,
+
+    /// Role granted to the members of the group. The roles that are given
+    /// replace all roles of the group
+    #[clap(long = "role", value_parser = parse_role)]
+    pub roles: Vec<Role>,
+
+    /// Remove all roles of the group
+    #[clap(long, conflicts_with = "roles")]
+    pub remove_roles: bool,
+
+    /// Only update the group if its entity tag is still this one
+    #[clap(long)]
+    pub if_match: Option<String>,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_update_group(args: UpdateGroupArgs) -> Result<()> {
+    let update = GroupUpdate {
+        description: if args.remove_description {
+            Some(None)
+        } else {
+            args.description.map(Some)
+        },
+        roles: if args.remove_roles {
+            Some(Vec::new())
+        } else if args.roles.is_empty() {
+            None
+        } else {
+            Some(args.roles)
         },
     };
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .update_group_if_match(&args.name, update, args.if_match.as_deref())
+        .await
+    {
+        Ok(group) => print_tagged(group),
+        Err(err) => report_error(err, report_group_error),
+    }
 
     Ok(())
 }
 
-pub enum SdkError<E> {
-    /// The request failed during construction. It was not dispatched over the network.
-    ConstructionFailure(ConstructionFailure),
+#[derive(Clone, Parser)]
+pub struct DeleteGroupArgs {
+    pub name: String,
 
-    /// The request failed due to a timeout. The request MAY have been sent and received.
-    TimeoutError(TimeoutError),
+    /// Only delete the group if its entity tag is still this one
+    #[clap(long)]
+    pub if_match: Option<String>,
 
-    /// The request failed during dispatch. An HTTP response was not received. The request MAY
-    /// have been sent.
-    DispatchFailure(DispatchFailure),
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_delete_group(args: DeleteGroupArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .delete_group_if_match(&args.name, args.if_match.as_deref())
+        .await
+    {
+        Ok(()) => println!("Deleted group {}", args.name),
+        Err(err) => report_error(err, report_group_error),
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct GroupMembersArgs {
+    pub name: String,
+
+    #[clap(flatten)]
+    pub page: PageArgs,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
 
-    /// A response was received but it was not parseable according the the protocol (for example
-    /// the server hung up without sending a complete response)
-    ResponseError(ResponseError),
+async fn handle_group_members(args: GroupMembersArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let result = client
+        .list_group_members(&args.name, args.page.cursor.as_deref(), args.page.limit)
+        .await;
+    report_page(result);
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct MemberArgs {
+    pub group: String,
+    pub username: String,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_add_member(args: MemberArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client.add_group_member(&args.group, &args.username).await {
+        Ok(()) => println!("{} is a member of {}", args.username, args.group),
+        Err(err) => report_error(err, report_group_error),
+    }
+
+    Ok(())
+}
+
+async fn handle_remove_member(args: MemberArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client
+        .remove_group_member(&args.group, &args.username)
+        .await
+    {
+        Ok(()) => println!("{} is not a member of {}", args.username, args.group),
+        Err(err) => report_error(err, report_group_error),
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct AuditFilterArgs {
+    /// Only events of requests made by this principal
+    #[clap(long)]
+    pub actor: Option<String>,
+
+    /// Only events of this action, like `user.updated`
+    #[clap(long, value_parser = parse_action)]
+    pub action: Option<AuditAction>,
+
+    /// Only events about the user or group with this name
+    #[clap(long)]
+    pub target: Option<String>,
+
+    /// Only events at or after this time, like `2024-01-31T12:00:00Z`
+    #[clap(long, value_parser = humantime::parse_rfc3339_weak)]
+    pub since: Option<SystemTime>,
+
+    /// Only events before this time
+    #[clap(long, value_parser = humantime::parse_rfc3339_weak)]
+    pub until: Option<SystemTime>,
+}
+
+impl From<AuditFilterArgs> for AuditFilter {
+    fn from(args: AuditFilterArgs) -> Self {
+        Self {
+            actor: args.actor,
+            action: args.action,
+            target: args.target,
+            since: args.since,
+            until: args.until,
+        }
+    }
+}
+
+#[derive(Clone, Parser)]
+pub struct ListAuditArgs {
+    #[clap(flatten)]
+    pub filter: AuditFilterArgs,
+
+    #[clap(flatten)]
+    pub page: PageArgs,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_list_audit(args: ListAuditArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    let filter = args.filter.into();
+    match client
+        .list_audit_events(&filter, args.page.cursor.as_deref(), args.page.limit)
+        .await
+    {
+        Ok(page) => {
+            println!("{:#?}", page.items);
+            if let Some(cursor) = page.next_cursor {
+                println!("Next cursor: {cursor}");
+            }
+        }
+        Err(err) => report_error(err, report_audit_error),
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Parser)]
+pub struct ExportAuditArgs {
+    #[clap(flatten)]
+    pub filter: AuditFilterArgs,
+
+    #[clap(from_global)]
+    pub endpoint: url::Url,
+
+    #[clap(from_global)]
+    pub api_key: Option<String>,
+
+    #[clap(from_global)]
+    pub tenant: Option<String>,
+}
+
+async fn handle_export_audit(args: ExportAuditArgs) -> Result<()> {
+    let client = new_client(args.endpoint, args.api_key, args.tenant);
+    match client.export_audit_events(&args.filter.into()).await {
+        Ok(events) => {
+            for event in events {
+                println!("{}", serde_json::to_string(&event)?);
+            }
+        }
+        Err(err) => report_error(err, report_audit_error),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn report_audit_error(error: AuditError, request_id: Option<String>) {
+    match error {
+        AuditError::InvalidPage { reason } => {
+            error!(%reason, ?request_id, "Invalid page");
+        }
+    }
+}
+
+fn report_page<T: std::fmt::Debug>(result: Result<Page<T>, ClientError<GroupError>>) {
+    match result {
+        Ok(page) => {
+            println!("{:#?}", page.items);
+            if let Some(cursor) = page.next_cursor {
+                println!("Next cursor: {cursor}");
+            }
+        }
+        Err(err) => report_error(err, report_group_error),
+    }
+}
+
+fn report_group_error(error: GroupError, request_id: Option<String>) {
+    match error {
+        GroupError::GroupNotFound { name } => {
+            error!(%name, ?request_id, "Group not found");
+        }
+        GroupError::UserNotFound { username } => {
+            error!(%username, ?request_id, "User not found");
+        }
+        GroupError::GroupAlreadyExists => {
+            error!(?request_id, "Group already exists");
+        }
+        GroupError::PreconditionFailed { etag } => {
+            error!(%etag, ?request_id, "Group was modified in the meantime");
+        }
+        GroupError::InvalidGroup { violations } => {
+            error!(?request_id, "Invalid group");
+            report_violations(violations);
+        }
+        GroupError::InvalidPage { reason } => {
+            error!(%reason, ?request_id, "Invalid page");
+        }
+    }
+}
+
+/// Log a failed request. Errors of the service itself are left to
+/// `report_service_error`, since they differ per request.
+pub(crate) fn report_error<E>(
+    err: ClientError<E>,
+    report_service_error: impl FnOnce(E, Option<String>),
+) {
+    match err {
+        ClientError::ConnectionError => error!("Connection error"),
+        ClientError::TimeoutError => error!("Timeout occurred"),
+        ClientError::UnknownError { status, .. } => {
+            error!(request_id = ?err.request_id(), ?status, "Unknown error")
+        }
+        ClientError::DeserializationError => {
+            error!("Unable to deserialize response")
+        }
+        ClientError::Unauthenticated { .. } => {
+            error!(request_id = ?err.request_id(), "Unauthenticated")
+        }
+        ClientError::Unauthorized { .. } => {
+            error!(request_id = ?err.request_id(), "Unauthorized")
+        }
+        ClientError::InactiveAccount { status, .. } => {
+            error!(request_id = ?err.request_id(), %status, "Account is not active")
+        }
+        ClientError::RateLimited { retry_after, .. } => {
+            error!(request_id = ?err.request_id(), ?retry_after, "Rate limited")
+        }
+        ClientError::Unavailable { retry_after, .. } => {
+            error!(request_id = ?err.request_id(), ?retry_after, "Service unavailable")
+        }
+        ClientError::InvalidRequest { error, request_id } => {
+            error!(?request_id, "Invalid request: {error}")
+        }
+        ClientError::IdempotencyConflict { error, request_id } => {
+            error!(?request_id, "Idempotency conflict: {error}")
+        }
+        ClientError::InvalidTenant { error, request_id } => {
+            error!(?request_id, "Invalid tenant: {error}")
+        }
+        ClientError::ServiceError { error, request_id } => report_service_error(error, request_id),
+    }
+}
+
+fn report_violations(violations: Vec<InvalidNewUserReason>) {
+    for violation in violations {
+        error!(field = %violation.field, reason = ?violation.reason, "Invalid field");
+    }
+}
+
+fn print_tagged<T: std::fmt::Debug>(tagged: Tagged<T>) {
+    println!("{:#?}", tagged.value);
+    if let Some(etag) = tagged.etag {
+        println!("ETag: {etag}");
+    }
+}
+
+fn parse_attribute(attribute: &str) -> Result<(String, Value), String> {
+    let (key, value) = attribute
+        .split_once('=')
+        .ok_or_else(|| format!("expected `key=value`, got `{attribute}`"))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+    Ok((key.to_string(), value))
+}
+
+fn parse_attribute_filter(attribute: &str) -> Result<(String, String), String> {
+    let (key, value) = attribute
+        .split_once('=')
+        .ok_or_else(|| format!("expected `key=value`, got `{attribute}`"))?;
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn parse_role(role: &str) -> Result<Role, String> {
+    serde_json::from_value(Value::String(role.to_string())).map_err(|_| {
+        format!("unknown role `{role}`, expected admin, user_admin, group_admin or auditor")
+    })
+}
 
-    /// An error response was received from the service
-    ServiceError(ServiceError<E>),
+fn parse_action(action: &str) -> Result<AuditAction, String> {
+    serde_json::from_value(Value::String(action.to_string()))
+        .map_err(|_| format!("unknown action `{action}`, like `user.updated`"))
 }
@@ -0,0 +1,45 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Clone, Parser)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: SubCommand,
+}
+
+#[derive(Clone, Subcommand)]
+pub enum SubCommand {
+    /// Validate a configuration file and print the effective configuration,
+    /// including environment variables and flags, with secrets redacted
+    Check(CheckArgs),
+}
+
+#[derive(Clone, Parser)]
+pub struct CheckArgs {
+    /// Configuration file in TOML format
+    pub file: PathBuf,
+
+    #[command(flatten)]
+    pub overrides: crate::commands::start::Overrides,
+}
+
+/// Handle a config command. The configuration has already been loaded and
+/// merged with the overrides, since the telemetry settings are needed before
+/// any command runs.
+pub fn handle_command(args: Args, config: Config) -> Result<()> {
+    match args.command {
+        SubCommand::Check(args) => handle_check(args, config),
+    }
+}
+
+fn handle_check(args: CheckArgs, config: Config) -> Result<()> {
+    config
+        .validate()
+        .with_context(|| format!("{} is not valid", args.file.display()))?;
+
+    print!("{}", config.to_redacted_toml()?);
+
+    Ok(())
+}
@@ -1,2 +1,4 @@
+pub mod audit;
 pub mod client;
+pub mod config;
 pub mod start;
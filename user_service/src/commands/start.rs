@@ -1,56 +1,446 @@
+use crate::audit::Checkpointer;
+use crate::config::{Config, StorageBackend, TlsConfig};
+use crate::db::memory::MemoryStore;
+use crate::db::Store;
+use crate::extract::JsonConfig;
 use crate::handlers;
-use anyhow::{Context, Result};
-use axum::body::HttpBody;
-use axum::response::Response;
-use axum::routing::{get, post};
-use axum::Router;
+use crate::idempotency::IdempotencyCache;
+use crate::middleware::access_log::AccessLogLayer;
+use crate::middleware::auth::{AuthLayer, RequireRoleLayer};
+use crate::middleware::cors::cors_layer;
+use crate::middleware::error_format::ErrorFormatLayer;
+use crate::middleware::idempotency::IdempotencyLayer;
+use crate::middleware::load_shed::LoadShedLayer;
+use crate::middleware::rate_limit::{ClientAddr, RateLimitLayer};
+use crate::middleware::request_id::RequestIdLayer;
+use crate::models::Role;
+use crate::reload::Reloader;
+use crate::state::AppState;
+use crate::tenant::TenantResolver;
+use anyhow::{bail, Context, Result};
+use axum::extract::DefaultBodyLimit;
+use axum::handler::Handler;
+use axum::routing::{get, post, put};
+use axum::{Extension, Router};
 use clap::Parser;
-use http::{Request, StatusCode};
+use futures_util::StreamExt;
+use hyper::server::conn::AddrIncoming;
 use std::error::Error;
 use std::future::Future;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
-use std::task::Poll;
-use tower::{Layer, Service};
-use tower_http::cors::CorsLayer;
-use tracing::debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tls_listener::TlsListener;
+use tokio_rustls::TlsAcceptor;
+use tower::{Layer, Service, ServiceBuilder};
+use tracing::{debug, info, warn};
 
-#[derive(Parser)]
+#[derive(Clone, Parser)]
 pub struct Args {
-    #[clap(short, long, env, default_value = "127.0.0.1:3000")]
-    listen_address: SocketAddr,
+    /// Configuration file in TOML format. Environment variables and flags
+    /// override the settings in this file
+    #[clap(short, long, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Reload the configuration file whenever it changes, checking for
+    /// changes at this interval. The configuration is also reloaded on SIGHUP
+    #[clap(long, env, requires = "config", value_parser = humantime::parse_duration)]
+    pub watch_config: Option<Duration>,
+
+    #[command(flatten)]
+    pub overrides: Overrides,
+}
+
+/// Flags that override the settings from the configuration file.
+#[derive(Clone, clap::Args)]
+pub struct Overrides {
+    /// Address the server listens on [default: 127.0.0.1:3000]
+    #[clap(short, long, env)]
+    listen_address: Option<SocketAddr>,
+
+    /// Paths for which no access log is written, separated by commas
+    /// [default: /healthz,/readyz,/metrics]
+    #[clap(long, env, value_delimiter = ',')]
+    access_log_skip_paths: Option<Vec<String>>,
+
+    /// How long the server keeps accepting requests after a shutdown signal,
+    /// while failing its readiness probe, so load balancers can stop sending
+    /// traffic to it [default: 5s]
+    #[clap(long, env, value_parser = humantime::parse_duration)]
+    drain_period: Option<Duration>,
+
+    /// How long in-flight requests get to finish once the server stops
+    /// accepting requests. Connections that are still open after this are
+    /// dropped [default: 30s]
+    #[clap(long, env, value_parser = humantime::parse_duration)]
+    shutdown_timeout: Option<Duration>,
+
+    /// PEM file containing the TLS certificate chain, enables HTTPS
+    #[clap(long, env, requires = "tls_key_path")]
+    tls_cert_path: Option<PathBuf>,
+
+    /// PEM file containing the TLS private key
+    #[clap(long, env, requires = "tls_cert_path")]
+    tls_key_path: Option<PathBuf>,
+
+    /// Maximum size of a request body, in bytes [default: 2097152]
+    #[clap(long, env)]
+    max_body_size: Option<usize>,
+
+    /// Reject JSON request bodies with unknown fields instead of ignoring them
+    #[clap(long, env)]
+    deny_unknown_fields: bool,
+
+    /// Maximum number of API requests handled at the same time, zero for no
+    /// limit. Requests over the limit are rejected [default: 1024]
+    #[clap(long, env)]
+    max_in_flight: Option<usize>,
+
+    /// How long an API request may take before it is aborted [default: 30s]
+    #[clap(long, env, value_parser = humantime::parse_duration)]
+    request_timeout: Option<Duration>,
+
+    /// Allow cross-origin requests from every origin, with any method and
+    /// header. Only meant for local development
+    #[clap(long, env)]
+    cors_dev_permissive: bool,
+}
+
+impl Overrides {
+    /// Apply the flags and environment variables that were set on top of the
+    /// configuration.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(listen_address) = self.listen_address {
+            config.listener.address = listen_address;
+        }
+        if let Some(access_log_skip_paths) = &self.access_log_skip_paths {
+            config.telemetry.access_log_skip_paths = access_log_skip_paths.clone();
+        }
+        if let Some(drain_period) = self.drain_period {
+            config.listener.drain_period = drain_period;
+        }
+        if let Some(shutdown_timeout) = self.shutdown_timeout {
+            config.listener.shutdown_timeout = shutdown_timeout;
+        }
+        if let (Some(cert_path), Some(key_path)) = (&self.tls_cert_path, &self.tls_key_path) {
+            config.tls = Some(TlsConfig {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            });
+        }
+        if let Some(max_body_size) = self.max_body_size {
+            config.limits.max_body_size = max_body_size;
+        }
+        if self.deny_unknown_fields {
+            config.limits.deny_unknown_fields = true;
+        }
+        if let Some(max_in_flight) = self.max_in_flight {
+            config.limits.max_in_flight = max_in_flight;
+        }
+        if let Some(request_timeout) = self.request_timeout {
+            config.limits.request_timeout = request_timeout;
+        }
+        if self.cors_dev_permissive {
+            config.cors.dev_permissive = true;
+        }
+    }
 }
 
-pub async fn handle_command(args: Args) -> Result<()> {
+pub async fn handle_command(args: Args, reloader: Reloader) -> Result<()> {
+    let config = reloader.config();
+    config.validate().context("invalid configuration")?;
+
+    let reloader = Arc::new(reloader);
+    let settings = reloader.settings();
+    reload_on_sighup(reloader.clone())?;
+    if let (Some(path), Some(interval)) = (args.config, args.watch_config) {
+        tokio::spawn(reloader.watch(path, interval));
+    }
+
+    let store: Arc<dyn Store> = match config.storage.backend {
+        StorageBackend::Memory => Arc::new(MemoryStore::new()),
+    };
+    let state = AppState::new(store, settings.clone());
+
+    let checkpointer = config
+        .audit
+        .checkpoint_key()?
+        .map(|key| Arc::new(Checkpointer::new(state.store.clone(), key)));
+    if let Some(checkpointer) = &checkpointer {
+        let public_key = checkpointer.public_key();
+        let interval = config.audit.checkpoint_interval;
+        info!(%public_key, ?interval, "Signing audit checkpoints");
+        tokio::spawn(checkpointer.clone().run(interval));
+    }
+
+    // Changes and reading the audit log require a role if roles are enforced,
+    // other reads do not.
+    let require = |role| {
+        RequireRoleLayer::new(
+            role,
+            settings.clone(),
+            state.store.clone(),
+            state.denials.clone(),
+        )
+    };
+
+    // Only the API requires authentication and is protected from overload,
+    // the probes and metrics have to be reachable by the orchestrator.
+    let api = Router::new()
+        .route(
+            "/users/:user_name",
+            get(handlers::get_user).patch(handlers::update_user.layer(require(Role::UserAdmin))),
+        )
+        .route(
+            "/users/:user_name/rename",
+            post(handlers::rename_user).layer(require(Role::UserAdmin)),
+        )
+        .route(
+            "/users/:user_name/status",
+            post(handlers::change_user_status).layer(require(Role::UserAdmin)),
+        )
+        .route(
+            "/users/:user_name/status-transitions",
+            get(handlers::get_status_transitions),
+        )
+        .route(
+            "/users/:user_name/failed-logins",
+            post(handlers::record_failed_login).layer(require(Role::UserAdmin)),
+        )
+        .route("/users/:user_name/groups", get(handlers::list_user_groups))
+        .route("/users/by-id/:id", get(handlers::get_user_by_id))
+        .route(
+            "/users",
+            get(handlers::list_users).post(
+                handlers::create_user.layer(
+                    ServiceBuilder::new().layer(require(Role::UserAdmin)).layer(
+                        IdempotencyLayer::new(
+                            Arc::new(IdempotencyCache::new(&config.idempotency)),
+                            config.idempotency.max_key_length,
+                        ),
+                    ),
+                ),
+            ),
+        )
+        .route(
+            "/groups",
+            get(handlers::list_groups)
+                .post(handlers::create_group.layer(require(Role::GroupAdmin))),
+        )
+        .route(
+            "/groups/:group_name",
+            get(handlers::get_group)
+                .patch(handlers::update_group.layer(require(Role::GroupAdmin)))
+                .delete(handlers::delete_group.layer(require(Role::GroupAdmin))),
+        )
+        .route(
+            "/groups/:group_name/members",
+            get(handlers::list_group_members),
+        )
+        .route(
+            "/groups/:group_name/members/:user_name",
+            put(handlers::add_group_member)
+                .delete(handlers::remove_group_member)
+                .layer(require(Role::GroupAdmin)),
+        )
+        .route(
+            "/audit",
+            get(handlers::list_audit_events).layer(require(Role::Auditor)),
+        )
+        .route(
+            "/audit/export",
+            get(handlers::export_audit_events).layer(require(Role::Auditor)),
+        )
+        .route(
+            "/audit/checkpoints",
+            get(handlers::list_audit_checkpoints).layer(require(Role::Auditor)),
+        )
+        .layer(LoadShedLayer::new(&config.limits, state.metrics.clone()))
+        .layer(AuthLayer::new(
+            settings.clone(),
+            state.store.clone(),
+            TenantResolver::new(&config.tenancy),
+            state.denials.clone(),
+        ))
+        .layer(RateLimitLayer::new(settings.clone()));
+
     // build our application with a route
     let app = Router::new()
-        .route("/users/:user_name", get(handlers::get_user))
-        .route("/users", post(handlers::create_user))
-        .layer(CorsLayer::very_permissive());
-    // .layer(TraceLayer::new_for_http())
+        .route("/healthz", get(handlers::healthz))
+        .route("/readyz", get(handlers::readyz))
+        .route("/metrics", get(handlers::metrics))
+        .merge(api)
+        .layer(DefaultBodyLimit::max(config.limits.max_body_size))
+        .layer(Extension(JsonConfig {
+            max_body_size: config.limits.max_body_size,
+            deny_unknown_fields: config.limits.deny_unknown_fields,
+        }))
+        .layer(AccessLogLayer::new().skip_paths(config.telemetry.access_log_skip_paths.clone()))
+        .layer(ErrorFormatLayer::new())
+        .layer(RequestIdLayer::new())
+        .layer(cors_layer(&config.cors, settings)?)
+        .with_state(state.clone());
     // .layer(OtlpLayer::new());
 
-    let server = axum::Server::try_bind(&args.listen_address)
-        .with_context(|| format!("failed to bind to {}", args.listen_address))?
-        .serve(app.into_make_service());
+    let shutdown_signal = shutdown_signal()?;
+
+    let incoming = AddrIncoming::bind(&config.listener.address)
+        .with_context(|| format!("failed to bind to {}", config.listener.address))?;
 
-    debug!("Listening on {}", server.local_addr());
+    debug!("Listening on {}", incoming.local_addr());
 
-    server.with_graceful_shutdown(shutdown_signal()).await?;
+    let (stop_accepting, stopped_accepting) = tokio::sync::oneshot::channel::<()>();
+    let stopped_accepting = async {
+        let _ = stopped_accepting.await;
+    };
+    let make_service = app.into_make_service_with_connect_info::<ClientAddr>();
+
+    let server: Pin<Box<dyn Future<Output = hyper::Result<()>> + Send>> = match &config.tls {
+        None => Box::pin(
+            axum::Server::builder(incoming)
+                .serve(make_service)
+                .with_graceful_shutdown(stopped_accepting),
+        ),
+        Some(tls) => {
+            let acceptor = TlsAcceptor::from(Arc::new(tls.load_server_config()?));
+
+            // A failed handshake only affects a single connection, it should
+            // not stop the server.
+            let incoming = TlsListener::new_hyper(acceptor, incoming).filter(|conn| {
+                if let Err(err) = conn {
+                    debug!(%err, "TLS handshake failed");
+                }
+                std::future::ready(conn.is_ok())
+            });
+
+            Box::pin(
+                axum::Server::builder(hyper::server::accept::from_stream(incoming))
+                    .serve(make_service)
+                    .with_graceful_shutdown(stopped_accepting),
+            )
+        }
+    };
+    let mut server = server;
+
+    let signal = tokio::select! {
+        result = &mut server => {
+            result.context("server stopped unexpectedly")?;
+            bail!("server stopped unexpectedly");
+        }
+        signal = shutdown_signal => signal,
+    };
+
+    // Fail the readiness probe right away, but keep serving requests for a
+    // while, so no new traffic is routed to this server by the time it stops
+    // accepting connections.
+    let drain_period = config.listener.drain_period;
+    info!(%signal, ?drain_period, "Received shutdown signal, draining");
+    state.lifecycle.start_draining();
+
+    tokio::select! {
+        result = &mut server => {
+            result.context("server stopped unexpectedly")?;
+            bail!("server stopped unexpectedly");
+        }
+        _ = tokio::time::sleep(drain_period) => {}
+    }
+
+    debug!("Waiting for in-flight requests to finish");
+    let _ = stop_accepting.send(());
+    let shutdown_timeout = config.listener.shutdown_timeout;
+    let result = tokio::time::timeout(shutdown_timeout, &mut server).await;
+
+    // Sign the events of the last requests too, so they are covered when
+    // the server starts again.
+    if let Some(checkpointer) = checkpointer {
+        if let Err(err) = checkpointer.checkpoint().await {
+            warn!(%err, "Unable to sign the final audit checkpoints");
+        }
+    }
+
+    // Flush the store even if the server did not stop cleanly, so no
+    // acknowledged writes are lost.
+    state
+        .store
+        .close()
+        .await
+        .context("unable to close the store")?;
+
+    match result {
+        Ok(result) => result.context("server failed while shutting down")?,
+        Err(_) => {
+            warn!("In-flight requests did not finish in time, dropping connections");
+            bail!("shutdown timeout of {shutdown_timeout:?} exceeded, dropped open connections");
+        }
+    }
+
+    info!("Shutdown complete");
 
     Ok(())
 }
 
-async fn shutdown_signal() {
-    tokio::signal::ctrl_c().await.unwrap();
-    debug!("Received shutdown signal");
+/// Reload the configuration whenever SIGHUP is received.
+#[cfg(unix)]
+fn reload_on_sighup(reloader: Arc<Reloader>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup()).context("unable to listen for SIGHUP")?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading configuration");
+            reloader.reload_and_log();
+        }
+    });
+
+    Ok(())
+}
+
+/// There is no SIGHUP on this platform, so the configuration can only be
+/// reloaded by watching the file.
+#[cfg(not(unix))]
+fn reload_on_sighup(_reloader: Arc<Reloader>) -> Result<()> {
+    Ok(())
+}
+
+/// Listen for the signals that ask the server to shut down: SIGTERM and
+/// SIGINT. The returned future resolves with the name of the first signal that
+/// is received.
+#[cfg(unix)]
+fn shutdown_signal() -> Result<impl Future<Output = &'static str>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).context("unable to listen for SIGTERM")?;
+    let mut sigint = signal(SignalKind::interrupt()).context("unable to listen for SIGINT")?;
+
+    Ok(async move {
+        tokio::select! {
+            _ = sigterm.recv() => "SIGTERM",
+            _ = sigint.recv() => "SIGINT",
+        }
+    })
+}
+
+/// Listen for Ctrl-C, which is the only shutdown signal on this platform.
+#[cfg(not(unix))]
+fn shutdown_signal() -> Result<impl Future<Output = &'static str>> {
+    Ok(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            // Without a working signal handler the server can only be killed.
+            std::future::pending::<()>().await;
+        }
+        "Ctrl-C"
+    })
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Otlp<S> {
     inner: S,
 }
 
+#[allow(dead_code)]
 impl<S> Otlp<S> {
     fn new(inner: S) -> Self {
         Self { inner }
@@ -90,9 +480,11 @@ where
     }
 }
 
+#[allow(dead_code)]
 #[derive(Clone)]
 pub struct OtlpLayer {}
 
+#[allow(dead_code)]
 impl OtlpLayer {
     pub fn new() -> Self {
         Self {}
@@ -0,0 +1,1125 @@
+use crate::middleware::cors::OriginPattern;
+use crate::models::{Role, IDEMPOTENCY_KEY_HEADER, REQUEST_ID_HEADER, TENANT_HEADER};
+use crate::sampling::SamplingStrategy;
+use crate::tenant::TenantId;
+use crate::validation::CharacterClass;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ipnet::IpNet;
+use jsonschema::JSONSchema;
+use ring::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+use url::Url;
+
+/// Configuration of the server.
+///
+/// Settings are layered: the defaults are overridden by the configuration
+/// file, which is overridden by environment variables, which are overridden
+/// by command line flags. Only the first two layers are handled here, the
+/// others are applied by the commands through clap.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listener: ListenerConfig,
+    pub tls: Option<TlsConfig>,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub tenancy: TenancyConfig,
+    pub telemetry: TelemetryConfig,
+    pub limits: LimitsConfig,
+    pub cors: CorsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub validation: ValidationConfig,
+    pub idempotency: IdempotencyConfig,
+    pub renames: RenameConfig,
+    pub lockout: LockoutConfig,
+    pub audit: AuditConfig,
+}
+
+impl Config {
+    /// Load the configuration from a TOML file, or use the defaults if no
+    /// file is given.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Config::default());
+        };
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("unable to read config file {}", path.display()))?;
+
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Check the settings that cannot be verified while deserializing.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(tls) = &self.tls {
+            tls.load_server_config()?;
+        }
+
+        self.auth.validate()?;
+        self.tenancy.validate(&self.auth)?;
+        self.telemetry.validate()?;
+        self.limits.validate()?;
+        self.cors.validate()?;
+        self.rate_limit.validate()?;
+        self.validation.validate()?;
+        self.idempotency.validate()?;
+        self.audit.validate()?;
+
+        Ok(())
+    }
+
+    /// Render the configuration as TOML, with all secrets redacted.
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self).context("unable to serialize config")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Address the server listens on.
+    pub address: SocketAddr,
+
+    /// How long the server keeps accepting requests after a shutdown signal,
+    /// while failing its readiness probe.
+    #[serde(with = "humantime_serde")]
+    pub drain_period: Duration,
+
+    /// How long in-flight requests get to finish once the server stops
+    /// accepting requests.
+    #[serde(with = "humantime_serde")]
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            address: ([127, 0, 0, 1], 3000).into(),
+            drain_period: Duration::from_secs(5),
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Serve HTTPS instead of plain HTTP.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file containing the certificate chain.
+    pub cert_path: PathBuf,
+
+    /// PEM file containing the private key.
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Load the certificate and key and create the rustls configuration.
+    pub fn load_server_config(&self) -> Result<tokio_rustls::rustls::ServerConfig> {
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+        let certs = fs::read(&self.cert_path)
+            .with_context(|| format!("unable to read certificate {}", self.cert_path.display()))?;
+        let certs: Vec<Certificate> = rustls_pemfile::certs(&mut certs.as_slice())
+            .with_context(|| format!("invalid certificate {}", self.cert_path.display()))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        if certs.is_empty() {
+            bail!("no certificates found in {}", self.cert_path.display());
+        }
+
+        let key = fs::read(&self.key_path)
+            .with_context(|| format!("unable to read private key {}", self.key_path.display()))?;
+        let key = rustls_pemfile::read_all(&mut key.as_slice())
+            .with_context(|| format!("invalid private key {}", self.key_path.display()))?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .with_context(|| format!("no private key found in {}", self.key_path.display()))?;
+
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("certificate and private key do not match")?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// Keep everything in memory, nothing is persisted across restarts.
+    #[default]
+    Memory,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Reject requests that do not carry an API key. If this is disabled,
+    /// requests without an API key are handled anonymously, but requests with
+    /// an unknown key are still rejected.
+    pub required: bool,
+
+    /// The API keys that are accepted, sent as a bearer token.
+    pub api_keys: Vec<ApiKeyConfig>,
+
+    /// Check the roles of the principal before changes are made: changing
+    /// users requires the `user_admin` role, managing groups `group_admin`,
+    /// and giving groups roles `admin`. Principals get roles from their API
+    /// key and from the groups of the user with the same name. If this is
+    /// disabled, every principal can make every change.
+    pub enforce_roles: bool,
+}
+
+impl AuthConfig {
+    fn validate(&self) -> Result<()> {
+        if self.required && self.api_keys.is_empty() {
+            bail!("auth.required is set, but no auth.api_keys are configured");
+        }
+
+        let mut keys = HashSet::new();
+        for api_key in &self.api_keys {
+            if api_key.principal.is_empty() {
+                bail!("auth.api_keys contains a key without a principal");
+            }
+            if api_key.key.expose().is_empty() {
+                bail!("API key for principal {} is empty", api_key.principal);
+            }
+            if !keys.insert(api_key.key.expose()) {
+                bail!("API key for principal {} is not unique", api_key.principal);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// The principal that requests using this key are made on behalf of.
+    pub principal: String,
+
+    pub key: Secret,
+
+    /// Roles granted to the principal, in addition to the roles of its
+    /// groups. This is how principals that are not users, like CI jobs, and
+    /// the first admins get their roles.
+    #[serde(default)]
+    pub roles: Vec<Role>,
+
+    /// The tenant the key belongs to. Such a key is only accepted for
+    /// requests to its own tenant; keys without a tenant are accepted for
+    /// every tenant.
+    pub tenant: Option<TenantId>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenancyConfig {
+    /// Where the tenant of a request comes from. Users and groups of
+    /// different tenants are completely separate, usernames only have to be
+    /// unique within a tenant.
+    pub source: TenantSource,
+
+    /// The header that names the tenant, if the source is `header`.
+    pub header: String,
+
+    /// The domain below which every tenant has a subdomain, if the source is
+    /// `subdomain`: requests to `acme.users.example.com` are made for the
+    /// tenant `acme` if this is `users.example.com`.
+    pub base_domain: Option<String>,
+
+    /// The tenant of all requests if the source is `none`.
+    pub default_tenant: TenantId,
+
+    /// The tenants that are served, all others are rejected. If this is
+    /// empty, any valid tenant is served. It is required if clients name the
+    /// tenant, with the `header` or `subdomain` source, since every tenant
+    /// that is served takes up memory and metric labels.
+    pub allowed_tenants: Vec<TenantId>,
+}
+
+impl Default for TenancyConfig {
+    fn default() -> Self {
+        Self {
+            source: TenantSource::None,
+            header: TENANT_HEADER.to_string(),
+            base_domain: None,
+            default_tenant: TenantId::default(),
+            allowed_tenants: Vec::new(),
+        }
+    }
+}
+
+impl TenancyConfig {
+    fn validate(&self, auth: &AuthConfig) -> Result<()> {
+        http::HeaderName::try_from(self.header.as_str())
+            .context("tenancy.header is not a valid header name")?;
+
+        match self.source {
+            TenantSource::None => {
+                for api_key in &auth.api_keys {
+                    if api_key
+                        .tenant
+                        .as_ref()
+                        .is_some_and(|tenant| *tenant != self.default_tenant)
+                    {
+                        bail!(
+                            "API key for principal {} belongs to another tenant than \
+                             tenancy.default_tenant",
+                            api_key.principal
+                        );
+                    }
+                }
+            }
+            TenantSource::Header => {
+                if self.allowed_tenants.is_empty() {
+                    bail!("tenancy.source is header, but no tenancy.allowed_tenants are set");
+                }
+            }
+            TenantSource::Subdomain => {
+                if self.base_domain.as_deref().unwrap_or_default().is_empty() {
+                    bail!("tenancy.source is subdomain, but no tenancy.base_domain is set");
+                }
+                if self.allowed_tenants.is_empty() {
+                    bail!("tenancy.source is subdomain, but no tenancy.allowed_tenants are set");
+                }
+            }
+            TenantSource::ApiKey => {
+                if let Some(api_key) = auth.api_keys.iter().find(|key| key.tenant.is_none()) {
+                    bail!(
+                        "tenancy.source is api_key, but the API key for principal {} has no \
+                         tenant",
+                        api_key.principal
+                    );
+                }
+            }
+        }
+
+        if !self.allowed_tenants.is_empty() {
+            if self.source == TenantSource::None
+                && !self.allowed_tenants.contains(&self.default_tenant)
+            {
+                bail!("tenancy.default_tenant is not in tenancy.allowed_tenants");
+            }
+            if let Some(tenant) = auth
+                .api_keys
+                .iter()
+                .filter_map(|api_key| api_key.tenant.as_ref())
+                .find(|tenant| !self.allowed_tenants.contains(tenant))
+            {
+                bail!("an API key belongs to tenant {tenant}, which is not in tenancy.allowed_tenants");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantSource {
+    /// There is a single tenant, `tenancy.default_tenant`.
+    #[default]
+    None,
+
+    /// The tenant is named by the `tenancy.header` header, which is set by
+    /// a trusted proxy or by the clients themselves.
+    Header,
+
+    /// The tenant is the subdomain of `tenancy.base_domain` the request is
+    /// made to.
+    Subdomain,
+
+    /// The tenant is the one the API key of the request belongs to, so every
+    /// API key needs a tenant and anonymous requests are rejected.
+    ApiKey,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Filter directives for the logs, in the same format as `RUST_LOG`.
+    pub log_filter: String,
+
+    /// Log using JSON.
+    pub log_json: bool,
+
+    /// Paths for which no access log is written.
+    pub access_log_skip_paths: Vec<String>,
+
+    /// Export traces to an OTLP collector.
+    pub tracing: bool,
+
+    /// Endpoint of the OTLP collector.
+    pub otlp_endpoint: Url,
+
+    /// Strategy used to decide which traces are exported.
+    pub trace_sampler: SamplingStrategy,
+
+    /// Fraction of traces that is exported by the ratio based samplers.
+    pub trace_sample_ratio: f64,
+}
+
+impl TelemetryConfig {
+    fn validate(&self) -> Result<()> {
+        EnvFilter::try_new(&self.log_filter).context("invalid telemetry.log_filter")?;
+
+        if !(0.0..=1.0).contains(&self.trace_sample_ratio) {
+            bail!("telemetry.trace_sample_ratio has to be between 0.0 and 1.0");
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_filter: "error".to_string(),
+            log_json: false,
+            access_log_skip_paths: ["/healthz", "/readyz", "/metrics"]
+                .map(String::from)
+                .to_vec(),
+            tracing: false,
+            otlp_endpoint: Url::parse("http://localhost:4317").expect("valid default URL"),
+            trace_sampler: SamplingStrategy::Always,
+            trace_sample_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum size of a request body, in bytes.
+    pub max_body_size: usize,
+
+    /// Reject JSON request bodies with fields the API does not know, instead
+    /// of ignoring them.
+    pub deny_unknown_fields: bool,
+
+    /// Maximum number of API requests that are handled at the same time.
+    /// Requests over this limit are rejected right away instead of queued.
+    /// Zero means unlimited.
+    pub max_in_flight: usize,
+
+    /// How long an API request may take before it is aborted.
+    #[serde(with = "humantime_serde")]
+    pub request_timeout: Duration,
+
+    /// How long clients are told to wait before retrying a rejected request.
+    #[serde(with = "humantime_serde")]
+    pub retry_after: Duration,
+
+    /// Limits for specific routes, on top of the limits above.
+    pub routes: Vec<RouteLimitsConfig>,
+}
+
+impl LimitsConfig {
+    fn validate(&self) -> Result<()> {
+        if self.request_timeout.is_zero() {
+            bail!("limits.request_timeout has to be positive");
+        }
+
+        for route in &self.routes {
+            if !route.path.starts_with('/') {
+                bail!("limited route {:?} has to start with /", route.path);
+            }
+            if route
+                .request_timeout
+                .is_some_and(|timeout| timeout.is_zero())
+            {
+                bail!(
+                    "request timeout for route {:?} has to be positive",
+                    route.path
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: 2 * 1024 * 1024,
+            deny_unknown_fields: false,
+            max_in_flight: 1024,
+            request_timeout: Duration::from_secs(30),
+            retry_after: Duration::from_secs(1),
+            routes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteLimitsConfig {
+    /// The route as it is declared in the router, like `/users/:user_name`.
+    pub path: String,
+
+    /// Maximum number of requests to this route that are handled at the same
+    /// time. Zero means unlimited.
+    #[serde(default)]
+    pub max_in_flight: usize,
+
+    /// Overrides `limits.request_timeout` for this route.
+    #[serde(default, with = "humantime_serde")]
+    pub request_timeout: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Allow every origin, method and header. Only meant for local
+    /// development, all other CORS settings are ignored when this is set.
+    pub dev_permissive: bool,
+
+    /// Origins that are allowed to make cross-origin requests, like
+    /// `https://app.example.com`. A leading wildcard label, as in
+    /// `https://*.example.com`, allows every subdomain. If this is empty,
+    /// cross-origin requests are not allowed.
+    pub allowed_origins: Vec<String>,
+
+    /// Methods that cross-origin requests may use.
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers that cross-origin requests may send.
+    pub allowed_headers: Vec<String>,
+
+    /// Allow cross-origin requests to include credentials.
+    pub allow_credentials: bool,
+
+    /// How long browsers may cache the result of a preflight request.
+    #[serde(with = "humantime_serde")]
+    pub max_age: Duration,
+}
+
+impl CorsConfig {
+    fn validate(&self) -> Result<()> {
+        for origin in &self.allowed_origins {
+            OriginPattern::parse(origin)?;
+        }
+        for method in &self.allowed_methods {
+            http::Method::from_bytes(method.as_bytes())
+                .with_context(|| format!("invalid CORS method {method:?}"))?;
+        }
+        for header in &self.allowed_headers {
+            http::HeaderName::from_bytes(header.as_bytes())
+                .with_context(|| format!("invalid CORS header {header:?}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            dev_permissive: false,
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: [
+                "authorization",
+                "content-type",
+                IDEMPOTENCY_KEY_HEADER,
+                "if-match",
+                "if-none-match",
+                REQUEST_ID_HEADER,
+            ]
+            .map(String::from)
+            .to_vec(),
+            allow_credentials: false,
+            max_age: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Limit the rate of requests to the API.
+    pub enabled: bool,
+
+    /// Proxies whose `X-Forwarded-For` header is trusted to contain the
+    /// address of the client, as networks like `10.0.0.0/8` or `10.0.0.1/32`.
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Number of requests a client can make per `period` to routes without a
+    /// limit of their own. Requests are limited per principal, or per client
+    /// address for anonymous requests.
+    pub requests: u32,
+
+    /// The period in which `requests` are allowed.
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+
+    /// Limits for specific routes.
+    pub routes: Vec<RouteRateLimitConfig>,
+}
+
+impl RateLimitConfig {
+    fn validate(&self) -> Result<()> {
+        if self.requests == 0 || self.period.is_zero() {
+            bail!("rate_limit.requests and rate_limit.period have to be positive");
+        }
+
+        for route in &self.routes {
+            if !route.path.starts_with('/') {
+                bail!("rate limited route {:?} has to start with /", route.path);
+            }
+            if let Some(method) = &route.method {
+                http::Method::from_bytes(method.as_bytes())
+                    .with_context(|| format!("invalid method {method:?} for rate limited route"))?;
+            }
+            if route.requests == 0 || route.period.is_zero() {
+                bail!(
+                    "rate limit for route {:?} needs positive requests and period",
+                    route.path
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            trusted_proxies: Vec::new(),
+            requests: 600,
+            period: Duration::from_secs(60),
+            routes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRateLimitConfig {
+    /// The route as it is declared in the router, like `/users/:user_name`.
+    pub path: String,
+
+    /// Only limit requests with this method. All methods are limited if it is
+    /// not set.
+    pub method: Option<String>,
+
+    pub requests: u32,
+
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+}
+
+/// Rules for the fields of new users.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    pub username: UsernameValidationConfig,
+    pub name: NameValidationConfig,
+    pub attributes: AttributesValidationConfig,
+}
+
+impl ValidationConfig {
+    fn validate(&self) -> Result<()> {
+        let username = &self.username;
+        if username.min_length == 0 || username.min_length > username.max_length {
+            bail!("validation.username needs 0 < min_length <= max_length");
+        }
+        if username.allowed_characters.is_empty() {
+            bail!("validation.username.allowed_characters must not be empty");
+        }
+        if username.reserved.iter().any(|name| name.is_empty()) {
+            bail!("validation.username.reserved must not contain empty usernames");
+        }
+
+        let name = &self.name;
+        if name.min_length == 0 || name.min_length > name.max_length {
+            bail!("validation.name needs 0 < min_length <= max_length");
+        }
+
+        if let Some(schema) = &self.attributes.schema {
+            if let Err(err) = JSONSchema::compile(schema) {
+                bail!("validation.attributes.schema is not a valid JSON Schema: {err}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsernameValidationConfig {
+    /// Minimum length of a username, in characters.
+    pub min_length: usize,
+
+    /// Maximum length of a username, in characters.
+    pub max_length: usize,
+
+    /// Classes of characters that usernames may consist of.
+    pub allowed_characters: Vec<CharacterClass>,
+
+    /// Other characters that usernames may contain, like `_`.
+    pub allowed_symbols: String,
+
+    /// Usernames that cannot be registered, compared in their canonical form
+    /// so case and Unicode normalization do not matter.
+    pub reserved: Vec<String>,
+
+    /// Reject usernames that mix scripts, like Latin and Cyrillic, and
+    /// usernames that look like a reserved or existing username, like
+    /// `adm1n` for `admin`.
+    pub reject_confusables: bool,
+}
+
+impl Default for UsernameValidationConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 1,
+            max_length: 20,
+            allowed_characters: vec![
+                CharacterClass::AsciiLowercase,
+                CharacterClass::AsciiUppercase,
+                CharacterClass::AsciiDigit,
+            ],
+            allowed_symbols: "_-.".to_string(),
+            reserved: [
+                "admin",
+                "administrator",
+                "api",
+                "me",
+                "root",
+                "security",
+                "support",
+                "system",
+            ]
+            .map(String::from)
+            .to_vec(),
+            reject_confusables: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NameValidationConfig {
+    /// Minimum length of a name, in user-perceived characters (grapheme
+    /// clusters). Leading and trailing whitespace does not count.
+    pub min_length: usize,
+
+    /// Maximum length of a name, in user-perceived characters (grapheme
+    /// clusters).
+    pub max_length: usize,
+}
+
+impl Default for NameValidationConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 1,
+            max_length: 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttributesValidationConfig {
+    /// JSON Schema that the `attributes` object of every user has to match,
+    /// written as a TOML table. Without a schema, any object is accepted.
+    pub schema: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    /// How long the response to a request with an `Idempotency-Key` header is
+    /// kept, to be replayed if the request is retried with the same key.
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+
+    /// Maximum length of an idempotency key.
+    pub max_key_length: usize,
+}
+
+impl IdempotencyConfig {
+    fn validate(&self) -> Result<()> {
+        if self.ttl.is_zero() || self.max_key_length == 0 {
+            bail!("idempotency.ttl and idempotency.max_key_length have to be positive");
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_key_length: 255,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenameConfig {
+    /// How long the old username of a renamed user is kept: requests for it
+    /// are redirected to the new username, and nobody else can register it.
+    /// Zero releases old usernames right away.
+    #[serde(with = "humantime_serde")]
+    pub grace_period: Duration,
+}
+
+impl Default for RenameConfig {
+    fn default() -> Self {
+        Self {
+            grace_period: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    /// Number of failed logins in a row after which a user is locked, until
+    /// an admin reactivates it. Zero never locks users.
+    pub max_failed_logins: u32,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failed_logins: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// How often a signed checkpoint of the audit log of every tenant is
+    /// stored, if the log changed since the last one.
+    #[serde(with = "humantime_serde")]
+    pub checkpoint_interval: Duration,
+
+    /// Base64 encoded 32 byte seed of the Ed25519 key that signs the
+    /// checkpoints. Without a key, no checkpoints are signed.
+    pub checkpoint_key: Option<Secret>,
+}
+
+impl AuditConfig {
+    fn validate(&self) -> Result<()> {
+        if self.checkpoint_interval.is_zero() {
+            bail!("audit.checkpoint_interval has to be positive");
+        }
+        self.checkpoint_key()?;
+
+        Ok(())
+    }
+
+    /// The key pair that signs the checkpoints, if one is configured.
+    pub fn checkpoint_key(&self) -> Result<Option<Ed25519KeyPair>> {
+        let Some(key) = &self.checkpoint_key else {
+            return Ok(None);
+        };
+
+        let seed = BASE64
+            .decode(key.expose())
+            .context("audit.checkpoint_key is not valid base64")?;
+        if seed.len() != 32 {
+            bail!("audit.checkpoint_key has to be 32 bytes long");
+        }
+        Ed25519KeyPair::from_seed_unchecked(&seed)
+            .map(Some)
+            .map_err(|err| anyhow!("audit.checkpoint_key is invalid: {err}"))
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval: Duration::from_secs(60 * 60),
+            checkpoint_key: None,
+        }
+    }
+}
+
+/// A value that should never end up in logs or printed configuration, such as
+/// an API key. It is redacted when it is serialized or debug printed.
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    #[cfg(test)]
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_file_uses_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+
+        assert_eq!(ListenerConfig::default().address, config.listener.address);
+        assert!(config.tls.is_none());
+        assert_eq!(StorageBackend::Memory, config.storage.backend);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn parse_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+            [listener]
+            address = "0.0.0.0:8080"
+            drain_period = "10s"
+
+            [[auth.api_keys]]
+            principal = "ci"
+            key = "s3cr3t"
+            tenant = "acme"
+
+            [tenancy]
+            source = "header"
+            allowed_tenants = ["acme", "globex"]
+
+            [telemetry]
+            trace_sampler = "rules"
+            trace_sample_ratio = 0.1
+
+            [[limits.routes]]
+            path = "/users"
+            max_in_flight = 16
+            request_timeout = "5s"
+
+            [rate_limit]
+            enabled = true
+            trusted_proxies = ["10.0.0.0/8", "192.168.1.1/32"]
+
+            [[rate_limit.routes]]
+            path = "/users"
+            method = "POST"
+            requests = 10
+            period = "1m"
+
+            [validation.username]
+            allowed_characters = ["ascii_lowercase", "letter"]
+            reserved = ["staff"]
+
+            [validation.attributes.schema]
+            type = "object"
+            properties.locale = { type = "string" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!("0.0.0.0:8080".parse(), Ok(config.listener.address));
+        assert_eq!(Duration::from_secs(10), config.listener.drain_period);
+        assert_eq!(Duration::from_secs(30), config.listener.shutdown_timeout);
+        assert_eq!("s3cr3t", config.auth.api_keys[0].key.expose());
+        assert_eq!(TenantSource::Header, config.tenancy.source);
+        assert_eq!(
+            config.auth.api_keys[0].tenant.as_ref(),
+            config.tenancy.allowed_tenants.first()
+        );
+        assert_eq!(SamplingStrategy::Rules, config.telemetry.trace_sampler);
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            config.limits.routes[0].request_timeout
+        );
+        assert_eq!(2, config.rate_limit.trusted_proxies.len());
+        assert_eq!(Duration::from_secs(60), config.rate_limit.routes[0].period);
+        assert_eq!(
+            vec![CharacterClass::AsciiLowercase, CharacterClass::Letter],
+            config.validation.username.allowed_characters
+        );
+        assert_eq!(20, config.validation.name.max_length);
+        assert_eq!(
+            Some(serde_json::json!({
+                "type": "object",
+                "properties": { "locale": { "type": "string" } },
+            })),
+            config.validation.attributes.schema
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_attributes_schemas_are_rejected() {
+        let mut config = Config::default();
+        config.validation.attributes.schema = Some(serde_json::json!({ "type": "text" }));
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let result = toml::from_str::<Config>("[listener]\nadress = \"0.0.0.0:8080\"");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let mut config = Config::default();
+        config.auth.api_keys.push(ApiKeyConfig {
+            principal: "ci".to_string(),
+            key: Secret::new("s3cr3t"),
+            roles: Vec::new(),
+            tenant: None,
+        });
+
+        let rendered = config.to_redacted_toml().unwrap();
+
+        assert!(!rendered.contains("s3cr3t"));
+        assert!(rendered.contains("<redacted>"));
+        assert!(!format!("{config:?}").contains("s3cr3t"));
+    }
+
+    #[test]
+    fn api_keys_have_to_fit_the_tenancy() {
+        let config = |source, tenant: Option<&str>| {
+            let mut config = Config::default();
+            config.tenancy.source = source;
+            if source == TenantSource::Header {
+                config.tenancy.allowed_tenants = vec![TenantId::parse("acme").unwrap()];
+            }
+            config.auth.api_keys.push(ApiKeyConfig {
+                principal: "ci".to_string(),
+                key: Secret::new("s3cr3t"),
+                roles: Vec::new(),
+                tenant: tenant.map(|tenant| TenantId::parse(tenant).unwrap()),
+            });
+            config
+        };
+
+        assert!(config(TenantSource::None, None).validate().is_ok());
+        assert!(config(TenantSource::None, Some("acme")).validate().is_err());
+        assert!(config(TenantSource::Header, Some("acme"))
+            .validate()
+            .is_ok());
+        assert!(config(TenantSource::ApiKey, None).validate().is_err());
+        assert!(config(TenantSource::ApiKey, Some("acme"))
+            .validate()
+            .is_ok());
+        assert!(config(TenantSource::Subdomain, None).validate().is_err());
+        assert!(toml::from_str::<Config>("[tenancy]\ndefault_tenant = \"Acme\"").is_err());
+    }
+
+    #[test]
+    fn tenants_named_by_clients_have_to_be_listed() {
+        for source in [TenantSource::Header, TenantSource::Subdomain] {
+            let mut config = Config::default();
+            config.tenancy.source = source;
+            config.tenancy.base_domain = Some("users.example.com".to_string());
+            assert!(config.validate().is_err(), "{source:?}");
+
+            config.tenancy.allowed_tenants = vec![TenantId::parse("acme").unwrap()];
+            assert!(config.validate().is_ok(), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn duplicate_api_keys_are_rejected() {
+        let mut config = Config::default();
+        for principal in ["a", "b"] {
+            config.auth.api_keys.push(ApiKeyConfig {
+                principal: principal.to_string(),
+                key: Secret::new("same"),
+                roles: Vec::new(),
+                tenant: None,
+            });
+        }
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_cors_settings_are_rejected() {
+        for (origin, method) in [("*", "GET"), ("https://*.example.com", "GET POST")] {
+            let mut config = Config::default();
+            config.cors.allowed_origins = vec![origin.to_string()];
+            config.cors.allowed_methods = vec![method.to_string()];
+
+            assert!(config.validate().is_err(), "{origin} {method}");
+        }
+    }
+
+    #[test]
+    fn invalid_validation_rules_are_rejected() {
+        let mut config = Config::default();
+        config.validation.name.min_length = 30;
+        assert!(config.validation.validate().is_err());
+
+        let mut config = Config::default();
+        config.validation.username.allowed_characters.clear();
+        assert!(config.validation.validate().is_err());
+    }
+
+    #[test]
+    fn checkpoint_keys_have_to_be_32_byte_seeds() {
+        let audit = |key: &str| AuditConfig {
+            checkpoint_key: Some(Secret::new(key)),
+            ..Default::default()
+        };
+
+        assert!(AuditConfig::default().checkpoint_key().unwrap().is_none());
+        assert!(audit(&BASE64.encode([7; 32]))
+            .checkpoint_key()
+            .unwrap()
+            .is_some());
+        assert!(audit(&BASE64.encode([7; 16])).validate().is_err());
+        assert!(audit("not base64!").validate().is_err());
+    }
+}
@@ -0,0 +1,1111 @@
+use super::{Store, StoreError, TenantStore, UsernameKeys};
+use crate::audit;
+use crate::models::{
+    AuditCheckpoint, AuditEvent, AuditFilter, Group, GroupMember, GroupUpdate, NewGroup, NewUser,
+    Role, StatusTransition, User, UserFilter, UserStatus, UserUpdate,
+};
+use crate::tenant::TenantId;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// A store that keeps everything in memory. Nothing is persisted across
+/// restarts, so this is only suitable for development and tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// The data of every tenant, created when it is first used.
+    tenants: Mutex<HashMap<TenantId, Arc<MemoryTenantStore>>>,
+}
+
+/// The users, groups and audit log of a single tenant of a [`MemoryStore`].
+#[derive(Debug, Default)]
+pub struct MemoryTenantStore {
+    // Whenever both are needed, users are locked before groups.
+    users: Mutex<Users>,
+    groups: Mutex<Groups>,
+
+    /// The audit events in the order they were appended.
+    audit: Mutex<Vec<AuditEvent>>,
+    checkpoints: Mutex<Vec<AuditCheckpoint>>,
+}
+
+#[derive(Debug, Default)]
+struct Users {
+    /// Ordered by id, so users can be listed a page at a time.
+    by_id: BTreeMap<Uuid, StoredUser>,
+    by_lookup_key: HashMap<String, Uuid>,
+
+    /// Old usernames of renamed users, by lookup key.
+    renamed: HashMap<String, OldUsername>,
+
+    /// The user every unique key belongs to, including the unique keys of
+    /// old usernames that are still kept.
+    unique_keys: HashMap<String, Uuid>,
+}
+
+#[derive(Debug)]
+struct StoredUser {
+    user: User,
+    unique_key: String,
+
+    /// Failed logins since the user was created or last reactivated.
+    failed_logins: u32,
+    transitions: Vec<StatusTransition>,
+}
+
+impl StoredUser {
+    fn transition(&mut self, status: UserStatus, reason: String, actor: String, now: SystemTime) {
+        self.transitions.push(StatusTransition {
+            from: self.user.status,
+            to: status,
+            reason,
+            actor,
+            at: now,
+        });
+        if status == UserStatus::Active {
+            self.failed_logins = 0;
+        }
+        self.user.status = status;
+        self.user.version += 1;
+        self.user.updated_at = now;
+    }
+}
+
+#[derive(Debug)]
+struct OldUsername {
+    id: Uuid,
+    unique_key: String,
+    until: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct Groups {
+    by_name: BTreeMap<String, StoredGroup>,
+}
+
+#[derive(Debug)]
+struct StoredGroup {
+    group: Group,
+
+    /// When each member was added, by user id.
+    members: BTreeMap<Uuid, SystemTime>,
+}
+
+impl Groups {
+    /// The groups ordered by name, starting after the group named `after`.
+    fn after<'a>(&'a self, after: Option<&str>) -> impl Iterator<Item = &'a StoredGroup> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        self.by_name
+            .range::<str, _>((start, Bound::Unbounded))
+            .map(|(_, stored)| stored)
+    }
+
+    fn get_mut(&mut self, name: &str) -> Result<&mut StoredGroup, StoreError> {
+        self.by_name.get_mut(name).ok_or(StoreError::GroupNotFound)
+    }
+}
+
+impl Users {
+    /// Whether any of the keys belongs to a user other than `id`.
+    fn is_taken(&self, keys: &UsernameKeys, id: Option<Uuid>) -> bool {
+        let other = |owner: &Uuid| Some(*owner) != id;
+
+        self.by_lookup_key.get(&keys.lookup).is_some_and(other)
+            || self
+                .renamed
+                .get(&keys.lookup)
+                .is_some_and(|old| other(&old.id))
+            || self.unique_keys.get(&keys.unique).is_some_and(other)
+    }
+
+    /// Forget the old usernames that are no longer kept, releasing their
+    /// unique keys unless the user still uses them.
+    fn prune(&mut self, now: SystemTime) {
+        let expired: Vec<String> = self
+            .renamed
+            .iter()
+            .filter(|(_, old)| old.until <= now)
+            .map(|(lookup_key, _)| lookup_key.clone())
+            .collect();
+
+        for lookup_key in expired {
+            let Some(old) = self.renamed.remove(&lookup_key) else {
+                continue;
+            };
+            let in_use = self
+                .by_id
+                .get(&old.id)
+                .is_some_and(|stored| stored.unique_key == old.unique_key)
+                || self
+                    .renamed
+                    .values()
+                    .any(|other| other.id == old.id && other.unique_key == old.unique_key);
+            if !in_use {
+                self.unique_keys.remove(&old.unique_key);
+            }
+        }
+    }
+
+    fn get_mut(&mut self, lookup_key: &str) -> Result<&mut StoredUser, StoreError> {
+        self.by_lookup_key
+            .get(lookup_key)
+            .and_then(|id| self.by_id.get_mut(id))
+            .ok_or(StoreError::UserNotFound)
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn check_version(current: u64, expected_version: Option<u64>) -> Result<(), StoreError> {
+    if expected_version.is_some_and(|version| version != current) {
+        return Err(StoreError::VersionConflict { current });
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn check_ready(&self) -> Result<(), StoreError> {
+        // There is nothing to connect to and no schema to migrate.
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn tenant(&self, tenant: &TenantId) -> Arc<dyn TenantStore> {
+        self.tenants
+            .lock()
+            .expect("tenants lock is poisoned")
+            .entry(tenant.clone())
+            .or_default()
+            .clone()
+    }
+
+    async fn tenants(&self) -> Result<Vec<TenantId>, StoreError> {
+        let tenants = self.tenants.lock().expect("tenants lock is poisoned");
+        Ok(tenants.keys().cloned().collect())
+    }
+}
+
+#[async_trait]
+impl TenantStore for MemoryTenantStore {
+    async fn create_user(&self, new_user: NewUser, keys: UsernameKeys) -> Result<User, StoreError> {
+        let now = SystemTime::now();
+        let mut users = self.users.lock().expect("users lock is poisoned");
+        users.prune(now);
+        if users.is_taken(&keys, None) {
+            return Err(StoreError::UsernameTaken);
+        }
+
+        let user = User {
+            id: Uuid::now_v7(),
+            username: new_user.username,
+            name: new_user.name,
+            email: new_user.email,
+            attributes: new_user.attributes,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            status: UserStatus::Active,
+        };
+
+        users.unique_keys.insert(keys.unique.clone(), user.id);
+        users.by_lookup_key.insert(keys.lookup, user.id);
+        users.by_id.insert(
+            user.id,
+            StoredUser {
+                user: user.clone(),
+                unique_key: keys.unique,
+                failed_logins: 0,
+                transitions: Vec::new(),
+            },
+        );
+        Ok(user)
+    }
+
+    async fn get_user(&self, lookup_key: &str) -> Result<Option<User>, StoreError> {
+        let users = self.users.lock().expect("users lock is poisoned");
+        Ok(users
+            .by_lookup_key
+            .get(lookup_key)
+            .and_then(|id| users.by_id.get(id))
+            .map(|stored| stored.user.clone()))
+    }
+
+    async fn get_user_by_id(&self, id: Uuid) -> Result<Option<User>, StoreError> {
+        let users = self.users.lock().expect("users lock is poisoned");
+        Ok(users.by_id.get(&id).map(|stored| stored.user.clone()))
+    }
+
+    async fn list_users(
+        &self,
+        filter: &UserFilter,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<User>, StoreError> {
+        let users = self.users.lock().expect("users lock is poisoned");
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        Ok(users
+            .by_id
+            .range((start, Bound::Unbounded))
+            .map(|(_, stored)| &stored.user)
+            .filter(|user| filter.matches(user))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_renamed_user(
+        &self,
+        lookup_key: &str,
+        now: SystemTime,
+    ) -> Result<Option<User>, StoreError> {
+        let users = self.users.lock().expect("users lock is poisoned");
+        Ok(users
+            .renamed
+            .get(lookup_key)
+            .filter(|old| old.until > now)
+            .and_then(|old| users.by_id.get(&old.id))
+            .map(|stored| stored.user.clone()))
+    }
+
+    async fn update_user(
+        &self,
+        lookup_key: &str,
+        expected_version: Option<u64>,
+        update: UserUpdate,
+    ) -> Result<User, StoreError> {
+        let mut users = self.users.lock().expect("users lock is poisoned");
+        let user = &mut users.get_mut(lookup_key)?.user;
+        check_version(user.version, expected_version)?;
+
+        if let Some(name) = update.name {
+            user.name = name;
+        }
+        if let Some(email) = update.email {
+            user.email = email;
+        }
+        if let Some(attributes) = update.attributes {
+            user.attributes = attributes;
+        }
+        user.version += 1;
+        user.updated_at = SystemTime::now();
+
+        Ok(user.clone())
+    }
+
+    async fn rename_user(
+        &self,
+        lookup_key: &str,
+        expected_version: Option<u64>,
+        username: String,
+        keys: UsernameKeys,
+        keep_old_until: SystemTime,
+    ) -> Result<User, StoreError> {
+        let now = SystemTime::now();
+        let mut users = self.users.lock().expect("users lock is poisoned");
+        users.prune(now);
+
+        let stored = users.get_mut(lookup_key)?;
+        check_version(stored.user.version, expected_version)?;
+        let id = stored.user.id;
+        if users.is_taken(&keys, Some(id)) {
+            return Err(StoreError::UsernameTaken);
+        }
+
+        // A rename that only changes case or normalization keeps the keys.
+        if keys.lookup != lookup_key {
+            let old_unique_key = std::mem::replace(
+                &mut users.get_mut(lookup_key)?.unique_key,
+                keys.unique.clone(),
+            );
+            users.by_lookup_key.remove(lookup_key);
+            users.by_lookup_key.insert(keys.lookup.clone(), id);
+            users.unique_keys.insert(keys.unique, id);
+            // Renaming back to an old username makes it current again.
+            users.renamed.remove(&keys.lookup);
+            users.renamed.insert(
+                lookup_key.to_string(),
+                OldUsername {
+                    id,
+                    unique_key: old_unique_key,
+                    until: keep_old_until,
+                },
+            );
+        }
+
+        let user = &mut users.get_mut(&keys.lookup)?.user;
+        user.username = username;
+        user.version += 1;
+        user.updated_at = now;
+
+        Ok(user.clone())
+    }
+
+    async fn change_status(
+        &self,
+        lookup_key: &str,
+        expected_version: Option<u64>,
+        status: UserStatus,
+        reason: String,
+        actor: String,
+    ) -> Result<User, StoreError> {
+        let mut users = self.users.lock().expect("users lock is poisoned");
+        let stored = users.get_mut(lookup_key)?;
+        check_version(stored.user.version, expected_version)?;
+        if !stored.user.status.can_become(status) {
+            return Err(StoreError::InvalidTransition {
+                from: stored.user.status,
+                to: status,
+            });
+        }
+
+        stored.transition(status, reason, actor, SystemTime::now());
+        Ok(stored.user.clone())
+    }
+
+    async fn status_transitions(
+        &self,
+        lookup_key: &str,
+    ) -> Result<Vec<StatusTransition>, StoreError> {
+        let mut users = self.users.lock().expect("users lock is poisoned");
+        Ok(users.get_mut(lookup_key)?.transitions.clone())
+    }
+
+    async fn record_failed_login(
+        &self,
+        lookup_key: &str,
+        max_failed_logins: u32,
+    ) -> Result<User, StoreError> {
+        let mut users = self.users.lock().expect("users lock is poisoned");
+        let stored = users.get_mut(lookup_key)?;
+        stored.failed_logins += 1;
+        if max_failed_logins > 0
+            && stored.failed_logins >= max_failed_logins
+            && stored.user.status == UserStatus::Active
+        {
+            let reason = format!("{} failed logins in a row", stored.failed_logins);
+            stored.transition(
+                UserStatus::Locked,
+                reason,
+                "system".to_string(),
+                SystemTime::now(),
+            );
+        }
+
+        Ok(stored.user.clone())
+    }
+
+    async fn create_group(&self, new_group: NewGroup) -> Result<Group, StoreError> {
+        let mut groups = self.groups.lock().expect("groups lock is poisoned");
+        if groups.by_name.contains_key(&new_group.name) {
+            return Err(StoreError::GroupNameTaken);
+        }
+
+        let now = SystemTime::now();
+        let group = Group {
+            id: Uuid::now_v7(),
+            name: new_group.name,
+            description: new_group.description,
+            roles: new_group.roles,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+        };
+        groups.by_name.insert(
+            group.name.clone(),
+            StoredGroup {
+                group: group.clone(),
+                members: BTreeMap::new(),
+            },
+        );
+        Ok(group)
+    }
+
+    async fn get_group(&self, name: &str) -> Result<Option<Group>, StoreError> {
+        let groups = self.groups.lock().expect("groups lock is poisoned");
+        Ok(groups.by_name.get(name).map(|stored| stored.group.clone()))
+    }
+
+    async fn list_groups(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Group>, StoreError> {
+        let groups = self.groups.lock().expect("groups lock is poisoned");
+        Ok(groups
+            .after(after)
+            .take(limit)
+            .map(|stored| stored.group.clone())
+            .collect())
+    }
+
+    async fn update_group(
+        &self,
+        name: &str,
+        expected_version: Option<u64>,
+        update: GroupUpdate,
+    ) -> Result<Group, StoreError> {
+        let mut groups = self.groups.lock().expect("groups lock is poisoned");
+        let group = &mut groups.get_mut(name)?.group;
+        check_version(group.version, expected_version)?;
+
+        if let Some(description) = update.description {
+            group.description = description;
+        }
+        if let Some(roles) = update.roles {
+            group.roles = roles;
+        }
+        group.version += 1;
+        group.updated_at = SystemTime::now();
+
+        Ok(group.clone())
+    }
+
+    async fn delete_group(
+        &self,
+        name: &str,
+        expected_version: Option<u64>,
+    ) -> Result<(), StoreError> {
+        let mut groups = self.groups.lock().expect("groups lock is poisoned");
+        check_version(groups.get_mut(name)?.group.version, expected_version)?;
+        groups.by_name.remove(name);
+
+        Ok(())
+    }
+
+    async fn add_member(&self, group: &str, user_id: Uuid) -> Result<bool, StoreError> {
+        let users = self.users.lock().expect("users lock is poisoned");
+        let mut groups = self.groups.lock().expect("groups lock is poisoned");
+        let stored = groups.get_mut(group)?;
+        if !users.by_id.contains_key(&user_id) {
+            return Err(StoreError::UserNotFound);
+        }
+        if stored.members.contains_key(&user_id) {
+            return Ok(false);
+        }
+
+        stored.members.insert(user_id, SystemTime::now());
+        Ok(true)
+    }
+
+    async fn remove_member(&self, group: &str, user_id: Uuid) -> Result<bool, StoreError> {
+        let mut groups = self.groups.lock().expect("groups lock is poisoned");
+        let removed = groups.get_mut(group)?.members.remove(&user_id);
+
+        Ok(removed.is_some())
+    }
+
+    async fn group_members(
+        &self,
+        group: &str,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<GroupMember>, StoreError> {
+        let users = self.users.lock().expect("users lock is poisoned");
+        let mut groups = self.groups.lock().expect("groups lock is poisoned");
+        let stored = groups.get_mut(group)?;
+
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        Ok(stored
+            .members
+            .range((start, Bound::Unbounded))
+            .filter_map(|(id, added_at)| {
+                users.by_id.get(id).map(|member| GroupMember {
+                    id: *id,
+                    username: member.user.username.clone(),
+                    added_at: *added_at,
+                })
+            })
+            .take(limit)
+            .collect())
+    }
+
+    async fn user_groups(
+        &self,
+        user_id: Uuid,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Group>, StoreError> {
+        let groups = self.groups.lock().expect("groups lock is poisoned");
+        Ok(groups
+            .after(after)
+            .filter(|stored| stored.members.contains_key(&user_id))
+            .take(limit)
+            .map(|stored| stored.group.clone())
+            .collect())
+    }
+
+    async fn user_roles(&self, user_id: Uuid) -> Result<Vec<Role>, StoreError> {
+        let groups = self.groups.lock().expect("groups lock is poisoned");
+        let roles: BTreeSet<Role> = groups
+            .by_name
+            .values()
+            .filter(|stored| stored.members.contains_key(&user_id))
+            .flat_map(|stored| stored.group.roles.iter().copied())
+            .collect();
+
+        Ok(roles.into_iter().collect())
+    }
+
+    async fn append_audit_event(&self, mut event: AuditEvent) -> Result<(), StoreError> {
+        let mut audit = self.audit.lock().expect("audit lock is poisoned");
+        audit::chain(&mut event, audit.last());
+        audit.push(event);
+
+        Ok(())
+    }
+
+    async fn last_audit_event(&self) -> Result<Option<AuditEvent>, StoreError> {
+        let audit = self.audit.lock().expect("audit lock is poisoned");
+        Ok(audit.last().cloned())
+    }
+
+    async fn audit_events(
+        &self,
+        filter: &AuditFilter,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<AuditEvent>, StoreError> {
+        let audit = self.audit.lock().expect("audit lock is poisoned");
+        let start = match after {
+            Some(after) => match audit.iter().position(|event| event.id == after) {
+                Some(position) => position + 1,
+                None => audit.len(),
+            },
+            None => 0,
+        };
+
+        Ok(audit[start..]
+            .iter()
+            .filter(|event| filter.matches(event))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn append_audit_checkpoint(&self, checkpoint: AuditCheckpoint) -> Result<(), StoreError> {
+        let mut checkpoints = self
+            .checkpoints
+            .lock()
+            .expect("checkpoints lock is poisoned");
+        checkpoints.push(checkpoint);
+
+        Ok(())
+    }
+
+    async fn audit_checkpoints(&self) -> Result<Vec<AuditCheckpoint>, StoreError> {
+        let checkpoints = self
+            .checkpoints
+            .lock()
+            .expect("checkpoints lock is poisoned");
+        Ok(checkpoints.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{Attributes, AuditAction};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn keys(lookup: &str, unique: &str) -> UsernameKeys {
+        UsernameKeys {
+            lookup: lookup.to_string(),
+            unique: unique.to_string(),
+        }
+    }
+
+    fn new_user(username: &str) -> NewUser {
+        NewUser {
+            username: username.to_string(),
+            name: "Jane".to_string(),
+            email: None,
+            attributes: Attributes::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn unique_keys_are_enforced() {
+        let store = MemoryTenantStore::default();
+
+        let created = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        let taken = store
+            .create_user(new_user("jane"), keys("jane", "jane"))
+            .await;
+        let confusable = store
+            .create_user(new_user("jаne"), keys("jаne", "jane"))
+            .await;
+
+        assert!(matches!(taken, Err(StoreError::UsernameTaken)));
+        assert!(matches!(confusable, Err(StoreError::UsernameTaken)));
+        assert_eq!(Some(created), store.get_user("jane").await.unwrap());
+        assert_eq!(None, store.get_user("jаne").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn tenants_do_not_share_users_or_groups() {
+        let store = MemoryStore::new();
+        let acme = store.tenant(&TenantId::parse("acme").unwrap());
+        let globex = store.tenant(&TenantId::parse("globex").unwrap());
+
+        let jane = acme
+            .create_user(new_user("jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        let other_jane = globex
+            .create_user(new_user("jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        acme.create_group(new_group("admins", vec![Role::Admin]))
+            .await
+            .unwrap();
+        let group_of_other_tenant = globex.add_member("admins", other_jane.id).await;
+        globex
+            .create_group(new_group("admins", Vec::new()))
+            .await
+            .unwrap();
+        let user_of_other_tenant = globex.add_member("admins", jane.id).await;
+
+        assert_ne!(jane.id, other_jane.id);
+        assert_eq!(None, globex.get_user_by_id(jane.id).await.unwrap());
+        assert!(matches!(
+            group_of_other_tenant,
+            Err(StoreError::GroupNotFound)
+        ));
+        assert!(matches!(
+            user_of_other_tenant,
+            Err(StoreError::UserNotFound)
+        ));
+        assert_eq!(
+            Some(jane),
+            store
+                .tenant(&TenantId::parse("acme").unwrap())
+                .get_user("jane")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn new_users_are_populated() {
+        let store = MemoryTenantStore::default();
+
+        let user = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+
+        assert_eq!("Jane", user.username);
+        assert_eq!(1, user.version);
+        assert_eq!(UserStatus::Active, user.status);
+        assert_eq!(user.created_at, user.updated_at);
+        assert!(!user.id.is_nil());
+    }
+
+    #[tokio::test]
+    async fn updates_check_the_expected_version() {
+        let store = MemoryTenantStore::default();
+        let _ = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        let update = UserUpdate {
+            name: Some("Jane Doe".to_string()),
+            email: None,
+            attributes: None,
+        };
+
+        let updated = store
+            .update_user("jane", Some(1), update.clone())
+            .await
+            .unwrap();
+        let stale = store.update_user("jane", Some(1), update.clone()).await;
+        let missing = store.update_user("john", None, update).await;
+
+        assert_eq!(2, updated.version);
+        assert_eq!("Jane Doe", updated.name);
+        assert!(matches!(
+            stale,
+            Err(StoreError::VersionConflict { current: 2 })
+        ));
+        assert!(matches!(missing, Err(StoreError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn old_usernames_are_kept_until_they_expire() {
+        let store = MemoryTenantStore::default();
+        let now = SystemTime::now();
+        let until = now + Duration::from_secs(60);
+        let jane = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        let _ = store
+            .create_user(new_user("John"), keys("john", "john"))
+            .await
+            .unwrap();
+
+        let taken = store
+            .rename_user("jane", None, "John".into(), keys("john", "john"), until)
+            .await;
+        let renamed = store
+            .rename_user(
+                "jane",
+                Some(1),
+                "Janet".into(),
+                keys("janet", "janet"),
+                until,
+            )
+            .await
+            .unwrap();
+        let reuse = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await;
+
+        assert!(matches!(taken, Err(StoreError::UsernameTaken)));
+        assert!(matches!(reuse, Err(StoreError::UsernameTaken)));
+        assert_eq!(jane.id, renamed.id);
+        assert_eq!(("Janet", 2), (renamed.username.as_str(), renamed.version));
+        assert_eq!(None, store.get_user("jane").await.unwrap());
+        assert_eq!(
+            Some(renamed.clone()),
+            store.get_user("janet").await.unwrap()
+        );
+        assert_eq!(
+            Some(renamed.clone()),
+            store.get_user_by_id(jane.id).await.unwrap()
+        );
+        assert_eq!(
+            Some(renamed),
+            store.get_renamed_user("jane", now).await.unwrap()
+        );
+        assert_eq!(None, store.get_renamed_user("jane", until).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_usernames_are_released() {
+        let store = MemoryTenantStore::default();
+        let _ = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+
+        let _ = store
+            .rename_user(
+                "jane",
+                None,
+                "Janet".into(),
+                keys("janet", "janet"),
+                UNIX_EPOCH,
+            )
+            .await
+            .unwrap();
+        let reuse = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await;
+
+        assert!(reuse.is_ok());
+    }
+
+    #[tokio::test]
+    async fn users_can_be_renamed_back() {
+        let store = MemoryTenantStore::default();
+        let until = SystemTime::now() + Duration::from_secs(60);
+        let _ = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+
+        let _ = store
+            .rename_user("jane", None, "Janet".into(), keys("janet", "janet"), until)
+            .await
+            .unwrap();
+        let back = store
+            .rename_user("janet", None, "JANE".into(), keys("jane", "jane"), until)
+            .await
+            .unwrap();
+
+        assert_eq!("JANE", back.username);
+        assert_eq!(
+            None,
+            store
+                .get_renamed_user("jane", SystemTime::now())
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(back),
+            store
+                .get_renamed_user("janet", SystemTime::now())
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn status_changes_follow_the_state_machine() {
+        let store = MemoryTenantStore::default();
+        let _ = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        let change = |status| {
+            store.change_status(
+                "jane",
+                None,
+                status,
+                "support ticket".to_string(),
+                "admin".to_string(),
+            )
+        };
+
+        let suspended = change(UserStatus::Suspended).await.unwrap();
+        let locked = change(UserStatus::Locked).await;
+        let active = change(UserStatus::Active).await.unwrap();
+        let transitions = store.status_transitions("jane").await.unwrap();
+
+        assert_eq!(UserStatus::Suspended, suspended.status);
+        assert!(matches!(
+            locked,
+            Err(StoreError::InvalidTransition {
+                from: UserStatus::Suspended,
+                to: UserStatus::Locked
+            })
+        ));
+        assert_eq!((UserStatus::Active, 3), (active.status, active.version));
+        assert_eq!(2, transitions.len());
+        assert_eq!(
+            (UserStatus::Active, UserStatus::Suspended, "admin"),
+            (
+                transitions[0].from,
+                transitions[0].to,
+                transitions[0].actor.as_str()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn users_are_locked_after_failed_logins() {
+        let store = MemoryTenantStore::default();
+        let _ = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+
+        let first = store.record_failed_login("jane", 2).await.unwrap();
+        let second = store.record_failed_login("jane", 2).await.unwrap();
+        let _ = store
+            .change_status(
+                "jane",
+                None,
+                UserStatus::Active,
+                "verified".to_string(),
+                "admin".to_string(),
+            )
+            .await
+            .unwrap();
+        let after_reactivation = store.record_failed_login("jane", 2).await.unwrap();
+        let transitions = store.status_transitions("jane").await.unwrap();
+
+        assert_eq!(UserStatus::Active, first.status);
+        assert_eq!(UserStatus::Locked, second.status);
+        assert_eq!(UserStatus::Active, after_reactivation.status);
+        assert_eq!("system", transitions[0].actor);
+    }
+
+    fn new_group(name: &str, roles: Vec<Role>) -> NewGroup {
+        NewGroup {
+            name: name.to_string(),
+            description: None,
+            roles,
+        }
+    }
+
+    #[tokio::test]
+    async fn group_members_are_paged_and_grant_roles() {
+        let store = MemoryTenantStore::default();
+        let mut ids = Vec::new();
+        for username in ["ann", "bob", "cat"] {
+            let user = store
+                .create_user(new_user(username), keys(username, username))
+                .await
+                .unwrap();
+            ids.push(user.id);
+        }
+        let _ = store
+            .create_group(new_group("admins", vec![Role::Admin]))
+            .await
+            .unwrap();
+        let _ = store
+            .create_group(new_group("support", vec![Role::UserAdmin]))
+            .await
+            .unwrap();
+        for id in &ids {
+            assert!(store.add_member("support", *id).await.unwrap());
+        }
+        assert!(!store.add_member("support", ids[0]).await.unwrap());
+        assert!(store.add_member("admins", ids[0]).await.unwrap());
+
+        let first = store.group_members("support", None, 2).await.unwrap();
+        let second = store
+            .group_members("support", Some(first[1].id), 2)
+            .await
+            .unwrap();
+        assert!(store.remove_member("support", ids[1]).await.unwrap());
+        assert!(!store.remove_member("support", ids[1]).await.unwrap());
+        let remaining = store.group_members("support", None, 10).await.unwrap();
+
+        // Members are ordered by id, which is not necessarily the order in
+        // which the users were created.
+        let mut paged = usernames(&first);
+        paged.extend(usernames(&second));
+        paged.sort();
+        let mut remaining = usernames(&remaining);
+        remaining.sort();
+
+        assert_eq!((2, 1), (first.len(), second.len()));
+        assert_eq!(vec!["ann", "bob", "cat"], paged);
+        assert_eq!(vec!["ann", "cat"], remaining);
+        assert_eq!(
+            vec![Role::Admin, Role::UserAdmin],
+            store.user_roles(ids[0]).await.unwrap()
+        );
+        assert!(matches!(
+            store.add_member("support", Uuid::nil()).await,
+            Err(StoreError::UserNotFound)
+        ));
+        assert!(matches!(
+            store.add_member("missing", ids[0]).await,
+            Err(StoreError::GroupNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn deleting_a_group_removes_its_memberships() {
+        let store = MemoryTenantStore::default();
+        let user = store
+            .create_user(new_user("Jane"), keys("jane", "jane"))
+            .await
+            .unwrap();
+        let group = store
+            .create_group(new_group("support", vec![Role::UserAdmin]))
+            .await
+            .unwrap();
+        store.add_member("support", user.id).await.unwrap();
+
+        let taken = store.create_group(new_group("support", vec![])).await;
+        let stale = store.delete_group("support", Some(group.version + 1)).await;
+        store
+            .delete_group("support", Some(group.version))
+            .await
+            .unwrap();
+
+        assert!(matches!(taken, Err(StoreError::GroupNameTaken)));
+        assert!(matches!(
+            stale,
+            Err(StoreError::VersionConflict { current: 1 })
+        ));
+        assert_eq!(None, store.get_group("support").await.unwrap());
+        assert!(store.user_roles(user.id).await.unwrap().is_empty());
+        assert!(store
+            .user_groups(user.id, None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    fn usernames(members: &[GroupMember]) -> Vec<&str> {
+        members
+            .iter()
+            .map(|member| member.username.as_str())
+            .collect()
+    }
+
+    fn audit_event(actor: &str, action: AuditAction, at: SystemTime) -> AuditEvent {
+        AuditEvent {
+            id: Uuid::now_v7(),
+            at,
+            actor: actor.to_string(),
+            action,
+            target: Some("jane".to_string()),
+            target_id: None,
+            changes: BTreeMap::new(),
+            reason: None,
+            request_id: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn audit_events_are_filtered_and_paged_in_order() {
+        let store = MemoryTenantStore::default();
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        for event in [
+            audit_event("ci", AuditAction::UserCreated, at(10)),
+            audit_event("ci", AuditAction::UserUpdated, at(20)),
+            audit_event("jane", AuditAction::AuthDenied, at(30)),
+            audit_event("ci", AuditAction::UserUpdated, at(40)),
+        ] {
+            store.append_audit_event(event).await.unwrap();
+        }
+
+        let all = AuditFilter::default();
+        let events = store.audit_events(&all, None, 10).await.unwrap();
+        let first = store.audit_events(&all, None, 3).await.unwrap();
+        let rest = store
+            .audit_events(&all, Some(first[2].id), 3)
+            .await
+            .unwrap();
+        let updates = AuditFilter {
+            actor: Some("ci".to_string()),
+            action: Some(AuditAction::UserUpdated),
+            ..Default::default()
+        };
+        let window = AuditFilter {
+            since: Some(at(20)),
+            until: Some(at(40)),
+            ..Default::default()
+        };
+
+        assert_eq!(4, events.len());
+        assert_eq!(Ok(()), audit::verify_chain(&events));
+        assert_eq!(
+            events.last(),
+            store.last_audit_event().await.unwrap().as_ref()
+        );
+        assert_eq!(events[..3], first);
+        assert_eq!(events[3..], rest);
+        assert_eq!(
+            vec![events[1].clone(), events[3].clone()],
+            store.audit_events(&updates, None, 10).await.unwrap()
+        );
+        assert_eq!(
+            events[1..3],
+            store.audit_events(&window, None, 10).await.unwrap()
+        );
+    }
+}
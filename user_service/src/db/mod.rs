@@ -0,0 +1,253 @@
+use crate::models::{
+    AuditCheckpoint, AuditEvent, AuditFilter, Group, GroupMember, GroupUpdate, NewGroup, NewUser,
+    Role, StatusTransition, User, UserFilter, UserStatus, UserUpdate,
+};
+use crate::tenant::TenantId;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::SystemTime;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub mod memory;
+
+/// Storage backend for the user service.
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Check whether the store can serve requests: it is reachable and all
+    /// migrations have been applied.
+    async fn check_ready(&self) -> Result<(), StoreError>;
+
+    /// Flush any pending writes and release the connections of the store.
+    /// This is called once, when the server shuts down.
+    async fn close(&self) -> Result<(), StoreError>;
+
+    /// The users and groups of a tenant. Tenants share nothing: the returned
+    /// store only ever reads and changes the data of `tenant`, so there is no
+    /// way to reach the data of another tenant through it. Usernames and
+    /// group names only have to be unique within a tenant.
+    fn tenant(&self, tenant: &TenantId) -> Arc<dyn TenantStore>;
+
+    /// The tenants that have any data in the store.
+    async fn tenants(&self) -> Result<Vec<TenantId>, StoreError>;
+}
+
+/// The part of a [`Store`] that belongs to a single tenant.
+#[async_trait]
+pub trait TenantStore: Send + Sync {
+    /// Store a new user, assigning its id, timestamps and first version.
+    /// Fails with [`StoreError::UsernameTaken`] if another user has the same
+    /// unique key.
+    async fn create_user(&self, new_user: NewUser, keys: UsernameKeys) -> Result<User, StoreError>;
+
+    /// Find the user with the given lookup key.
+    async fn get_user(&self, lookup_key: &str) -> Result<Option<User>, StoreError>;
+
+    /// Find the user with the given id.
+    async fn get_user_by_id(&self, id: Uuid) -> Result<Option<User>, StoreError>;
+
+    /// Up to `limit` users that match `filter`, ordered by id, starting after
+    /// the user with the id `after`.
+    async fn list_users(
+        &self,
+        filter: &UserFilter,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<User>, StoreError>;
+
+    /// Find the user that was renamed away from the given lookup key, as long
+    /// as the old username is still kept for it.
+    async fn get_renamed_user(
+        &self,
+        lookup_key: &str,
+        now: SystemTime,
+    ) -> Result<Option<User>, StoreError>;
+
+    /// Apply an update to the user with the given lookup key, incrementing
+    /// its version. If `expected_version` is set, the update is only applied
+    /// if the user still has that version, otherwise it fails with
+    /// [`StoreError::VersionConflict`].
+    async fn update_user(
+        &self,
+        lookup_key: &str,
+        expected_version: Option<u64>,
+        update: UserUpdate,
+    ) -> Result<User, StoreError>;
+
+    /// Change the username of the user with the given lookup key, checking
+    /// `expected_version` like [`TenantStore::update_user`]. The old
+    /// username stays reserved for the user until `keep_old_until`, so
+    /// requests for it can be redirected and nobody else can take it over in
+    /// the meantime. Fails with [`StoreError::UsernameTaken`] if another user
+    /// has, or recently had, the same unique key.
+    async fn rename_user(
+        &self,
+        lookup_key: &str,
+        expected_version: Option<u64>,
+        username: String,
+        keys: UsernameKeys,
+        keep_old_until: SystemTime,
+    ) -> Result<User, StoreError>;
+
+    /// Change the status of the user with the given lookup key, checking
+    /// `expected_version` like [`TenantStore::update_user`], and record the
+    /// transition. Fails with [`StoreError::InvalidTransition`] if the user
+    /// cannot change from its current status to `status`. Reactivating a
+    /// user resets its failed logins.
+    async fn change_status(
+        &self,
+        lookup_key: &str,
+        expected_version: Option<u64>,
+        status: UserStatus,
+        reason: String,
+        actor: String,
+    ) -> Result<User, StoreError>;
+
+    /// The status transitions of the user with the given lookup key, oldest
+    /// first.
+    async fn status_transitions(
+        &self,
+        lookup_key: &str,
+    ) -> Result<Vec<StatusTransition>, StoreError>;
+
+    /// Count a failed login of the user with the given lookup key. An active
+    /// user that reaches `max_failed_logins` failed logins in a row is
+    /// locked; zero never locks users.
+    async fn record_failed_login(
+        &self,
+        lookup_key: &str,
+        max_failed_logins: u32,
+    ) -> Result<User, StoreError>;
+
+    /// Store a new group, assigning its id, timestamps and first version.
+    /// Fails with [`StoreError::GroupNameTaken`] if a group with the same
+    /// name exists.
+    async fn create_group(&self, new_group: NewGroup) -> Result<Group, StoreError>;
+
+    /// Find the group with the given name.
+    async fn get_group(&self, name: &str) -> Result<Option<Group>, StoreError>;
+
+    /// Up to `limit` groups ordered by name, starting after the group named
+    /// `after`.
+    async fn list_groups(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Group>, StoreError>;
+
+    /// Apply an update to the group with the given name, checking
+    /// `expected_version` like [`TenantStore::update_user`].
+    async fn update_group(
+        &self,
+        name: &str,
+        expected_version: Option<u64>,
+        update: GroupUpdate,
+    ) -> Result<Group, StoreError>;
+
+    /// Delete the group with the given name and all its memberships,
+    /// checking `expected_version` like [`TenantStore::update_user`].
+    async fn delete_group(
+        &self,
+        name: &str,
+        expected_version: Option<u64>,
+    ) -> Result<(), StoreError>;
+
+    /// Add the user with the given id to a group, returning whether it was
+    /// not a member yet. Adding a member again changes nothing.
+    async fn add_member(&self, group: &str, user_id: Uuid) -> Result<bool, StoreError>;
+
+    /// Remove the user with the given id from a group, returning whether it
+    /// was a member. Removing a user that is not a member changes nothing.
+    async fn remove_member(&self, group: &str, user_id: Uuid) -> Result<bool, StoreError>;
+
+    /// Up to `limit` members of a group ordered by id, starting after the
+    /// member with the id `after`.
+    async fn group_members(
+        &self,
+        group: &str,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<GroupMember>, StoreError>;
+
+    /// Up to `limit` groups of the user with the given id, ordered by name
+    /// like [`TenantStore::list_groups`].
+    async fn user_groups(
+        &self,
+        user_id: Uuid,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Group>, StoreError>;
+
+    /// The roles the groups of the user with the given id grant it.
+    async fn user_roles(&self, user_id: Uuid) -> Result<Vec<Role>, StoreError>;
+
+    /// Append an event to the audit log, linking it to the last event with
+    /// [`audit::chain`](crate::audit::chain). Appending has to be serialized,
+    /// so every event follows exactly one other event. There is no way to
+    /// change or remove an event once it was appended.
+    async fn append_audit_event(&self, event: AuditEvent) -> Result<(), StoreError>;
+
+    /// The event that was appended to the audit log last.
+    async fn last_audit_event(&self) -> Result<Option<AuditEvent>, StoreError>;
+
+    /// Up to `limit` events of the audit log that match `filter`, in the
+    /// order they were appended, starting after the event with the id
+    /// `after`.
+    async fn audit_events(
+        &self,
+        filter: &AuditFilter,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<AuditEvent>, StoreError>;
+
+    /// Store a signed checkpoint of the audit log.
+    async fn append_audit_checkpoint(&self, checkpoint: AuditCheckpoint) -> Result<(), StoreError>;
+
+    /// All checkpoints of the audit log, oldest first.
+    async fn audit_checkpoints(&self) -> Result<Vec<AuditCheckpoint>, StoreError>;
+}
+
+/// The keys a user is stored under, derived from the username by the
+/// [`Validator`](crate::validation::Validator).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsernameKeys {
+    /// Key to find the user by its username: the canonical form of the
+    /// username, so every equivalent spelling finds the user.
+    pub lookup: String,
+
+    /// Key that has to be unique among all users. Usernames that are
+    /// confusable with each other have the same unique key.
+    pub unique: String,
+}
+
+// The in-memory store is always ready, only persistent stores construct these.
+#[allow(dead_code)]
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("store is unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("store has pending migrations")]
+    MigrationsPending,
+
+    #[error("username is taken")]
+    UsernameTaken,
+
+    #[error("user was not found")]
+    UserNotFound,
+
+    #[error("group name is taken")]
+    GroupNameTaken,
+
+    #[error("group was not found")]
+    GroupNotFound,
+
+    /// The user or group was changed concurrently, it now has a different
+    /// version.
+    #[error("version is {current}")]
+    VersionConflict { current: u64 },
+
+    /// The user cannot change from its current status to the requested one.
+    #[error("user cannot change from {from} to {to}")]
+    InvalidTransition { from: UserStatus, to: UserStatus },
+}
@@ -0,0 +1,241 @@
+use crate::models::JsonBodyError;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::FromRequest;
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Request, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_ignored::Path;
+
+/// How JSON request bodies are parsed, stored in the request extensions by
+/// the router.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonConfig {
+    /// Maximum size of a request body, only used to report the limit. The
+    /// limit itself is enforced by `DefaultBodyLimit`.
+    pub max_body_size: usize,
+
+    /// Reject bodies with fields that are not part of the expected type.
+    pub deny_unknown_fields: bool,
+}
+
+/// Extracts a JSON request body like `axum::Json`, but rejects invalid bodies
+/// with a [`JsonBodyError`] instead of a plain text message, so clients get
+/// the same error format as for every other error.
+#[derive(Debug)]
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: axum::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<axum::BoxError>,
+{
+    type Rejection = JsonBodyError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err(JsonBodyError::UnsupportedMediaType);
+        }
+
+        let config = req
+            .extensions()
+            .get::<JsonConfig>()
+            .copied()
+            .unwrap_or_default();
+
+        let body = Bytes::from_request(req, state).await.map_err(|rejection| {
+            match rejection.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => JsonBodyError::PayloadTooLarge {
+                    limit: config.max_body_size,
+                },
+                _ => JsonBodyError::UnreadableBody,
+            }
+        })?;
+
+        parse(&body, config.deny_unknown_fields).map(JsonBody)
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+/// Parse a JSON body, reporting the path of the field that caused an error.
+fn parse<T: DeserializeOwned>(body: &[u8], deny_unknown_fields: bool) -> Result<T, JsonBodyError> {
+    // Serde skips fields the type does not know, they are collected here.
+    let mut ignored = Vec::new();
+    let mut record_ignored = |path: Path| ignored.push(field_path(&path));
+
+    let deserializer = &mut serde_json::Deserializer::from_slice(body);
+    let deserializer = serde_ignored::Deserializer::new(deserializer, &mut record_ignored);
+    let value: T = serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        classify(&path, err.into_inner())
+    })?;
+
+    if deny_unknown_fields {
+        if let Some(path) = ignored.into_iter().next() {
+            return Err(JsonBodyError::UnknownField { path });
+        }
+    }
+
+    Ok(value)
+}
+
+fn classify(path: &str, err: serde_json::Error) -> JsonBodyError {
+    if !err.is_data() {
+        return JsonBodyError::MalformedJson {
+            message: err.to_string(),
+        };
+    }
+
+    // The position is not useful for data errors, the path is reported
+    // instead.
+    let message = err.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+
+    let field = |prefix: &str| {
+        message
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.split('`').next())
+            .map(|field| join(path, field))
+    };
+
+    if let Some(path) = field("missing field `") {
+        JsonBodyError::MissingField { path }
+    } else if let Some(path) = field("unknown field `") {
+        JsonBodyError::UnknownField { path }
+    } else {
+        JsonBodyError::InvalidValue {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Format a path reported by `serde_ignored` like the paths of the other
+/// errors, such as `members[0].admin`.
+fn field_path(path: &Path) -> String {
+    match path {
+        Path::Root => ".".to_string(),
+        Path::Seq { parent, index } => {
+            format!("{}[{index}]", field_path(parent).trim_end_matches('.'))
+        }
+        Path::Map { parent, key } => join(&field_path(parent), key),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => field_path(parent),
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path == "." {
+        field.to_string()
+    } else {
+        format!("{path}.{field}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{NewUser, UserUpdate};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Team {
+        members: Vec<NewUser>,
+    }
+
+    #[test]
+    fn parse_errors_report_the_field() {
+        let tests = vec![
+            (
+                r#"{"username": "jane""#,
+                JsonBodyError::MalformedJson {
+                    message: "EOF while parsing an object at line 1 column 19".to_string(),
+                },
+            ),
+            (
+                r#"{"members": [{"username": "jane"}]}"#,
+                JsonBodyError::MissingField {
+                    path: "members[0].name".to_string(),
+                },
+            ),
+            (
+                r#"{"members": [{"username": 1, "name": "Jane"}]}"#,
+                JsonBodyError::InvalidValue {
+                    path: "members[0].username".to_string(),
+                    message: "invalid type: integer `1`, expected a string".to_string(),
+                },
+            ),
+        ];
+
+        for (body, expected) in tests {
+            let err = parse::<Team>(body.as_bytes(), false).unwrap_err();
+
+            assert_eq!(expected, err, "{body}");
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_optionally_rejected() {
+        let body = br#"{"members": [{"username": "jane", "name": "Jane", "admin": true}]}"#;
+
+        let team = parse::<Team>(body, false).unwrap();
+
+        assert_eq!("jane", team.members[0].username);
+        assert_eq!(
+            JsonBodyError::UnknownField {
+                path: "members[0].admin".to_string()
+            },
+            parse::<Team>(body, true).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn null_optional_fields_are_not_unknown() {
+        let body = br#"{"name": null, "email": null, "attributes": null}"#;
+
+        let update = parse::<UserUpdate>(body, true).unwrap();
+
+        assert_eq!(None, update.name);
+        assert_eq!(Some(None), update.email);
+    }
+
+    #[test]
+    fn json_content_types() {
+        let tests = vec![
+            (Some("application/json"), true),
+            (Some("application/json; charset=utf-8"), true),
+            (Some("application/merge-patch+json"), true),
+            (Some("text/plain"), false),
+            (None, false),
+        ];
+
+        for (content_type, expected) in tests {
+            let mut headers = HeaderMap::new();
+            if let Some(content_type) = content_type {
+                headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+            }
+
+            assert_eq!(expected, is_json(&headers), "{content_type:?}");
+        }
+    }
+}
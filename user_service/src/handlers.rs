@@ -1,94 +1,1079 @@
+use crate::audit;
+use crate::auth::Principal;
+use crate::db::StoreError;
+use crate::extract::JsonBody;
+use crate::middleware::auth::{group_roles, ClientIp};
 use crate::models::{
-    self, CreateUserError, GetUserError, HandlerError, InvalidNameReason, InvalidUsernameReason,
-    User,
+    self, AuditAction, AuditCheckpoint, AuditError, AuditEvent, AuditFilter, ChangeStatusError,
+    CreateUserError, FieldChange, GetUserError, Group, GroupError, GroupMember, GroupUpdate,
+    HandlerError, ListUsersError, NewGroup, Page, ReadinessError, RenameUserError, Role,
+    StatusChange, StatusTransition, UpdateUserError, User, UserFilter, UserUpdate, UsernameChange,
 };
-use axum::extract::Path;
-use axum::Json;
-use opentelemetry::trace::TraceContextExt;
-use tracing::{debug, instrument};
-use tracing::{error, info, instrument, Span};
-use tracing_opentelemetry::OpenTelemetrySpanExt;
-
-#[instrument(err)]
+use crate::precondition::{etag, if_match, if_none_match, user_etag};
+use crate::state::AppState;
+use crate::tenant::Tenant;
+use axum::body::StreamBody;
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Extension, Json};
+use futures_util::stream;
+use http::header::{CONTENT_TYPE, ETAG, IF_MATCH};
+use http::{HeaderMap, HeaderName, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::SystemTime;
+use tracing::{debug, error, instrument};
+use uuid::Uuid;
+
+/// Characters that are escaped in a path segment, all but the unreserved
+/// characters of RFC 3986.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Media type of newline-delimited JSON, which the audit log is exported in.
+const NDJSON: &str = "application/x-ndjson";
+
+/// Number of items on a page of a list, unless the client asks for another
+/// number.
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Maximum number of items on a page of a list.
+const MAX_PAGE_SIZE: usize = 200;
+
+/// A user, with its entity tag in the `ETag` header.
+type TaggedUser = ([(HeaderName, String); 1], Json<User>);
+
+fn tagged(user: User) -> TaggedUser {
+    ([(ETAG, user_etag(&user))], Json(user))
+}
+
+/// Get a user. If the `If-None-Match` header lists the current entity tag of
+/// the user, only 304 Not Modified is returned. Requests for the old username
+/// of a renamed user are redirected to the new one.
+#[instrument(err, skip(state, tenant, headers))]
 pub async fn get_user(
+    State(state): State<AppState>,
+    tenant: Tenant,
     Path(username): Path<String>,
-) -> Result<Json<User>, HandlerError<GetUserError>> {
-    check_auth(&username)?;
+    headers: HeaderMap,
+) -> Result<Response, HandlerError<GetUserError>> {
+    let keys = state.settings.load().validator.username_keys(&username);
+    let user = match tenant.store.get_user(&keys.lookup).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return match redirect_renamed(&tenant, &keys.lookup, "").await? {
+                Some(redirect) => Ok(redirect),
+                None => Err(HandlerError::service_error(GetUserError::UserNotFound {
+                    username,
+                })),
+            }
+        }
+        Err(err) => return Err(store_error(err)),
+    };
 
-    if username == "not_found" {
-        return Err(HandlerError::service_error(GetUserError::UserNotFound {
-            username,
-        }));
+    Ok(conditional(user, &headers))
+}
+
+/// Get a user by its id, which never changes, unlike its username.
+#[instrument(err, skip(tenant, headers))]
+pub async fn get_user_by_id(
+    tenant: Tenant,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, HandlerError<GetUserError>> {
+    match tenant.store.get_user_by_id(id).await {
+        Ok(Some(user)) => Ok(conditional(user, &headers)),
+        Ok(None) => Err(HandlerError::service_error(GetUserError::UserIdNotFound {
+            id,
+        })),
+        Err(err) => Err(store_error(err)),
     }
+}
 
-    Ok(Json(models::User {
-        username,
-        name: "John Doe".to_string(),
-    }))
+/// One page of the users, ordered by id, with only the users whose
+/// attributes match the `attributes.<name>` parameters of the query.
+#[instrument(err, skip(tenant))]
+pub async fn list_users(
+    tenant: Tenant,
+    Query(parameters): Query<Vec<(String, String)>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<User>>, HandlerError<ListUsersError>> {
+    let invalid_page = |reason| HandlerError::service_error(ListUsersError::InvalidPage { reason });
+    let limit = query.limit().map_err(invalid_page)?;
+    let after = query.id_cursor().map_err(invalid_page)?;
+    let filter = UserFilter::from_query(&parameters)
+        .map_err(|reason| HandlerError::service_error(ListUsersError::InvalidFilter { reason }))?;
+
+    let users = tenant
+        .store
+        .list_users(&filter, after, limit + 1)
+        .await
+        .map_err(store_error)?;
+
+    Ok(Json(page(users, limit, |user| {
+        user.id.simple().to_string()
+    })))
+}
+
+/// The user, or only 304 Not Modified if the `If-None-Match` header lists
+/// its current entity tag.
+fn conditional(user: User, headers: &HeaderMap) -> Response {
+    let etag = user_etag(&user);
+    if if_none_match(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+
+    tagged(user).into_response()
 }
 
-#[instrument(err)]
+#[instrument(err, skip(state, tenant))]
 pub async fn create_user(
-    Json(new_user): Json<models::NewUser>,
-) -> Result<Json<models::User>, HandlerError<CreateUserError>> {
+    State(state): State<AppState>,
+    tenant: Tenant,
+    principal: Option<Extension<Principal>>,
+    JsonBody(new_user): JsonBody<models::NewUser>,
+) -> Result<TaggedUser, HandlerError<CreateUserError>> {
     debug!("creating user: {:?}", new_user);
 
-    let trace_id = Span::current().context().span().span_context().trace_id();
-    error!(trace_id = %trace_id, "creating user");
+    let keys = {
+        let settings = state.settings.load();
+        settings
+            .validator
+            .validate_new_user(&new_user)
+            .map_err(|violations| {
+                HandlerError::service_error(CreateUserError::InvalidNewUser { violations })
+            })?;
+        settings.validator.username_keys(&new_user.username)
+    };
 
-    if new_user.username.is_empty() {
+    match tenant.store.create_user(new_user, keys).await {
+        Ok(user) => {
+            let event = user_event(AuditAction::UserCreated, principal, None, &user);
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged(user))
+        }
+        Err(StoreError::UsernameTaken) => Err(HandlerError::service_error(
+            CreateUserError::UsernameAlreadyExists,
+        )),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Change the fields of a user. If the request has an `If-Match` header, the
+/// user is only changed if the header matches its current entity tag, so
+/// concurrent changes are not lost.
+#[instrument(err, skip(state, tenant, headers))]
+pub async fn update_user(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(username): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    JsonBody(update): JsonBody<UserUpdate>,
+) -> Result<Response, HandlerError<UpdateUserError>> {
+    let not_found = || {
+        HandlerError::service_error(UpdateUserError::UserNotFound {
+            username: username.clone(),
+        })
+    };
+
+    let settings = state.settings.load_full();
+    let keys = settings.validator.username_keys(&username);
+    let current = match tenant.store.get_user(&keys.lookup).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return redirect_renamed(&tenant, &keys.lookup, "")
+                .await?
+                .ok_or_else(not_found)
+        }
+        Err(err) => return Err(store_error(err)),
+    };
+
+    let current_etag = user_etag(&current);
+    if !if_match(&headers, &current_etag) {
         return Err(HandlerError::service_error(
-            CreateUserError::InvalidUsername(InvalidUsernameReason::TooShort),
+            UpdateUserError::PreconditionFailed { etag: current_etag },
         ));
-    } else if new_user.username.len() > 20 {
+    }
+
+    settings
+        .validator
+        .validate_user_update(&update)
+        .map_err(|violations| {
+            HandlerError::service_error(UpdateUserError::InvalidUserUpdate { violations })
+        })?;
+
+    // Without `If-Match` the update is applied to whichever version is
+    // current, with it the store makes sure nothing changed since the check.
+    let expected_version = headers.contains_key(IF_MATCH).then_some(current.version);
+    match tenant
+        .store
+        .update_user(&keys.lookup, expected_version, update)
+        .await
+    {
+        Ok(user) => {
+            let event = user_event(AuditAction::UserUpdated, principal, Some(&current), &user);
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged(user).into_response())
+        }
+        Err(StoreError::UserNotFound) => Err(not_found()),
+        Err(StoreError::VersionConflict { current: version }) => Err(HandlerError::service_error(
+            UpdateUserError::PreconditionFailed {
+                etag: etag(current.id, version),
+            },
+        )),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Change the username of a user. The user keeps its id, and requests for
+/// the old username are redirected to the new one for the configured grace
+/// period. Supports `If-Match` like [`update_user`].
+#[instrument(err, skip(state, tenant, headers))]
+pub async fn rename_user(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(username): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    JsonBody(change): JsonBody<UsernameChange>,
+) -> Result<Response, HandlerError<RenameUserError>> {
+    let not_found = || {
+        HandlerError::service_error(RenameUserError::UserNotFound {
+            username: username.clone(),
+        })
+    };
+
+    let settings = state.settings.load_full();
+    let keys = settings.validator.username_keys(&username);
+    let current = match tenant.store.get_user(&keys.lookup).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return redirect_renamed(&tenant, &keys.lookup, "/rename")
+                .await?
+                .ok_or_else(not_found)
+        }
+        Err(err) => return Err(store_error(err)),
+    };
+
+    let current_etag = user_etag(&current);
+    if !if_match(&headers, &current_etag) {
         return Err(HandlerError::service_error(
-            CreateUserError::InvalidUsername(InvalidUsernameReason::TooLong),
+            RenameUserError::PreconditionFailed { etag: current_etag },
         ));
-    } else if new_user.username == "invalid" {
+    }
+
+    settings
+        .validator
+        .validate_username_change(&change)
+        .map_err(|violations| {
+            HandlerError::service_error(RenameUserError::InvalidUsername { violations })
+        })?;
+
+    let new_keys = settings.validator.username_keys(&change.username);
+    let keep_old_until = SystemTime::now() + settings.rename_grace_period;
+    let expected_version = headers.contains_key(IF_MATCH).then_some(current.version);
+    match tenant
+        .store
+        .rename_user(
+            &keys.lookup,
+            expected_version,
+            change.username,
+            new_keys,
+            keep_old_until,
+        )
+        .await
+    {
+        Ok(user) => {
+            let event = user_event(AuditAction::UserRenamed, principal, Some(&current), &user);
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged(user).into_response())
+        }
+        Err(StoreError::UserNotFound) => Err(not_found()),
+        Err(StoreError::UsernameTaken) => Err(HandlerError::service_error(
+            RenameUserError::UsernameAlreadyExists,
+        )),
+        Err(StoreError::VersionConflict { current: version }) => Err(HandlerError::service_error(
+            RenameUserError::PreconditionFailed {
+                etag: etag(current.id, version),
+            },
+        )),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Change the status of a user, like suspending or reactivating it. The
+/// transition is recorded with its reason and the principal that made it.
+/// Supports `If-Match` like [`update_user`].
+#[instrument(err, skip(state, tenant, headers))]
+pub async fn change_user_status(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(username): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    JsonBody(change): JsonBody<StatusChange>,
+) -> Result<Response, HandlerError<ChangeStatusError>> {
+    let not_found = || {
+        HandlerError::service_error(ChangeStatusError::UserNotFound {
+            username: username.clone(),
+        })
+    };
+
+    let settings = state.settings.load_full();
+    let keys = settings.validator.username_keys(&username);
+    let current = match tenant.store.get_user(&keys.lookup).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return redirect_renamed(&tenant, &keys.lookup, "/status")
+                .await?
+                .ok_or_else(not_found)
+        }
+        Err(err) => return Err(store_error(err)),
+    };
+
+    let current_etag = user_etag(&current);
+    if !if_match(&headers, &current_etag) {
         return Err(HandlerError::service_error(
-            CreateUserError::InvalidUsername(InvalidUsernameReason::InvalidCharacters),
+            ChangeStatusError::PreconditionFailed { etag: current_etag },
         ));
-    } else if new_user.username == "taken" {
+    }
+
+    settings
+        .validator
+        .validate_status_change(&change)
+        .map_err(|violations| {
+            HandlerError::service_error(ChangeStatusError::InvalidStatusChange { violations })
+        })?;
+
+    let actor = audit::actor(principal.as_deref());
+    let expected_version = headers.contains_key(IF_MATCH).then_some(current.version);
+    match tenant
+        .store
+        .change_status(
+            &keys.lookup,
+            expected_version,
+            change.status,
+            change.reason.clone(),
+            actor,
+        )
+        .await
+    {
+        Ok(user) => {
+            let event = AuditEvent {
+                reason: Some(change.reason),
+                ..user_event(
+                    AuditAction::UserStatusChanged,
+                    principal,
+                    Some(&current),
+                    &user,
+                )
+            };
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged(user).into_response())
+        }
+        Err(StoreError::UserNotFound) => Err(not_found()),
+        Err(StoreError::InvalidTransition { from, to }) => Err(HandlerError::service_error(
+            ChangeStatusError::InvalidTransition { from, to },
+        )),
+        Err(StoreError::VersionConflict { current: version }) => Err(HandlerError::service_error(
+            ChangeStatusError::PreconditionFailed {
+                etag: etag(current.id, version),
+            },
+        )),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// The status transitions of a user, oldest first.
+#[instrument(err, skip(state, tenant))]
+pub async fn get_status_transitions(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(username): Path<String>,
+) -> Result<Response, HandlerError<GetUserError>> {
+    let keys = state.settings.load().validator.username_keys(&username);
+    match tenant.store.status_transitions(&keys.lookup).await {
+        Ok(transitions) => Ok(Json::<Vec<StatusTransition>>(transitions).into_response()),
+        Err(StoreError::UserNotFound) => {
+            redirect_renamed(&tenant, &keys.lookup, "/status-transitions")
+                .await?
+                .ok_or_else(|| HandlerError::service_error(GetUserError::UserNotFound { username }))
+        }
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Record a failed login of a user, reported by whatever handles logins.
+/// After too many failed logins in a row the user is locked.
+#[instrument(err, skip(state, tenant))]
+pub async fn record_failed_login(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(username): Path<String>,
+    principal: Option<Extension<Principal>>,
+) -> Result<TaggedUser, HandlerError<GetUserError>> {
+    let settings = state.settings.load_full();
+    let keys = settings.validator.username_keys(&username);
+    let current = tenant
+        .store
+        .get_user(&keys.lookup)
+        .await
+        .map_err(store_error)?;
+    match tenant
+        .store
+        .record_failed_login(&keys.lookup, settings.max_failed_logins)
+        .await
+    {
+        Ok(user) => {
+            let event = user_event(
+                AuditAction::UserLoginFailed,
+                principal,
+                current.as_ref(),
+                &user,
+            );
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged(user))
+        }
+        Err(StoreError::UserNotFound) => {
+            Err(HandlerError::service_error(GetUserError::UserNotFound {
+                username,
+            }))
+        }
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// A group, with its entity tag in the `ETag` header.
+type TaggedGroup = ([(HeaderName, String); 1], Json<Group>);
+
+fn tagged_group(group: Group) -> TaggedGroup {
+    ([(ETAG, etag(group.id, group.version))], Json(group))
+}
+
+/// Query parameters of the endpoints that return a [`Page`].
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+impl PageQuery {
+    /// The number of items on the page, or why it is out of range.
+    fn limit(&self) -> Result<usize, String> {
+        match self.limit.unwrap_or(DEFAULT_PAGE_SIZE) {
+            limit @ 1..=MAX_PAGE_SIZE => Ok(limit),
+            _ => Err(format!("limit has to be between 1 and {MAX_PAGE_SIZE}")),
+        }
+    }
+
+    /// The cursor of a list that is ordered by id.
+    fn id_cursor(&self) -> Result<Option<Uuid>, String> {
+        self.cursor
+            .as_deref()
+            .map(Uuid::try_parse)
+            .transpose()
+            .map_err(|_| "cursor was not returned by a previous page".to_string())
+    }
+}
+
+fn invalid_page(reason: String) -> HandlerError<GroupError> {
+    HandlerError::service_error(GroupError::InvalidPage { reason })
+}
+
+/// A page of at most `limit` items, from one more item than that if there is
+/// a next page. The cursor to the next page is the key of the last item.
+fn page<T>(mut items: Vec<T>, limit: usize, key: impl Fn(&T) -> String) -> Page<T> {
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(key)
+    } else {
+        None
+    };
+
+    Page { items, next_cursor }
+}
+
+/// List all groups, ordered by name.
+#[instrument(err, skip(tenant))]
+pub async fn list_groups(
+    tenant: Tenant,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<Group>>, HandlerError<GroupError>> {
+    let limit = query.limit().map_err(invalid_page)?;
+    let groups = tenant
+        .store
+        .list_groups(query.cursor.as_deref(), limit + 1)
+        .await
+        .map_err(store_error)?;
+
+    Ok(Json(page(groups, limit, |group| group.name.clone())))
+}
+
+#[instrument(err, skip(state, tenant))]
+pub async fn create_group(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    principal: Option<Extension<Principal>>,
+    client: Option<Extension<ClientIp>>,
+    JsonBody(new_group): JsonBody<NewGroup>,
+) -> Result<TaggedGroup, HandlerError<GroupError>> {
+    state
+        .settings
+        .load()
+        .validator
+        .validate_new_group(&new_group)
+        .map_err(|violations| {
+            HandlerError::service_error(GroupError::InvalidGroup { violations })
+        })?;
+    if !new_group.roles.is_empty() {
+        check_role_grant(&state, &tenant, principal.as_deref(), client.as_deref()).await?;
+    }
+
+    match tenant.store.create_group(new_group).await {
+        Ok(group) => {
+            let event = group_event(AuditAction::GroupCreated, principal, None, Some(&group));
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged_group(group))
+        }
+        Err(StoreError::GroupNameTaken) => {
+            Err(HandlerError::service_error(GroupError::GroupAlreadyExists))
+        }
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Get a group. Supports `If-None-Match` like [`get_user`].
+#[instrument(err, skip(tenant, headers))]
+pub async fn get_group(
+    tenant: Tenant,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, HandlerError<GroupError>> {
+    let group = current_group(&tenant, &name).await?;
+
+    let etag = etag(group.id, group.version);
+    if if_none_match(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    Ok(tagged_group(group).into_response())
+}
+
+/// Change the description or roles of a group. Supports `If-Match` like
+/// [`update_user`].
+#[instrument(err, skip(state, tenant, headers))]
+pub async fn update_group(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(name): Path<String>,
+    principal: Option<Extension<Principal>>,
+    client: Option<Extension<ClientIp>>,
+    headers: HeaderMap,
+    JsonBody(update): JsonBody<GroupUpdate>,
+) -> Result<TaggedGroup, HandlerError<GroupError>> {
+    let current = current_group(&tenant, &name).await?;
+    let expected_version = check_group_precondition(&current, &headers)?;
+
+    state
+        .settings
+        .load()
+        .validator
+        .validate_group_update(&update)
+        .map_err(|violations| {
+            HandlerError::service_error(GroupError::InvalidGroup { violations })
+        })?;
+    let changes_roles = update.roles.as_ref().is_some_and(|roles| {
+        roles.iter().collect::<BTreeSet<_>>() != current.roles.iter().collect::<BTreeSet<_>>()
+    });
+    if changes_roles {
+        check_role_grant(&state, &tenant, principal.as_deref(), client.as_deref()).await?;
+    }
+
+    match tenant
+        .store
+        .update_group(&name, expected_version, update)
+        .await
+    {
+        Ok(group) => {
+            let event = group_event(
+                AuditAction::GroupUpdated,
+                principal,
+                Some(&current),
+                Some(&group),
+            );
+            audit::record(&*tenant.store, event).await;
+            Ok(tagged_group(group))
+        }
+        Err(err) => Err(group_error(err, &current)),
+    }
+}
+
+/// Delete a group with all its memberships. Supports `If-Match` like
+/// [`update_user`].
+#[instrument(err, skip(tenant, headers))]
+pub async fn delete_group(
+    tenant: Tenant,
+    Path(name): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+) -> Result<StatusCode, HandlerError<GroupError>> {
+    let current = current_group(&tenant, &name).await?;
+    let expected_version = check_group_precondition(&current, &headers)?;
+
+    match tenant.store.delete_group(&name, expected_version).await {
+        Ok(()) => {
+            let event = group_event(AuditAction::GroupDeleted, principal, Some(&current), None);
+            audit::record(&*tenant.store, event).await;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(err) => Err(group_error(err, &current)),
+    }
+}
+
+/// The members of a group, ordered by id.
+#[instrument(err, skip(tenant))]
+pub async fn list_group_members(
+    tenant: Tenant,
+    Path(name): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<GroupMember>>, HandlerError<GroupError>> {
+    let limit = query.limit().map_err(invalid_page)?;
+    let after = query.id_cursor().map_err(invalid_page)?;
+
+    match tenant.store.group_members(&name, after, limit + 1).await {
+        Ok(members) => Ok(Json(page(members, limit, |member| {
+            member.id.simple().to_string()
+        }))),
+        Err(StoreError::GroupNotFound) => Err(group_not_found(name)),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Add a user to a group. Adding a user that is already a member succeeds
+/// without changing anything.
+#[instrument(err, skip(state, tenant))]
+pub async fn add_group_member(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path((name, username)): Path<(String, String)>,
+    principal: Option<Extension<Principal>>,
+) -> Result<StatusCode, HandlerError<GroupError>> {
+    let user = member(&state, &tenant, username).await?;
+
+    match tenant.store.add_member(&name, user.id).await {
+        Ok(added) => {
+            if added {
+                let event = member_event(AuditAction::GroupMemberAdded, principal, &name, &user);
+                audit::record(&*tenant.store, event).await;
+            }
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(StoreError::GroupNotFound) => Err(group_not_found(name)),
+        Err(StoreError::UserNotFound) => {
+            Err(HandlerError::service_error(GroupError::UserNotFound {
+                username: user.username,
+            }))
+        }
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Remove a user from a group. Removing a user that is not a member succeeds
+/// without changing anything.
+#[instrument(err, skip(state, tenant))]
+pub async fn remove_group_member(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path((name, username)): Path<(String, String)>,
+    principal: Option<Extension<Principal>>,
+) -> Result<StatusCode, HandlerError<GroupError>> {
+    let user = member(&state, &tenant, username).await?;
+
+    match tenant.store.remove_member(&name, user.id).await {
+        Ok(removed) => {
+            if removed {
+                let event = member_event(AuditAction::GroupMemberRemoved, principal, &name, &user);
+                audit::record(&*tenant.store, event).await;
+            }
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(StoreError::GroupNotFound) => Err(group_not_found(name)),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// The groups of a user, ordered by name.
+#[instrument(err, skip(state, tenant))]
+pub async fn list_user_groups(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(username): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Response, HandlerError<GroupError>> {
+    let limit = query.limit().map_err(invalid_page)?;
+    let keys = state.settings.load().validator.username_keys(&username);
+    let user = match tenant.store.get_user(&keys.lookup).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return redirect_renamed(&tenant, &keys.lookup, "/groups")
+                .await?
+                .ok_or_else(|| HandlerError::service_error(GroupError::UserNotFound { username }))
+        }
+        Err(err) => return Err(store_error(err)),
+    };
+
+    let groups = tenant
+        .store
+        .user_groups(user.id, query.cursor.as_deref(), limit + 1)
+        .await
+        .map_err(store_error)?;
+
+    Ok(Json(page(groups, limit, |group| group.name.clone())).into_response())
+}
+
+/// One page of the audit log, with the events that match the filter in the
+/// query, in the order they were recorded.
+#[instrument(err, skip(tenant))]
+pub async fn list_audit_events(
+    tenant: Tenant,
+    Query(filter): Query<AuditFilter>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<AuditEvent>>, HandlerError<AuditError>> {
+    let invalid_page = |reason| HandlerError::service_error(AuditError::InvalidPage { reason });
+    let limit = query.limit().map_err(invalid_page)?;
+    let after = query.id_cursor().map_err(invalid_page)?;
+
+    let events = tenant
+        .store
+        .audit_events(&filter, after, limit + 1)
+        .await
+        .map_err(store_error)?;
+
+    Ok(Json(page(events, limit, |event| {
+        event.id.simple().to_string()
+    })))
+}
+
+/// All events of the audit log that match the filter in the query, as
+/// newline-delimited JSON in the order they were recorded. The events are
+/// read a page at a time while the response is sent, so the log never has
+/// to fit into memory. If the store fails halfway, the response is cut off.
+#[instrument(err, skip(tenant))]
+pub async fn export_audit_events(
+    tenant: Tenant,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Response, HandlerError<AuditError>> {
+    let store = tenant.store;
+    let first = store
+        .audit_events(&filter, None, MAX_PAGE_SIZE)
+        .await
+        .map_err(store_error)?;
+
+    let lines = stream::try_unfold(Some(first), move |events| {
+        let store = store.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(events) = events.filter(|events| !events.is_empty()) else {
+                return Ok(None);
+            };
+
+            let mut lines = Vec::new();
+            for event in &events {
+                serde_json::to_writer(&mut lines, event).expect("audit events are valid JSON");
+                lines.push(b'\n');
+            }
+
+            let next = match events.last() {
+                Some(last) if events.len() == MAX_PAGE_SIZE => Some(
+                    store
+                        .audit_events(&filter, Some(last.id), MAX_PAGE_SIZE)
+                        .await
+                        .map_err(|err| {
+                            error!(%err, "Store failed to export the audit log");
+                            io::Error::other(err)
+                        })?,
+                ),
+                _ => None,
+            };
+            Ok::<_, io::Error>(Some((lines, next)))
+        }
+    });
+
+    Ok(([(CONTENT_TYPE, NDJSON)], StreamBody::new(lines)).into_response())
+}
+
+/// The signed checkpoints of the audit log, oldest first.
+#[instrument(err, skip(tenant))]
+pub async fn list_audit_checkpoints(
+    tenant: Tenant,
+) -> Result<Json<Vec<AuditCheckpoint>>, HandlerError<AuditError>> {
+    let checkpoints = tenant
+        .store
+        .audit_checkpoints()
+        .await
+        .map_err(store_error)?;
+
+    Ok(Json(checkpoints))
+}
+
+/// The roles of a group are granted to all its members, so only admins may
+/// grant them. Otherwise a `group_admin` could give a group the `admin` role
+/// and add its own user to it.
+async fn check_role_grant(
+    state: &AppState,
+    tenant: &Tenant,
+    principal: Option<&Principal>,
+    client: Option<&ClientIp>,
+) -> Result<(), HandlerError<GroupError>> {
+    let settings = state.settings.load_full();
+    if !settings.authorizer.is_enforced() {
+        return Ok(());
+    }
+
+    let roles = match principal {
+        Some(principal) => {
+            let lookup_key = settings.validator.username_keys(&principal.0).lookup;
+            group_roles(&*tenant.store, &lookup_key)
+                .await
+                .map_err(store_error)?
+        }
+        None => Vec::new(),
+    };
+    if settings.authorizer.allows(principal, &roles, Role::Admin) {
+        return Ok(());
+    }
+
+    let event = AuditEvent {
+        reason: Some(format!("missing role {} to grant roles", Role::Admin)),
+        ..audit::event(AuditAction::AuthDenied, audit::actor(principal))
+    };
+    let client = client.map_or(IpAddr::from(Ipv4Addr::UNSPECIFIED), |client| client.0);
+    audit::record_denial(&*tenant.store, &state.denials, &tenant.id, client, event).await;
+    Err(HandlerError::Unauthorized)
+}
+
+async fn current_group(tenant: &Tenant, name: &str) -> Result<Group, HandlerError<GroupError>> {
+    match tenant.store.get_group(name).await {
+        Ok(Some(group)) => Ok(group),
+        Ok(None) => Err(group_not_found(name.to_string())),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+/// Check the `If-Match` header of a change of a group, returning the version
+/// the store has to check if the header is present.
+fn check_group_precondition(
+    current: &Group,
+    headers: &HeaderMap,
+) -> Result<Option<u64>, HandlerError<GroupError>> {
+    let current_etag = etag(current.id, current.version);
+    if !if_match(headers, &current_etag) {
         return Err(HandlerError::service_error(
-            CreateUserError::UsernameAlreadyExists,
+            GroupError::PreconditionFailed { etag: current_etag },
         ));
     }
 
-    if new_user.name.is_empty() {
-        return Err(HandlerError::service_error(CreateUserError::InvalidName(
-            InvalidNameReason::TooShort,
-        )));
-    } else if new_user.name.len() > 20 {
-        return Err(HandlerError::service_error(CreateUserError::InvalidName(
-            InvalidNameReason::TooLong,
-        )));
-    } else if new_user.name == "invalid" {
-        return Err(HandlerError::service_error(CreateUserError::InvalidName(
-            InvalidNameReason::InvalidCharacters,
-        )));
+    Ok(headers.contains_key(IF_MATCH).then_some(current.version))
+}
+
+/// The user that is added to or removed from a group.
+async fn member(
+    state: &AppState,
+    tenant: &Tenant,
+    username: String,
+) -> Result<User, HandlerError<GroupError>> {
+    let keys = state.settings.load().validator.username_keys(&username);
+    match tenant.store.get_user(&keys.lookup).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HandlerError::service_error(GroupError::UserNotFound {
+            username,
+        })),
+        Err(err) => Err(store_error(err)),
+    }
+}
+
+fn group_not_found(name: String) -> HandlerError<GroupError> {
+    HandlerError::service_error(GroupError::GroupNotFound { name })
+}
+
+/// Map the errors of a change of the group `current`.
+fn group_error(err: StoreError, current: &Group) -> HandlerError<GroupError> {
+    match err {
+        StoreError::GroupNotFound => group_not_found(current.name.clone()),
+        StoreError::VersionConflict { current: version } => {
+            HandlerError::service_error(GroupError::PreconditionFailed {
+                etag: etag(current.id, version),
+            })
+        }
+        err => store_error(err),
+    }
+}
+
+/// The audit event of a change of `user`, which was `before` before it.
+fn user_event(
+    action: AuditAction,
+    principal: Option<Extension<Principal>>,
+    before: Option<&User>,
+    user: &User,
+) -> AuditEvent {
+    AuditEvent {
+        target: Some(user.username.clone()),
+        target_id: Some(user.id),
+        changes: audit::changes(before, Some(user)),
+        ..audit::event(action, audit::actor(principal.as_deref()))
+    }
+}
+
+/// The audit event of a change of a group, which is `None` before it was
+/// created or after it was deleted.
+fn group_event(
+    action: AuditAction,
+    principal: Option<Extension<Principal>>,
+    before: Option<&Group>,
+    after: Option<&Group>,
+) -> AuditEvent {
+    let group = after.or(before).expect("a group was changed");
+    AuditEvent {
+        target: Some(group.name.clone()),
+        target_id: Some(group.id),
+        changes: audit::changes(before, after),
+        ..audit::event(action, audit::actor(principal.as_deref()))
+    }
+}
+
+/// The audit event of adding `user` to or removing it from a group. The
+/// target is the user, the group is recorded as the change.
+fn member_event(
+    action: AuditAction,
+    principal: Option<Extension<Principal>>,
+    group: &str,
+    user: &User,
+) -> AuditEvent {
+    let (before, after) = match action {
+        AuditAction::GroupMemberRemoved => (Value::from(group), Value::Null),
+        _ => (Value::Null, Value::from(group)),
+    };
+    AuditEvent {
+        target: Some(user.username.clone()),
+        target_id: Some(user.id),
+        changes: BTreeMap::from([("group".to_string(), FieldChange { before, after })]),
+        ..audit::event(action, audit::actor(principal.as_deref()))
     }
+}
 
-    Ok(Json(models::User {
-        username: new_user.username,
-        name: new_user.name,
+/// If the lookup key belongs to the old username of a renamed user, redirect
+/// to the same resource under its current username. The redirect is
+/// temporary, since the old username is released after the grace period,
+/// and keeps the method and body of the request.
+async fn redirect_renamed<E>(
+    tenant: &Tenant,
+    lookup_key: &str,
+    suffix: &str,
+) -> Result<Option<Response>, HandlerError<E>> {
+    let user = tenant
+        .store
+        .get_renamed_user(lookup_key, SystemTime::now())
+        .await
+        .map_err(store_error)?;
+
+    Ok(user.map(|user| {
+        let username = utf8_percent_encode(&user.username, PATH_SEGMENT);
+        Redirect::temporary(&format!("/users/{username}{suffix}")).into_response()
     }))
 }
 
-/// Just a fake auth check, this can force a specific error by supplying a
-/// specific username.
-pub fn check_auth(username: &str) -> Result<(), models::AuthError> {
-    match username {
-        "unauthenticated" => Err(models::AuthError::Unauthenticated),
-        "unauthorized" => Err(models::AuthError::Unauthorized),
-        _ => Ok(()),
+fn store_error<E>(err: StoreError) -> HandlerError<E> {
+    error!(%err, "Store failed to handle the request");
+    HandlerError::StoreUnavailable
+}
+
+/// Liveness probe: succeeds as long as the process is able to handle
+/// requests.
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness probe: succeeds if the store is ready and the server is not
+/// shutting down.
+pub async fn readyz(State(state): State<AppState>) -> Result<&'static str, ReadinessError> {
+    if state.lifecycle.is_draining() {
+        return Err(ReadinessError::Draining);
     }
+
+    state
+        .store
+        .check_ready()
+        .await
+        .map_err(|err| ReadinessError::StoreNotReady {
+            reason: err.to_string(),
+        })?;
+
+    Ok("ok")
+}
+
+/// Metrics in the Prometheus text format.
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::config::{ApiKeyConfig, Config, Secret};
+    use crate::db::memory::MemoryStore;
+    use crate::models::{Attributes, UserStatus};
+    use crate::reload::Settings;
+    use crate::tenant::TenantId;
+    use arc_swap::ArcSwap;
     use axum::response::IntoResponse;
-    use http::StatusCode;
+    use http::header::{IF_NONE_MATCH, LOCATION};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn state() -> AppState {
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&Config::default())));
+        AppState::new(Arc::new(MemoryStore::new()), settings)
+    }
+
+    fn tenant(state: &AppState) -> Tenant {
+        Tenant::new(state, &TenantId::default())
+    }
+
+    async fn get(state: &AppState, username: &str, headers: HeaderMap) -> Response {
+        get_user(
+            State(state.clone()),
+            tenant(state),
+            Path(username.to_string()),
+            headers,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn body(response: Response) -> User {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn new_user(username: &str, name: &str) -> JsonBody<models::NewUser> {
+        JsonBody(models::NewUser {
+            username: username.to_string(),
+            name: name.to_string(),
+            email: None,
+            attributes: Attributes::new(),
+        })
+    }
 
     #[test]
     fn get_user_error_into_response() {
@@ -107,14 +1092,716 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn error_response_includes_request_id() {
+        let err = HandlerError::service_error(models::GetUserError::UserNotFound {
+            username: "not_found".to_string(),
+        });
+
+        let response = models::CURRENT_REQUEST_ID
+            .scope(models::RequestId("abc-123".to_string()), async {
+                err.into_response()
+            })
+            .await;
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("abc-123", body["request_id"]);
+
+        let err: models::GetUserError = serde_json::from_value(body).unwrap();
+        assert_eq!(
+            models::GetUserError::UserNotFound {
+                username: "not_found".to_string()
+            },
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn readyz_fails_when_draining() {
+        let state = state();
+        assert_eq!(Ok("ok"), readyz(State(state.clone())).await);
+
+        state.lifecycle.start_draining();
+        assert_eq!(Err(ReadinessError::Draining), readyz(State(state)).await);
+    }
+
     #[tokio::test]
     async fn get_user_not_found() {
+        let state = state();
         let path = "not_found".to_string();
-        let err = get_user(Path(path)).await.expect_err("expected an error");
+        let err = get_user(
+            State(state.clone()),
+            tenant(&state),
+            Path(path),
+            HeaderMap::new(),
+        )
+        .await
+        .expect_err("expected an error");
 
         match err {
-            models::GetUserError::UserNotFound { username } if username == "not_found" => {}
+            HandlerError::ServiceError(models::GetUserError::UserNotFound { username })
+                if username == "not_found" => {}
             _ => panic!("expected UserNotFound error"),
         }
     }
+
+    #[tokio::test]
+    async fn any_valid_username_can_be_read() {
+        let state = state();
+        for username in ["unauthenticated", "unauthorized"] {
+            let _ = create_user(
+                State(state.clone()),
+                tenant(&state),
+                None,
+                new_user(username, "Jane"),
+            )
+            .await
+            .unwrap();
+
+            let response = get(&state, username, HeaderMap::new()).await;
+
+            assert_eq!(StatusCode::OK, response.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn create_user_reports_all_violations() {
+        let state = state();
+        let err = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("a_username_that_is_too_long", ""),
+        )
+        .await
+        .expect_err("expected an error");
+
+        match err {
+            HandlerError::ServiceError(CreateUserError::InvalidNewUser { violations }) => {
+                let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+                assert_eq!(vec!["username", "name"], fields);
+            }
+            _ => panic!("expected InvalidNewUser error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn equivalent_usernames_resolve_to_the_same_user() {
+        let state = state();
+        let _ = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+
+        for username in ["jane", "JANE", "ＪＡＮＥ"] {
+            let user = body(get(&state, username, HeaderMap::new()).await).await;
+
+            assert_eq!("Jane", user.username, "{username}");
+        }
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique_regardless_of_case() {
+        let state = state();
+        let _ = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+
+        let err = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("jANE", "Jane Doe"),
+        )
+        .await
+        .expect_err("expected an error");
+        let user = body(get(&state, "JANE", HeaderMap::new()).await).await;
+
+        assert!(matches!(
+            err,
+            HandlerError::ServiceError(CreateUserError::UsernameAlreadyExists)
+        ));
+        assert_eq!("Jane", user.username);
+    }
+
+    #[tokio::test]
+    async fn users_can_be_read_by_old_clients() {
+        // The user as it was returned before ids, timestamps and versions
+        // were added.
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct OldUser {
+            username: String,
+            name: String,
+        }
+
+        let state = state();
+        let (_, Json(user)) = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+        let body = serde_json::to_vec(&user).unwrap();
+
+        assert_eq!(
+            OldUser {
+                username: "Jane".to_string(),
+                name: "Jane Doe".to_string(),
+            },
+            serde_json::from_slice(&body).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn get_user_is_not_modified_if_the_etag_matches() {
+        let state = state();
+        let _ = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+
+        let response = get(&state, "jane", HeaderMap::new()).await;
+        let etag = response.headers()[ETAG].clone();
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag.clone());
+        let not_modified = get(&state, "jane", headers).await;
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(StatusCode::NOT_MODIFIED, not_modified.status());
+        assert_eq!(etag, not_modified.headers()[ETAG]);
+    }
+
+    #[tokio::test]
+    async fn update_user_checks_if_match() {
+        let state = state();
+        let ([(_, etag)], _) = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+        let update = |name: &str| {
+            JsonBody(UserUpdate {
+                name: Some(name.to_string()),
+                email: None,
+                attributes: None,
+            })
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MATCH, etag.parse().unwrap());
+
+        let response = update_user(
+            State(state.clone()),
+            tenant(&state),
+            Path("jane".to_string()),
+            None,
+            headers.clone(),
+            update("Jane Roe"),
+        )
+        .await
+        .unwrap();
+        let new_etag = response.headers()[ETAG].to_str().unwrap().to_string();
+        let user = body(response).await;
+        let err = update_user(
+            State(state.clone()),
+            tenant(&state),
+            Path("jane".to_string()),
+            None,
+            headers,
+            update("Jane Poe"),
+        )
+        .await
+        .expect_err("expected an error");
+
+        assert_eq!("Jane Roe", user.name);
+        assert_eq!(2, user.version);
+        assert_eq!(
+            HandlerError::ServiceError(UpdateUserError::PreconditionFailed { etag: new_etag }),
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn old_usernames_redirect_to_the_renamed_user() {
+        let state = state();
+        let (_, Json(created)) = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+        let rename = |username: &str| {
+            JsonBody(UsernameChange {
+                username: username.to_string(),
+            })
+        };
+
+        let renamed = rename_user(
+            State(state.clone()),
+            tenant(&state),
+            Path("jane".to_string()),
+            None,
+            HeaderMap::new(),
+            rename("Janet"),
+        )
+        .await
+        .unwrap();
+        let redirect = get(&state, "JANE", HeaderMap::new()).await;
+        let taken = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("jane", "Jane Roe"),
+        )
+        .await;
+        let by_id = get_user_by_id(tenant(&state), Path(created.id), HeaderMap::new())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, renamed.status());
+        assert_eq!(StatusCode::TEMPORARY_REDIRECT, redirect.status());
+        assert_eq!("/users/Janet", redirect.headers()[LOCATION]);
+        assert!(taken.is_err());
+        assert_eq!(created.id, body(by_id).await.id);
+    }
+
+    #[tokio::test]
+    async fn get_user_by_id_not_found() {
+        let id = Uuid::now_v7();
+
+        let err = get_user_by_id(tenant(&state()), Path(id), HeaderMap::new())
+            .await
+            .expect_err("expected an error");
+
+        assert_eq!(
+            HandlerError::ServiceError(GetUserError::UserIdNotFound { id }),
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn status_changes_are_recorded_with_their_actor() {
+        let state = state();
+        let _ = create_user(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+        let change = |status| {
+            change_user_status(
+                State(state.clone()),
+                tenant(&state),
+                Path("jane".to_string()),
+                Some(Extension(Principal("admin".to_string()))),
+                HeaderMap::new(),
+                JsonBody(StatusChange {
+                    status,
+                    reason: "chargeback".to_string(),
+                }),
+            )
+        };
+
+        let suspended = body(change(UserStatus::Suspended).await.unwrap()).await;
+        let err = change(UserStatus::Locked)
+            .await
+            .expect_err("expected an error");
+        let transitions = get_status_transitions(
+            State(state.clone()),
+            tenant(&state),
+            Path("jane".to_string()),
+        )
+        .await
+        .unwrap();
+        let body = hyper::body::to_bytes(transitions.into_body())
+            .await
+            .unwrap();
+        let transitions: Vec<StatusTransition> = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(UserStatus::Suspended, suspended.status);
+        assert_eq!(
+            HandlerError::ServiceError(ChangeStatusError::InvalidTransition {
+                from: UserStatus::Suspended,
+                to: UserStatus::Locked,
+            }),
+            err
+        );
+        assert_eq!(1, transitions.len());
+        assert_eq!(
+            ("admin", "chargeback"),
+            (
+                transitions[0].actor.as_str(),
+                transitions[0].reason.as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn only_allowed_status_transitions_are_possible() {
+        use UserStatus::*;
+        let statuses = [Active, Suspended, Locked, Deleted];
+        let allowed = [
+            (Active, Suspended),
+            (Active, Locked),
+            (Suspended, Active),
+            (Locked, Active),
+            (Locked, Suspended),
+        ];
+
+        for from in statuses {
+            for to in statuses {
+                assert_eq!(
+                    allowed.contains(&(from, to)),
+                    from.can_become(to),
+                    "{from} -> {to}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn users_are_listed_by_attribute() {
+        let state = state();
+        for (username, attributes) in [
+            ("ann", json!({"department": "eng", "level": 3})),
+            ("bob", json!({"department": "eng"})),
+            ("cat", json!({"department": "sales"})),
+            ("dan", json!({})),
+        ] {
+            let JsonBody(mut new_user) = new_user(username, "Jane");
+            new_user.attributes = serde_json::from_value(attributes).unwrap();
+            let _ = create_user(
+                State(state.clone()),
+                tenant(&state),
+                None,
+                JsonBody(new_user),
+            )
+            .await
+            .unwrap();
+        }
+        let list = |parameters: &[(&str, &str)], limit, cursor: Option<&str>| {
+            let parameters = parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            list_users(
+                tenant(&state),
+                Query(parameters),
+                Query(PageQuery {
+                    limit,
+                    cursor: cursor.map(ToString::to_string),
+                }),
+            )
+        };
+        let usernames = |page: &Page<User>| {
+            page.items
+                .iter()
+                .map(|user| user.username.clone())
+                .collect::<BTreeSet<_>>()
+        };
+
+        let Json(all) = list(&[], None, None).await.unwrap();
+        let eng = [("attributes.department", "eng")];
+        let Json(first) = list(&eng, Some(1), None).await.unwrap();
+        let Json(second) = list(&eng, Some(1), first.next_cursor.as_deref())
+            .await
+            .unwrap();
+        let Json(senior) = list(&[("attributes.level", "3")], None, None)
+            .await
+            .unwrap();
+        let invalid = list(&[("attributes.", "eng")], None, None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(4, all.items.len());
+        assert!(all.items.windows(2).all(|users| users[0].id < users[1].id));
+        assert_eq!(1, first.items.len());
+        assert!(first.next_cursor.is_some());
+        assert_eq!(None, second.next_cursor);
+        let mut engineers = usernames(&first);
+        engineers.extend(usernames(&second));
+        assert_eq!(
+            BTreeSet::from(["ann".to_string(), "bob".to_string()]),
+            engineers
+        );
+        assert_eq!(BTreeSet::from(["ann".to_string()]), usernames(&senior));
+        assert_eq!(StatusCode::BAD_REQUEST, invalid.into_response().status());
+    }
+
+    #[tokio::test]
+    async fn groups_are_listed_in_pages() {
+        let state = state();
+        for name in ["c", "a", "b"] {
+            let group = NewGroup {
+                name: name.to_string(),
+                description: None,
+                roles: Vec::new(),
+            };
+            let _ = create_group(
+                State(state.clone()),
+                tenant(&state),
+                None,
+                None,
+                JsonBody(group),
+            )
+            .await
+            .unwrap();
+        }
+        let list = |limit, cursor: Option<&str>| {
+            list_groups(
+                tenant(&state),
+                Query(PageQuery {
+                    limit,
+                    cursor: cursor.map(ToString::to_string),
+                }),
+            )
+        };
+        let names = |page: &Page<Group>| {
+            page.items
+                .iter()
+                .map(|group| group.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let Json(first) = list(Some(2), None).await.unwrap();
+        let Json(second) = list(Some(2), first.next_cursor.as_deref()).await.unwrap();
+        let invalid = list(Some(0), None).await.unwrap_err();
+
+        assert_eq!(vec!["a", "b"], names(&first));
+        assert_eq!(Some("b".to_string()), first.next_cursor);
+        assert_eq!(vec!["c"], names(&second));
+        assert_eq!(None, second.next_cursor);
+        assert_eq!(StatusCode::BAD_REQUEST, invalid.into_response().status());
+    }
+
+    #[tokio::test]
+    async fn only_admins_grant_roles_through_groups() {
+        let mut config = Config::default();
+        config.auth.enforce_roles = true;
+        for (principal, role) in [("ci", Role::GroupAdmin), ("root", Role::Admin)] {
+            config.auth.api_keys.push(ApiKeyConfig {
+                principal: principal.to_string(),
+                key: Secret::new(principal),
+                roles: vec![role],
+                tenant: None,
+            });
+        }
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+        let state = AppState::new(Arc::new(MemoryStore::new()), settings);
+        let principal = |name: &str| Some(Extension(Principal(name.to_string())));
+        let create = |principal, roles| {
+            create_group(
+                State(state.clone()),
+                tenant(&state),
+                principal,
+                None,
+                JsonBody(NewGroup {
+                    name: "ops".to_string(),
+                    description: None,
+                    roles,
+                }),
+            )
+        };
+        let update = |principal, roles| {
+            update_group(
+                State(state.clone()),
+                tenant(&state),
+                Path("ops".to_string()),
+                principal,
+                None,
+                HeaderMap::new(),
+                JsonBody(GroupUpdate {
+                    description: Some(Some("Operations".to_string())),
+                    roles: Some(roles),
+                }),
+            )
+        };
+
+        for _ in 0..2 {
+            let escalated = create(principal("ci"), vec![Role::Admin]).await;
+            assert_eq!(Some(HandlerError::Unauthorized), escalated.err());
+        }
+
+        let _ = create(principal("ci"), Vec::new()).await.unwrap();
+        let escalated = update(principal("ci"), vec![Role::Admin]).await;
+        assert_eq!(Some(HandlerError::Unauthorized), escalated.err());
+
+        let (_, Json(granted)) = update(principal("root"), vec![Role::Admin]).await.unwrap();
+        assert_eq!(vec![Role::Admin], granted.roles);
+
+        // Leaving the roles as they are is not a grant.
+        let _ = update(principal("ci"), vec![Role::Admin]).await.unwrap();
+
+        // The same client was denied the same way every time.
+        let filter = AuditFilter {
+            action: Some(AuditAction::AuthDenied),
+            ..Default::default()
+        };
+        let denied = tenant(&state)
+            .store
+            .audit_events(&filter, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(1, denied.len());
+    }
+
+    #[tokio::test]
+    async fn changes_are_audited_with_their_actor_and_request() {
+        let state = state();
+        let admin = || Some(Extension(Principal("admin".to_string())));
+        let (_, Json(user)) = create_user(
+            State(state.clone()),
+            tenant(&state),
+            admin(),
+            new_user("Jane", "Jane Doe"),
+        )
+        .await
+        .unwrap();
+        models::CURRENT_REQUEST_ID
+            .scope(
+                models::RequestId("abc-123".to_string()),
+                update_user(
+                    State(state.clone()),
+                    tenant(&state),
+                    Path("jane".to_string()),
+                    admin(),
+                    HeaderMap::new(),
+                    JsonBody(UserUpdate {
+                        name: Some("Jane Roe".to_string()),
+                        ..Default::default()
+                    }),
+                ),
+            )
+            .await
+            .unwrap();
+        let group = NewGroup {
+            name: "support".to_string(),
+            description: None,
+            roles: Vec::new(),
+        };
+        let _ = create_group(
+            State(state.clone()),
+            tenant(&state),
+            None,
+            None,
+            JsonBody(group),
+        )
+        .await
+        .unwrap();
+        add_group_member(
+            State(state.clone()),
+            tenant(&state),
+            Path(("support".to_string(), "jane".to_string())),
+            admin(),
+        )
+        .await
+        .unwrap();
+        let list = |filter| {
+            list_audit_events(
+                tenant(&state),
+                Query(filter),
+                Query(PageQuery {
+                    limit: None,
+                    cursor: None,
+                }),
+            )
+        };
+
+        let Json(all) = list(AuditFilter::default()).await.unwrap();
+        let Json(of_jane) = list(AuditFilter {
+            target: Some("Jane".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let update = &of_jane.items[1];
+
+        let actions: Vec<_> = all.items.iter().map(|event| event.action).collect();
+        assert_eq!(
+            vec![
+                AuditAction::UserCreated,
+                AuditAction::UserUpdated,
+                AuditAction::GroupCreated,
+                AuditAction::GroupMemberAdded,
+            ],
+            actions
+        );
+        assert_eq!("anonymous", all.items[2].actor);
+        assert_eq!(3, of_jane.items.len());
+        assert_eq!(
+            ("admin", Some(user.id), Some("abc-123")),
+            (
+                update.actor.as_str(),
+                update.target_id,
+                update.request_id.as_deref()
+            )
+        );
+        assert_eq!(
+            BTreeMap::from([(
+                "name".to_string(),
+                FieldChange {
+                    before: Value::from("Jane Doe"),
+                    after: Value::from("Jane Roe"),
+                }
+            )]),
+            update.changes
+        );
+        assert_eq!(
+            Value::from("support"),
+            of_jane.items[2].changes["group"].after
+        );
+    }
+
+    #[tokio::test]
+    async fn the_audit_log_is_exported_as_ndjson() {
+        let state = state();
+        for username in ["jane", "john"] {
+            let _ = create_user(
+                State(state.clone()),
+                tenant(&state),
+                None,
+                new_user(username, "Doe"),
+            )
+            .await
+            .unwrap();
+        }
+
+        let response = export_audit_events(tenant(&state), Query(AuditFilter::default()))
+            .await
+            .unwrap();
+        let content_type = response.headers()[CONTENT_TYPE].clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let events: Vec<AuditEvent> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        let targets: Vec<_> = events.iter().map(|event| event.target.as_deref()).collect();
+
+        assert_eq!(NDJSON, content_type);
+        assert_eq!(vec![Some("jane"), Some("john")], targets);
+        assert_eq!(Ok(()), audit::verify_chain(&events));
+    }
 }
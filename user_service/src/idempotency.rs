@@ -0,0 +1,264 @@
+use crate::config::IdempotencyConfig;
+use crate::tenant::TenantId;
+use axum::body::Bytes;
+use http::{HeaderMap, Method, StatusCode};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often expired entries are removed.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Identifies the requests that share an idempotency key. Keys are scoped to
+/// the tenant, the principal and the route, so clients never see each other's
+/// responses.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestKey {
+    pub tenant: Option<TenantId>,
+    pub principal: Option<String>,
+    pub method: Method,
+    pub path: String,
+    pub idempotency_key: String,
+}
+
+/// A response that is kept to be replayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// What to do with a request that has an idempotency key.
+#[derive(Debug)]
+pub enum Begin {
+    /// This is the first request with the key, it has to be handled and its
+    /// response stored with [`Pending::complete`].
+    Started(Pending),
+
+    /// The request was already handled, this is its response.
+    Replay(StoredResponse),
+
+    /// The first request with the key is still being handled.
+    InProgress,
+
+    /// The key was used for a request with a different body.
+    Mismatch,
+}
+
+#[derive(Debug)]
+enum State {
+    InProgress,
+    Completed(StoredResponse),
+}
+
+#[derive(Debug)]
+struct Entry {
+    fingerprint: u64,
+    state: State,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+struct Entries {
+    entries: HashMap<RequestKey, Entry>,
+    last_pruned: Instant,
+}
+
+/// Keeps the responses to requests with an `Idempotency-Key` header, so
+/// retries of a request get the original response instead of handling the
+/// request again.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyCache {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        Self {
+            ttl: config.ttl,
+            entries: Mutex::new(Entries {
+                entries: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Look up a request by its key. Retries have to have exactly the same
+    /// body as the first request.
+    pub fn begin(self: &Arc<Self>, key: RequestKey, body: &[u8], now: Instant) -> Begin {
+        let fingerprint = fingerprint(body);
+        let mut entries = self.lock();
+        if now.saturating_duration_since(entries.last_pruned) >= PRUNE_INTERVAL {
+            entries.entries.retain(|_, entry| entry.expires > now);
+            entries.last_pruned = now;
+        }
+
+        match entries.entries.get(&key) {
+            Some(entry) if entry.expires > now => {
+                if entry.fingerprint != fingerprint {
+                    return Begin::Mismatch;
+                }
+                return match &entry.state {
+                    State::InProgress => Begin::InProgress,
+                    State::Completed(response) => Begin::Replay(response.clone()),
+                };
+            }
+            _ => {}
+        }
+
+        entries.entries.insert(
+            key.clone(),
+            Entry {
+                fingerprint,
+                state: State::InProgress,
+                expires: now + self.ttl,
+            },
+        );
+
+        Begin::Started(Pending {
+            cache: self.clone(),
+            key: Some(key),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().expect("idempotency lock is poisoned")
+    }
+}
+
+/// A request that is being handled for the first time. If it is dropped
+/// without being completed, for example because the request was cancelled,
+/// the key is released so the request can be retried.
+#[derive(Debug)]
+pub struct Pending {
+    cache: Arc<IdempotencyCache>,
+    key: Option<RequestKey>,
+}
+
+impl Pending {
+    /// Store the response to replay it to retries for the configured time.
+    pub fn complete(mut self, response: StoredResponse, now: Instant) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        if let Some(entry) = self.cache.lock().entries.get_mut(&key) {
+            entry.state = State::Completed(response);
+            entry.expires = now + self.cache.ttl;
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.lock().entries.remove(&key);
+        }
+    }
+}
+
+fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(idempotency_key: &str) -> RequestKey {
+        RequestKey {
+            tenant: Some(TenantId::default()),
+            principal: Some("ci".to_string()),
+            method: Method::POST,
+            path: "/users".to_string(),
+            idempotency_key: idempotency_key.to_string(),
+        }
+    }
+
+    fn response() -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(b"{}"),
+        }
+    }
+
+    fn cache() -> Arc<IdempotencyCache> {
+        Arc::new(IdempotencyCache::new(&IdempotencyConfig {
+            ttl: Duration::from_secs(60),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn retries_are_replayed_until_the_key_expires() {
+        let cache = cache();
+        let now = Instant::now();
+
+        let Begin::Started(pending) = cache.begin(key("a"), b"body", now) else {
+            panic!("expected the first request to start");
+        };
+        assert!(matches!(
+            cache.begin(key("a"), b"body", now),
+            Begin::InProgress
+        ));
+        pending.complete(response(), now);
+
+        match cache.begin(key("a"), b"body", now + Duration::from_secs(59)) {
+            Begin::Replay(replayed) => assert_eq!(response(), replayed),
+            other => panic!("expected a replay, got {other:?}"),
+        }
+        assert!(matches!(
+            cache.begin(key("a"), b"other body", now),
+            Begin::Mismatch
+        ));
+        assert!(matches!(
+            cache.begin(key("a"), b"body", now + Duration::from_secs(60)),
+            Begin::Started(_)
+        ));
+    }
+
+    #[test]
+    fn cancelled_requests_release_the_key() {
+        let cache = cache();
+        let now = Instant::now();
+
+        let started = cache.begin(key("a"), b"body", now);
+        drop(started);
+
+        assert!(matches!(
+            cache.begin(key("a"), b"body", now),
+            Begin::Started(_)
+        ));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_the_tenant_and_principal() {
+        let cache = cache();
+        let now = Instant::now();
+        let other = RequestKey {
+            principal: None,
+            ..key("a")
+        };
+        let other_tenant = RequestKey {
+            tenant: Some(TenantId::parse("acme").unwrap()),
+            ..key("a")
+        };
+
+        let _first = cache.begin(key("a"), b"body", now);
+
+        assert!(matches!(
+            cache.begin(other_tenant, b"body", now),
+            Begin::Started(_)
+        ));
+        assert!(matches!(
+            cache.begin(other, b"body", now),
+            Begin::Started(_)
+        ));
+    }
+}
@@ -1,25 +1,43 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use config::{Config, TelemetryConfig};
 use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::TracerProvider;
 use opentelemetry::KeyValue;
-use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use reload::{LogFilterHandle, Reloader};
+use sampling::SamplingStrategy;
+use std::env;
 use std::io;
-use std::process;
 use std::process::ExitCode;
 use tracing::error;
-use tracing::{debug, error};
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer, Registry};
 use url::Url;
 
+mod audit;
+mod auth;
 mod client;
 mod commands;
+mod config;
+mod db;
+mod extract;
 mod handlers;
+mod idempotency;
+mod metrics;
+mod middleware;
 mod models;
-
-#[derive(Parser)]
+mod precondition;
+mod rate_limit;
+mod reload;
+mod sampling;
+mod state;
+mod tenant;
+mod validation;
+
+#[derive(Clone, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Application {
     #[command(subcommand)]
@@ -33,16 +51,80 @@ struct Application {
     #[clap(long, env)]
     tracing: bool,
 
-    /// Endpoint of the OTLP collector
-    #[clap(long, env, default_value = "http://localhost:4317")]
-    otlp_endpoint: Url,
+    /// Endpoint of the OTLP collector [default: http://localhost:4317]
+    #[clap(long, env)]
+    otlp_endpoint: Option<Url>,
+
+    /// Strategy used to decide which traces are exported [default: always]
+    #[clap(long, env, value_enum)]
+    trace_sampler: Option<SamplingStrategy>,
+
+    /// Fraction of traces that is exported by the ratio based samplers
+    /// [default: 1.0]
+    #[clap(long, env, value_parser = sampling::parse_ratio)]
+    trace_sample_ratio: Option<f64>,
+}
+
+impl Application {
+    /// Load the configuration file of the command, if it has one, and apply
+    /// the environment variables and flags on top of it.
+    fn load_config(&self) -> Result<Config> {
+        let mut config = match &self.command {
+            SubCommands::Audit(_) | SubCommands::Client(_) => Config::default(),
+            SubCommands::Config(args) => match &args.command {
+                commands::config::SubCommand::Check(args) => {
+                    let mut config = Config::load(Some(&args.file))?;
+                    args.overrides.apply(&mut config);
+                    config
+                }
+            },
+            SubCommands::Start(args) => {
+                let mut config = Config::load(args.config.as_deref())?;
+                args.overrides.apply(&mut config);
+                config
+            }
+        };
+
+        self.apply_telemetry(&mut config.telemetry);
+
+        Ok(config)
+    }
+
+    fn apply_telemetry(&self, telemetry: &mut TelemetryConfig) {
+        // `RUST_LOG` takes precedence over the configured filter, like the
+        // other environment variables.
+        if let Ok(log_filter) = env::var(EnvFilter::DEFAULT_ENV) {
+            telemetry.log_filter = log_filter;
+        }
+        if self.json {
+            telemetry.log_json = true;
+        }
+        if self.tracing {
+            telemetry.tracing = true;
+        }
+        if let Some(otlp_endpoint) = &self.otlp_endpoint {
+            telemetry.otlp_endpoint = otlp_endpoint.clone();
+        }
+        if let Some(trace_sampler) = self.trace_sampler {
+            telemetry.trace_sampler = trace_sampler;
+        }
+        if let Some(trace_sample_ratio) = self.trace_sample_ratio {
+            telemetry.trace_sample_ratio = trace_sample_ratio;
+        }
+    }
 }
 
-#[derive(Subcommand)]
+#[derive(Clone, Subcommand)]
 enum SubCommands {
+    /// Verify the audit log of a server
+    Audit(commands::audit::Args),
+
     /// Invoke a server
     Client(commands::client::Args),
 
+    /// Inspect the server configuration
+    Config(commands::config::Args),
+
     /// Start the server
     Start(commands::start::Args),
 }
@@ -51,33 +133,61 @@ enum SubCommands {
 async fn main() -> ExitCode {
     let app = Application::parse();
 
-    let result = init_logging(&app);
-    if let Err(err) = result {
-        error!(%err, "unable to initialize logging");
-        return ExitCode::FAILURE;
-    }
+    // Logging is not initialized yet, so errors can only be printed.
+    let config = match app.load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Unable to load configuration: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let result = match app.command {
+    let log_filter = match init_logging(&config.telemetry) {
+        Ok(log_filter) => log_filter,
+        Err(err) => {
+            eprintln!("Unable to initialize logging: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let tracing = config.telemetry.tracing;
+    let result = match app.command.clone() {
+        SubCommands::Audit(args) => commands::audit::handle_command(args).await,
         SubCommands::Client(args) => commands::client::handle_command(args).await,
-        SubCommands::Start(args) => commands::start::handle_command(args).await,
+        SubCommands::Config(args) => commands::config::handle_command(args, config),
+        SubCommands::Start(args) => {
+            let loader = Box::new(move || app.load_config());
+            let reloader = Reloader::new(loader, config, Some(log_filter));
+            commands::start::handle_command(args, reloader).await
+        }
     };
 
+    // Make sure all pending spans are exported before the process exits. This
+    // blocks until the exporter is done, so it is moved off the runtime.
+    if tracing {
+        let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+    }
+
     if let Err(e) = result {
-        error!("Unable to initialize logging: {:#}", e);
+        error!("Command failed: {:#}", e);
         return ExitCode::FAILURE;
     }
 
     ExitCode::SUCCESS
 }
 
-fn init_logging(app: &Application) -> Result<()> {
-    // The filter layer controls which log levels to display.
-    let filter_layer = EnvFilter::from_default_env();
+/// Initialize logging and tracing. The returned handle can be used to change
+/// the log filter later on.
+fn init_logging(telemetry: &TelemetryConfig) -> Result<LogFilterHandle> {
+    // The filter layer controls which log levels to display. It is wrapped so
+    // it can be replaced when the configuration is reloaded.
+    let filter_layer = EnvFilter::try_new(&telemetry.log_filter).context("invalid log filter")?;
+    let (filter_layer, log_filter) = tracing_subscriber::reload::Layer::new(filter_layer);
 
     // The log layer controls the output of log events to stderr. Depending on the
     // `json` flag, it will either be human readable or json encoded.
     let log_layer = tracing_subscriber::fmt::layer().with_writer(io::stderr);
-    let log_layer = if app.json {
+    let log_layer = if telemetry.log_json {
         log_layer.json().boxed()
     } else {
         log_layer.boxed()
@@ -85,21 +195,39 @@ fn init_logging(app: &Application) -> Result<()> {
 
     // The trace layer will send traces to the configured tracing backend
     // depending on the `tracing` flag.
-    let trace_layer = if false {
-        // This tracer is responsible for sending the actual traces.
-        let tracer = opentelemetry_otlp::new_pipeline()
-            .tracing()
-            .with_exporter(
-                opentelemetry_otlp::new_exporter()
-                    .tonic()
-                    .with_endpoint(app.otlp_endpoint.to_string()),
+    let trace_layer = if telemetry.tracing {
+        // This exporter is responsible for sending the actual traces.
+        let exporter = SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(telemetry.otlp_endpoint.to_string()),
+        )
+        .build_span_exporter()
+        .context("unable to create trace exporter")?;
+
+        let config = trace::config()
+            .with_sampler(
+                telemetry
+                    .trace_sampler
+                    .sampler(telemetry.trace_sample_ratio),
             )
-            .with_trace_config(
-                trace::config()
-                    .with_resource(Resource::new(vec![KeyValue::new("service.name", "api")])),
+            .with_resource(Resource::new(vec![KeyValue::new("service.name", "api")]));
+
+        // The rules based sampler can only decide whether to keep a trace once
+        // it is finished, so the decision is made just before exporting.
+        let provider = trace::TracerProvider::builder().with_config(config);
+        let provider = if telemetry.trace_sampler == SamplingStrategy::Rules {
+            provider.with_batch_exporter(
+                sampling::RuleBasedExporter::new(exporter, telemetry.trace_sample_ratio),
+                opentelemetry::runtime::Tokio,
             )
-            .install_batch(opentelemetry::runtime::Tokio)
-            .context("unable to install tracer: {err}")?;
+        } else {
+            provider.with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+        }
+        .build();
+
+        let tracer = provider.tracer("user_service");
+        opentelemetry::global::set_tracer_provider(provider);
 
         // This layer will take the traces from the `tracing` crate and send
         // them to the tracer specified above.
@@ -115,5 +243,5 @@ fn init_logging(app: &Application) -> Result<()> {
         .try_init()
         .context("unable to initialize logger")?;
 
-    Ok(())
+    Ok(log_filter)
 }
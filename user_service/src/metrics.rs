@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Why a request was shed instead of handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShedReason {
+    /// The server handles as many requests as it is allowed to.
+    GlobalLimit,
+
+    /// The route handles as many requests as it is allowed to.
+    RouteLimit,
+}
+
+impl ShedReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShedReason::GlobalLimit => "global_limit",
+            ShedReason::RouteLimit => "route_limit",
+        }
+    }
+}
+
+/// A monotonically increasing count.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The number of requests that are in flight.
+#[derive(Debug, Default)]
+pub struct InFlight(AtomicUsize);
+
+impl InFlight {
+    /// Count one more request, unless that would exceed `max`. The request is
+    /// counted until the returned guard is dropped.
+    pub fn try_acquire(self: &Arc<Self>, max: Option<usize>) -> Option<InFlightGuard> {
+        let max = max.unwrap_or(usize::MAX);
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < max).then_some(current + 1)
+            })
+            .ok()
+            .map(|_| InFlightGuard(self.clone()))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Counts a request as in flight for as long as it exists.
+#[derive(Debug)]
+pub struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Metrics about the load on the server, exposed in the Prometheus text
+/// format on `/metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    in_flight: Arc<InFlight>,
+    route_in_flight: Mutex<BTreeMap<String, Arc<InFlight>>>,
+    requests: Mutex<BTreeMap<(String, String, u16), Arc<Counter>>>,
+    shed: Mutex<BTreeMap<(ShedReason, String, String), Arc<Counter>>>,
+    timed_out: Mutex<BTreeMap<(String, String), Arc<Counter>>>,
+}
+
+impl Metrics {
+    /// The requests in flight on the whole server.
+    pub fn in_flight(&self) -> &Arc<InFlight> {
+        &self.in_flight
+    }
+
+    /// The requests in flight on a single route.
+    pub fn route_in_flight(&self, route: &str) -> Arc<InFlight> {
+        get_or_default(&self.route_in_flight, route.to_string())
+    }
+
+    /// Count a request of a tenant that was handled, with the status of its
+    /// response.
+    pub fn record_request(&self, tenant: &str, route: &str, status: u16) {
+        get_or_default(
+            &self.requests,
+            (tenant.to_string(), route.to_string(), status),
+        )
+        .increment();
+    }
+
+    pub fn record_shed(&self, reason: ShedReason, route: &str, tenant: &str) {
+        get_or_default(&self.shed, (reason, route.to_string(), tenant.to_string())).increment();
+    }
+
+    pub fn record_timeout(&self, route: &str, tenant: &str) {
+        get_or_default(&self.timed_out, (route.to_string(), tenant.to_string())).increment();
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "user_service_requests_in_flight",
+            "gauge",
+            "Requests that are currently being handled.",
+        );
+        let _ = writeln!(
+            out,
+            "user_service_requests_in_flight {}",
+            self.in_flight.get()
+        );
+
+        header(
+            &mut out,
+            "user_service_route_requests_in_flight",
+            "gauge",
+            "Requests that are currently being handled, per route.",
+        );
+        for (route, in_flight) in lock(&self.route_in_flight).iter() {
+            let _ = writeln!(
+                out,
+                "user_service_route_requests_in_flight{{route=\"{}\"}} {}",
+                escape(route),
+                in_flight.get()
+            );
+        }
+
+        header(
+            &mut out,
+            "user_service_requests_total",
+            "counter",
+            "Requests that were handled, per tenant, route and status.",
+        );
+        for ((tenant, route, status), count) in lock(&self.requests).iter() {
+            let _ = writeln!(
+                out,
+                "user_service_requests_total{{route=\"{}\",status=\"{}\",tenant=\"{}\"}} {}",
+                escape(route),
+                status,
+                escape(tenant),
+                count.get()
+            );
+        }
+
+        header(
+            &mut out,
+            "user_service_requests_shed_total",
+            "counter",
+            "Requests that were rejected because the server was overloaded.",
+        );
+        for ((reason, route, tenant), count) in lock(&self.shed).iter() {
+            let _ = writeln!(
+                out,
+                "user_service_requests_shed_total{{reason=\"{}\",route=\"{}\",tenant=\"{}\"}} {}",
+                reason.as_str(),
+                escape(route),
+                escape(tenant),
+                count.get()
+            );
+        }
+
+        header(
+            &mut out,
+            "user_service_requests_timed_out_total",
+            "counter",
+            "Requests that did not finish within their timeout.",
+        );
+        for ((route, tenant), count) in lock(&self.timed_out).iter() {
+            let _ = writeln!(
+                out,
+                "user_service_requests_timed_out_total{{route=\"{}\",tenant=\"{}\"}} {}",
+                escape(route),
+                escape(tenant),
+                count.get()
+            );
+        }
+
+        out
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().expect("metrics lock is poisoned")
+}
+
+fn get_or_default<K: Ord, V: Default>(map: &Mutex<BTreeMap<K, Arc<V>>>, key: K) -> Arc<V> {
+    lock(map).entry(key).or_default().clone()
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escape a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn in_flight_is_limited() {
+        let in_flight = Arc::new(InFlight::default());
+
+        let first = in_flight.try_acquire(Some(1));
+        assert!(first.is_some());
+        assert!(in_flight.try_acquire(Some(1)).is_none());
+
+        drop(first);
+        assert_eq!(0, in_flight.get());
+        assert!(in_flight.try_acquire(Some(1)).is_some());
+    }
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::default();
+        let _guard = metrics.route_in_flight("/users").try_acquire(None);
+        metrics.record_request("acme", "/users", 201);
+        metrics.record_shed(ShedReason::GlobalLimit, "/users", "acme");
+        metrics.record_timeout("/users/:user_name", "acme");
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("user_service_requests_in_flight 0\n"));
+        assert!(rendered.contains("user_service_route_requests_in_flight{route=\"/users\"} 1\n"));
+        assert!(rendered.contains(
+            "user_service_requests_total{route=\"/users\",status=\"201\",tenant=\"acme\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "user_service_requests_shed_total{reason=\"global_limit\",route=\"/users\",tenant=\"acme\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "user_service_requests_timed_out_total{route=\"/users/:user_name\",tenant=\"acme\"} 1\n"
+        ));
+    }
+}
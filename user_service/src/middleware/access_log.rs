@@ -0,0 +1,256 @@
+use crate::auth::Principal;
+use crate::models::RequestId;
+use crate::tenant::TenantId;
+use axum::body::HttpBody;
+use axum::extract::MatchedPath;
+use axum::response::Response;
+use http::header::{CONTENT_LENGTH, USER_AGENT};
+use http::{HeaderMap, Request};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+use tracing::info;
+
+/// Layer that emits a single structured log event for every request, once
+/// the response is available.
+#[derive(Clone, Debug, Default)]
+pub struct AccessLogLayer {
+    skip_paths: Arc<Vec<String>>,
+}
+
+impl AccessLogLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Do not log requests for these paths, for example health checks that
+    /// would otherwise drown out all other requests.
+    pub fn skip_paths(mut self, paths: impl IntoIterator<Item = String>) -> Self {
+        self.skip_paths = Arc::new(paths.into_iter().collect());
+        self
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLog {
+            inner,
+            skip_paths: self.skip_paths.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AccessLog<S> {
+    inner: S,
+    skip_paths: Arc<Vec<String>>,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for AccessLog<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ResBody: HttpBody,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if self.skip_paths.iter().any(|path| path == req.uri().path()) {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let entry = AccessLogEntry::from_request(&req);
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            if let Ok(response) = &result {
+                entry.log(response);
+            }
+            result
+        })
+    }
+}
+
+/// The details of a request that are needed to log it after the response has
+/// been produced.
+struct AccessLogEntry {
+    start: Instant,
+    method: String,
+    route: Option<String>,
+    request_id: Option<String>,
+    user_agent: Option<String>,
+    bytes_in: Option<u64>,
+}
+
+impl AccessLogEntry {
+    fn from_request<B>(req: &Request<B>) -> Self {
+        let headers = req.headers();
+        Self {
+            start: Instant::now(),
+            method: req.method().to_string(),
+            route: req
+                .extensions()
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_string()),
+            request_id: req
+                .extensions()
+                .get::<RequestId>()
+                .map(|request_id| request_id.0.clone()),
+            user_agent: header_str(headers, USER_AGENT.as_str()),
+            bytes_in: content_length(headers),
+        }
+    }
+
+    fn log<B: HttpBody>(self, response: &Response<B>) {
+        let bytes_out = response
+            .body()
+            .size_hint()
+            .exact()
+            .or_else(|| content_length(response.headers()));
+        let principal = response
+            .extensions()
+            .get::<Principal>()
+            .map(|principal| principal.0.as_str());
+        let tenant = response
+            .extensions()
+            .get::<TenantId>()
+            .map(TenantId::as_str);
+
+        info!(
+            target: "access_log",
+            method = %self.method,
+            route = self.route.as_deref().unwrap_or("-"),
+            status = response.status().as_u16(),
+            latency_ms = self.start.elapsed().as_secs_f64() * 1000.0,
+            bytes_in = self.bytes_in.unwrap_or(0),
+            bytes_out = bytes_out.unwrap_or(0),
+            principal = principal.unwrap_or("-"),
+            tenant = tenant.unwrap_or("-"),
+            request_id = self.request_id.as_deref().unwrap_or("-"),
+            user_agent = self.user_agent.as_deref().unwrap_or("-"),
+            "request completed"
+        );
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context as LayerContext, SubscriberExt};
+
+    type Fields = BTreeMap<String, String>;
+
+    /// Records the fields of every access log event.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<Fields>>>);
+
+    impl<S: Subscriber> tracing_subscriber::Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+            if event.metadata().target() != "access_log" {
+                return;
+            }
+            let mut fields = Fields::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            self.0.lock().unwrap().push(fields);
+        }
+    }
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/users/:user_name", get(|| async { "jane" }))
+            .route("/healthz", get(|| async { "ok" }))
+            .layer(AccessLogLayer::new().skip_paths(["/healthz".to_string()]))
+    }
+
+    async fn access_log(req: Request<Body>) -> Vec<Fields> {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        app().oneshot(req).await.unwrap();
+
+        let events = capture.0.lock().unwrap().clone();
+        events
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_with_their_route() {
+        let req = Request::builder()
+            .method("GET")
+            .uri("/users/jane")
+            .header(USER_AGENT, "test/1.0")
+            .body(Body::empty())
+            .unwrap();
+
+        let events = access_log(req).await;
+
+        assert_eq!(1, events.len());
+        let fields = &events[0];
+        assert_eq!("GET", fields["method"]);
+        assert_eq!("/users/:user_name", fields["route"]);
+        assert_eq!("200", fields["status"]);
+        assert_eq!("4", fields["bytes_out"]);
+        assert_eq!("-", fields["principal"]);
+        assert_eq!("test/1.0", fields["user_agent"]);
+        assert!(fields.contains_key("latency_ms"));
+    }
+
+    #[tokio::test]
+    async fn skipped_paths_are_not_logged() {
+        let req = Request::builder()
+            .uri("/healthz")
+            .body(Body::empty())
+            .unwrap();
+
+        assert!(access_log(req).await.is_empty());
+    }
+}
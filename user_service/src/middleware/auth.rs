@@ -0,0 +1,673 @@
+use crate::audit;
+use crate::audit::RecentDenials;
+use crate::auth::Principal;
+use crate::db::{Store, StoreError, TenantStore};
+use crate::middleware::rate_limit::client_ip;
+use crate::models::{AuditAction, AuditEvent, AuthError, HandlerError, Role, User, UserStatus};
+use crate::reload::{Settings, SharedSettings};
+use crate::tenant::{TenantId, TenantResolver};
+use axum::response::{IntoResponse, Response};
+use http::header::AUTHORIZATION;
+use http::Request;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{error, Span};
+
+/// The address of the client of a request, taking trusted proxies into
+/// account. [`AuthLayer`] stores it in the request extensions, so rejections
+/// further in can be told apart by client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Layer that authenticates every request with its API key and determines
+/// the tenant it is made for.
+///
+/// Requests with an unknown API key, or without one if authentication is
+/// required, are rejected, as are requests without a valid tenant and
+/// requests with an API key of another tenant. The principal and tenant of
+/// accepted requests are stored in the request extensions for the handlers,
+/// and in the response extensions for the middleware wrapping this layer.
+/// The tenant is also recorded on the span of the request.
+///
+/// Principals that are users of the tenant, because their name is a
+/// username, are only allowed while the user is active. Requests of
+/// suspended or locked users are rejected.
+///
+/// Rejected requests are recorded in the audit log of the tenant they name,
+/// if they name a valid one, even if their API key belongs to another tenant.
+/// Anyone can send requests that are rejected, so a rejection is recorded at
+/// most once a minute per tenant, client address, principal and reason. The
+/// audit log then grows by at most one event a minute for every client
+/// address that keeps being rejected, and remembering the recent rejections
+/// takes memory for every such address seen in the last two minutes. Rate
+/// limiting bounds how fast a single address can cause either. The recent
+/// rejections are shared with [`RequireRoleLayer`] and the handlers.
+///
+/// The API keys are taken from the current settings for every request, so
+/// they can be changed by reloading the configuration.
+#[derive(Clone)]
+pub struct AuthLayer {
+    settings: SharedSettings,
+    store: Arc<dyn Store>,
+    tenants: Arc<TenantResolver>,
+    denials: Arc<RecentDenials>,
+}
+
+impl AuthLayer {
+    pub fn new(
+        settings: SharedSettings,
+        store: Arc<dyn Store>,
+        tenants: TenantResolver,
+        denials: Arc<RecentDenials>,
+    ) -> Self {
+        Self {
+            settings,
+            store,
+            tenants: Arc::new(tenants),
+            denials,
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            settings: self.settings.clone(),
+            store: self.store.clone(),
+            tenants: self.tenants.clone(),
+            denials: self.denials.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth<S> {
+    inner: S,
+    settings: SharedSettings,
+    store: Arc<dyn Store>,
+    tenants: Arc<TenantResolver>,
+    denials: Arc<RecentDenials>,
+}
+
+impl<S, B> Service<Request<B>> for Auth<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let authorization = req
+            .headers()
+            .get(AUTHORIZATION)
+            .map(|value| value.to_str().unwrap_or_default());
+
+        let settings = self.settings.load();
+        let credentials = match settings.authenticator.authenticate(authorization) {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                let log = self.denial_log(&req, &settings);
+                return Box::pin(reject(log, AuditAction::AuthFailed, None, None, err));
+            }
+        };
+        let key_tenant = credentials
+            .as_ref()
+            .and_then(|credentials| credentials.tenant.as_ref());
+        let tenant = match self.tenants.resolve(&req, key_tenant) {
+            Ok(tenant) => tenant,
+            Err(err) => {
+                let log = self.denial_log(&req, &settings);
+                let principal = credentials.map(|credentials| credentials.principal);
+                return Box::pin(reject(log, AuditAction::AuthDenied, principal, None, err));
+            }
+        };
+        Span::current().record("tenant", tenant.as_str());
+
+        let principal = credentials.map(|credentials| credentials.principal);
+
+        let lookup_key = principal
+            .as_ref()
+            .map(|principal| settings.validator.username_keys(&principal.0).lookup);
+        if let Some(principal) = &principal {
+            req.extensions_mut().insert(principal.clone());
+        }
+        req.extensions_mut().insert(tenant.clone());
+        let client = client_ip(&settings, &req);
+        req.extensions_mut().insert(ClientIp(client));
+        let denials = self.denials.clone();
+
+        // The service that was polled ready has to handle the request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let store = self.store.tenant(&tenant);
+        Box::pin(async move {
+            if let Some(lookup_key) = lookup_key {
+                match store.get_user(&lookup_key).await {
+                    Ok(Some(user)) if user.status != UserStatus::Active => {
+                        let err = AuthError::InactiveAccount {
+                            status: user.status,
+                        };
+                        let log = DenialLog {
+                            store,
+                            tenant: tenant.clone(),
+                            client,
+                            denials,
+                        };
+                        let action = AuditAction::AuthDenied;
+                        return reject(Some(log), action, principal, Some(&user), err).await;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        error!(%err, "Unable to check the status of the principal");
+                        return Ok(HandlerError::<AuthError>::StoreUnavailable.into_response());
+                    }
+                }
+            }
+
+            let mut response = inner.call(req).await?;
+            if let Some(principal) = principal {
+                response.extensions_mut().insert::<Principal>(principal);
+            }
+            response.extensions_mut().insert(tenant);
+            Ok(response)
+        })
+    }
+}
+
+impl<S> Auth<S> {
+    /// The audit log of the tenant a rejected request names, ignoring its
+    /// API key, so the rejection can be audited by the tenant it was made
+    /// for. Requests that do not name a valid tenant are not audited
+    /// anywhere.
+    fn denial_log<B>(&self, req: &Request<B>, settings: &Settings) -> Option<DenialLog> {
+        let tenant = self.tenants.resolve(req, None).ok()?;
+        Some(DenialLog {
+            store: self.store.tenant(&tenant),
+            tenant,
+            client: client_ip(settings, req),
+            denials: self.denials.clone(),
+        })
+    }
+}
+
+/// Where the rejection of a request is recorded, and for which client.
+struct DenialLog {
+    store: Arc<dyn TenantStore>,
+    tenant: TenantId,
+    client: IpAddr,
+    denials: Arc<RecentDenials>,
+}
+
+/// Reject a request with `err`, recording the rejection in the audit log of
+/// the tenant, if it is known. `user` is the user the principal of the
+/// request is, if it is one.
+async fn reject<E>(
+    log: Option<DenialLog>,
+    action: AuditAction,
+    principal: Option<Principal>,
+    user: Option<&User>,
+    err: AuthError,
+) -> Result<Response, E> {
+    if let Some(log) = log {
+        let event = AuditEvent {
+            target: user.map(|user| user.username.clone()),
+            target_id: user.map(|user| user.id),
+            reason: Some(err.to_string()),
+            ..audit::event(action, audit::actor(principal.as_ref()))
+        };
+        audit::record_denial(&*log.store, &log.denials, &log.tenant, log.client, event).await;
+    }
+
+    Ok(err.into_response())
+}
+
+/// Layer that only lets a request through if its principal has a role that
+/// grants `role`, when roles are enforced.
+///
+/// The principal is the one [`AuthLayer`] stored in the request, so this has
+/// to be wrapped by it. If the principal is a user of the tenant of the
+/// request, the roles of its groups count too; they are looked up for every
+/// request, so changes to groups apply immediately. Rejected requests are
+/// recorded in the audit log of the tenant, like those of [`AuthLayer`] at
+/// most once a minute per client, principal and reason.
+#[derive(Clone)]
+pub struct RequireRoleLayer {
+    role: Role,
+    settings: SharedSettings,
+    store: Arc<dyn Store>,
+    denials: Arc<RecentDenials>,
+}
+
+impl RequireRoleLayer {
+    pub fn new(
+        role: Role,
+        settings: SharedSettings,
+        store: Arc<dyn Store>,
+        denials: Arc<RecentDenials>,
+    ) -> Self {
+        Self {
+            role,
+            settings,
+            store,
+            denials,
+        }
+    }
+}
+
+impl<S> Layer<S> for RequireRoleLayer {
+    type Service = RequireRole<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireRole {
+            inner,
+            role: self.role,
+            settings: self.settings.clone(),
+            store: self.store.clone(),
+            denials: self.denials.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireRole<S> {
+    inner: S,
+    role: Role,
+    settings: SharedSettings,
+    store: Arc<dyn Store>,
+    denials: Arc<RecentDenials>,
+}
+
+impl<S, B> Service<Request<B>> for RequireRole<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let settings = self.settings.load_full();
+        if !settings.authorizer.is_enforced() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let Some(tenant) = req.extensions().get::<TenantId>() else {
+            return Box::pin(async move { Ok(AuthError::MissingTenant.into_response()) });
+        };
+        let tenant = tenant.clone();
+        let store = self.store.tenant(&tenant);
+        let principal = req.extensions().get::<Principal>().cloned();
+        let client = req
+            .extensions()
+            .get::<ClientIp>()
+            .map_or(IpAddr::from(Ipv4Addr::UNSPECIFIED), |client| client.0);
+        let denials = self.denials.clone();
+        let role = self.role;
+        // The service that was polled ready has to handle the request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let group_roles = match &principal {
+                Some(principal) => {
+                    let lookup_key = settings.validator.username_keys(&principal.0).lookup;
+                    match group_roles(&*store, &lookup_key).await {
+                        Ok(roles) => roles,
+                        Err(err) => {
+                            error!(%err, "Unable to look up the roles of the principal");
+                            return Ok(HandlerError::<AuthError>::StoreUnavailable.into_response());
+                        }
+                    }
+                }
+                None => Vec::new(),
+            };
+
+            if !settings
+                .authorizer
+                .allows(principal.as_ref(), &group_roles, role)
+            {
+                let event = AuditEvent {
+                    reason: Some(format!("missing role {role}")),
+                    ..audit::event(AuditAction::AuthDenied, audit::actor(principal.as_ref()))
+                };
+                audit::record_denial(&*store, &denials, &tenant, client, event).await;
+                return Ok(AuthError::Unauthorized.into_response());
+            }
+
+            inner.call(req).await
+        })
+    }
+}
+
+/// The roles granted by the groups of the user with the given lookup key, if
+/// there is one.
+pub async fn group_roles(
+    store: &dyn TenantStore,
+    lookup_key: &str,
+) -> Result<Vec<Role>, StoreError> {
+    match store.get_user(lookup_key).await? {
+        Some(user) => store.user_roles(user.id).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ApiKeyConfig, Config, Secret, TenantSource};
+    use crate::db::memory::MemoryStore;
+    use crate::middleware::rate_limit::ClientAddr;
+    use crate::models::{Attributes, NewGroup, NewUser, TENANT_HEADER};
+    use crate::reload::Settings;
+    use arc_swap::ArcSwap;
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::routing::post;
+    use axum::Router;
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    fn request(api_key: Option<&str>) -> Request<Body> {
+        tenant_request(api_key, None)
+    }
+
+    fn tenant_request(api_key: Option<&str>, tenant: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().method("POST").uri("/users");
+        if let Some(api_key) = api_key {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {api_key}"));
+        }
+        if let Some(tenant) = tenant {
+            builder = builder.header(TENANT_HEADER, tenant);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    fn api_key(principal: &str, roles: Vec<Role>, tenant: Option<&str>) -> ApiKeyConfig {
+        ApiKeyConfig {
+            principal: principal.to_string(),
+            key: Secret::new(principal),
+            roles,
+            tenant: tenant.map(|tenant| TenantId::parse(tenant).unwrap()),
+        }
+    }
+
+    async fn create_user(store: &dyn TenantStore, settings: &SharedSettings, username: &str) {
+        let new_user = NewUser {
+            username: username.to_string(),
+            name: "Jane".to_string(),
+            email: None,
+            attributes: Attributes::new(),
+        };
+        let keys = settings.load().validator.username_keys(username);
+        store.create_user(new_user, keys).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn changes_require_a_role_of_the_principal_or_its_groups() {
+        let mut config = Config::default();
+        config.auth.enforce_roles = true;
+        config.auth.api_keys = vec![
+            api_key("ci", vec![Role::UserAdmin], None),
+            api_key("jane", vec![], None),
+        ];
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let tenant = store.tenant(&TenantId::default());
+        create_user(&*tenant, &settings, "jane").await;
+        let app = Router::new()
+            .route(
+                "/users",
+                post(|| async { "ok" }).layer(RequireRoleLayer::new(
+                    Role::UserAdmin,
+                    settings.clone(),
+                    store.clone(),
+                    Arc::default(),
+                )),
+            )
+            .layer(AuthLayer::new(
+                settings,
+                store.clone(),
+                TenantResolver::new(&config.tenancy),
+                Arc::default(),
+            ));
+        let status = |api_key| {
+            let app = app.clone();
+            async move { app.oneshot(request(api_key)).await.unwrap().status() }
+        };
+
+        assert_eq!(StatusCode::FORBIDDEN, status(None).await);
+        assert_eq!(StatusCode::OK, status(Some("ci")).await);
+        assert_eq!(StatusCode::FORBIDDEN, status(Some("jane")).await);
+
+        tenant
+            .create_group(NewGroup {
+                name: "support".to_string(),
+                description: None,
+                roles: vec![Role::UserAdmin],
+            })
+            .await
+            .unwrap();
+        let jane = tenant.get_user("jane").await.unwrap().unwrap();
+        tenant.add_member("support", jane.id).await.unwrap();
+
+        assert_eq!(StatusCode::OK, status(Some("jane")).await);
+    }
+
+    #[tokio::test]
+    async fn requests_only_reach_their_own_tenant() {
+        let mut config = Config::default();
+        config.tenancy.source = TenantSource::Header;
+        config.auth.enforce_roles = true;
+        config.auth.api_keys = vec![
+            api_key("ci", vec![Role::UserAdmin], Some("acme")),
+            api_key("jane", vec![], None),
+        ];
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let acme = store.tenant(&TenantId::parse("acme").unwrap());
+        create_user(&*acme, &settings, "jane").await;
+        acme.create_group(NewGroup {
+            name: "support".to_string(),
+            description: None,
+            roles: vec![Role::UserAdmin],
+        })
+        .await
+        .unwrap();
+        let jane = acme.get_user("jane").await.unwrap().unwrap();
+        acme.add_member("support", jane.id).await.unwrap();
+        let app = Router::new()
+            .route(
+                "/users",
+                post(|| async { "ok" }).layer(RequireRoleLayer::new(
+                    Role::UserAdmin,
+                    settings.clone(),
+                    store.clone(),
+                    Arc::default(),
+                )),
+            )
+            .layer(AuthLayer::new(
+                settings,
+                store.clone(),
+                TenantResolver::new(&config.tenancy),
+                Arc::default(),
+            ));
+        let status = |api_key, tenant| {
+            let app = app.clone();
+            async move {
+                app.oneshot(tenant_request(api_key, tenant))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(StatusCode::BAD_REQUEST, status(Some("ci"), None).await);
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            status(Some("ci"), Some("ac me")).await
+        );
+        assert_eq!(StatusCode::OK, status(Some("ci"), Some("acme")).await);
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            status(Some("ci"), Some("globex")).await
+        );
+        // Jane only has a role through her group in acme.
+        assert_eq!(StatusCode::OK, status(Some("jane"), Some("acme")).await);
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            status(Some("jane"), Some("globex")).await
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_are_audited_by_their_tenant() {
+        let mut config = Config::default();
+        config.tenancy.source = TenantSource::Header;
+        config.auth.enforce_roles = true;
+        config.auth.api_keys = vec![
+            api_key("ci", vec![Role::UserAdmin], Some("acme")),
+            api_key("jane", vec![], None),
+        ];
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let app = Router::new()
+            .route(
+                "/users",
+                post(|| async { "ok" }).layer(RequireRoleLayer::new(
+                    Role::UserAdmin,
+                    settings.clone(),
+                    store.clone(),
+                    Arc::default(),
+                )),
+            )
+            .layer(AuthLayer::new(
+                settings,
+                store.clone(),
+                TenantResolver::new(&config.tenancy),
+                Arc::default(),
+            ));
+
+        for (api_key, tenant) in [
+            (Some("unknown"), Some("globex")),
+            (Some("ci"), Some("globex")),
+            (Some("jane"), Some("globex")),
+            (Some("ci"), None),
+            (Some("ci"), Some("acme")),
+        ] {
+            let request = tenant_request(api_key, tenant);
+            app.clone().oneshot(request).await.unwrap();
+        }
+        let events = |tenant| {
+            let store = store.tenant(&TenantId::parse(tenant).unwrap());
+            async move {
+                let events = store
+                    .audit_events(&Default::default(), None, 10)
+                    .await
+                    .unwrap();
+                events
+                    .into_iter()
+                    .map(|event| (event.action, event.actor, event.reason.unwrap()))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            vec![
+                (
+                    AuditAction::AuthFailed,
+                    "anonymous".to_string(),
+                    "unauthenticated".to_string()
+                ),
+                (
+                    AuditAction::AuthDenied,
+                    "ci".to_string(),
+                    "API key does not belong to the tenant".to_string()
+                ),
+                (
+                    AuditAction::AuthDenied,
+                    "jane".to_string(),
+                    "missing role user_admin".to_string()
+                ),
+            ],
+            events("globex").await
+        );
+        assert!(events("acme").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn repeated_rejections_are_recorded_once_per_client() {
+        let mut config = Config::default();
+        config.tenancy.source = TenantSource::Header;
+        config.auth.enforce_roles = true;
+        config.auth.api_keys = vec![api_key("jane", vec![], None)];
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let denials = Arc::new(RecentDenials::new());
+        let app = Router::new()
+            .route(
+                "/users",
+                post(|| async { "ok" }).layer(RequireRoleLayer::new(
+                    Role::UserAdmin,
+                    settings.clone(),
+                    store.clone(),
+                    denials.clone(),
+                )),
+            )
+            .layer(AuthLayer::new(
+                settings,
+                store.clone(),
+                TenantResolver::new(&config.tenancy),
+                denials,
+            ));
+
+        for (api_key, client, expected) in [
+            ("guess-1", "192.0.2.1:4000", StatusCode::UNAUTHORIZED),
+            ("guess-2", "192.0.2.1:4001", StatusCode::UNAUTHORIZED),
+            ("guess-3", "192.0.2.2:4000", StatusCode::UNAUTHORIZED),
+            ("jane", "192.0.2.1:4000", StatusCode::FORBIDDEN),
+            ("jane", "192.0.2.1:4002", StatusCode::FORBIDDEN),
+        ] {
+            let mut request = tenant_request(Some(api_key), Some("globex"));
+            let client = ClientAddr(client.parse().unwrap());
+            request.extensions_mut().insert(ConnectInfo(client));
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(expected, response.status());
+        }
+        let globex = store.tenant(&TenantId::parse("globex").unwrap());
+        let events = globex
+            .audit_events(&Default::default(), None, 10)
+            .await
+            .unwrap();
+        let recorded: Vec<_> = events
+            .iter()
+            .map(|event| (event.action, event.actor.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (AuditAction::AuthFailed, "anonymous"),
+                (AuditAction::AuthFailed, "anonymous"),
+                (AuditAction::AuthDenied, "jane"),
+            ],
+            recorded
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::config::CorsConfig;
+use crate::models::{IDEMPOTENT_REPLAYED_HEADER, REQUEST_ID_HEADER};
+use crate::reload::SharedSettings;
+use anyhow::{bail, Context, Result};
+use http::header::ETAG;
+use http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Create the CORS layer from the configuration.
+///
+/// In permissive development mode every origin, method and header is allowed.
+/// Otherwise only the configured origins are allowed; they are taken from the
+/// current settings for every request, so they can be changed by reloading
+/// the configuration.
+pub fn cors_layer(config: &CorsConfig, settings: SharedSettings) -> Result<CorsLayer> {
+    if config.dev_permissive {
+        return Ok(CorsLayer::very_permissive());
+    }
+
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| method.parse::<Method>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid CORS method")?;
+    let headers = config
+        .allowed_headers
+        .iter()
+        .map(|header| header.parse::<HeaderName>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid CORS header")?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            settings.load().cors_origins.matches(origin)
+        }))
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(config.allow_credentials)
+        .expose_headers([
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER),
+            ETAG,
+        ])
+        .max_age(config.max_age))
+}
+
+/// The origins that are allowed to make cross-origin requests.
+#[derive(Clone, Debug, Default)]
+pub struct OriginMatcher {
+    patterns: Vec<OriginPattern>,
+}
+
+impl OriginMatcher {
+    /// Create a matcher from the configured origins. These have been validated
+    /// together with the rest of the configuration, invalid ones are ignored.
+    pub fn new(origins: &[String]) -> Self {
+        let patterns = origins
+            .iter()
+            .filter_map(|origin| OriginPattern::parse(origin).ok())
+            .collect();
+
+        Self { patterns }
+    }
+
+    pub fn matches(&self, origin: &HeaderValue) -> bool {
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        let origin = origin.to_ascii_lowercase();
+
+        self.patterns.iter().any(|pattern| pattern.matches(&origin))
+    }
+}
+
+/// An allowed origin: either an exact origin like `https://example.com`, or a
+/// pattern like `https://*.example.com` that allows every subdomain (but not
+/// `https://example.com` itself).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginPattern {
+    Exact(String),
+    Subdomains { scheme: String, parent: String },
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.to_ascii_lowercase();
+        let Some((scheme, host)) = pattern.split_once("://") else {
+            bail!("CORS origin {pattern:?} has no scheme");
+        };
+
+        if scheme.is_empty() || host.is_empty() || host.contains(['/', '?', '#', '@']) {
+            bail!("CORS origin {pattern:?} has to be of the form scheme://host[:port]");
+        }
+
+        match host.strip_prefix("*.") {
+            Some(parent) if !parent.is_empty() && !parent.contains('*') => {
+                Ok(OriginPattern::Subdomains {
+                    scheme: scheme.to_string(),
+                    parent: format!(".{parent}"),
+                })
+            }
+            None if !host.contains('*') => Ok(OriginPattern::Exact(pattern)),
+            _ => bail!("CORS origin {pattern:?} may only use a wildcard as its first label"),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Exact(exact) => exact == origin,
+            OriginPattern::Subdomains { scheme, parent } => {
+                let Some(host) = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                else {
+                    return false;
+                };
+
+                host.strip_suffix(parent.as_str())
+                    .map(|subdomain| {
+                        !subdomain.is_empty()
+                            && subdomain
+                                .bytes()
+                                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+                    })
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_origin_pattern() {
+        assert_eq!(
+            OriginPattern::Exact("https://example.com".to_string()),
+            OriginPattern::parse("https://Example.com").unwrap()
+        );
+        assert_eq!(
+            OriginPattern::Subdomains {
+                scheme: "https".to_string(),
+                parent: ".example.com:8443".to_string()
+            },
+            OriginPattern::parse("https://*.example.com:8443").unwrap()
+        );
+
+        for invalid in [
+            "*",
+            "example.com",
+            "https://",
+            "https://example.com/path",
+            "https://*",
+            "https://api.*.example.com",
+            "https://*.*.example.com",
+        ] {
+            assert!(OriginPattern::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn match_origins() {
+        let matcher = OriginMatcher::new(&[
+            "https://app.example.com".to_string(),
+            "https://*.example.org".to_string(),
+        ]);
+
+        let tests = vec![
+            ("https://app.example.com", true),
+            ("https://APP.example.com", true),
+            ("http://app.example.com", false),
+            ("https://other.example.com", false),
+            ("https://a.example.org", true),
+            ("https://a.b.example.org", true),
+            ("https://example.org", false),
+            ("https://evil.com/.example.org", false),
+            ("https://evilexample.org", false),
+            ("https://a.example.org:8443", false),
+        ];
+
+        for (origin, expected) in tests {
+            let origin = HeaderValue::from_static(origin);
+
+            assert_eq!(expected, matcher.matches(&origin), "{origin:?}");
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::models::{ErrorFormat, CURRENT_ERROR_FORMAT, PROBLEM_JSON};
+use axum::response::Response;
+use http::header::ACCEPT;
+use http::{HeaderMap, Request};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Layer that picks the format of error responses from the `Accept` header.
+///
+/// Errors are rendered as RFC 7807 problem details if the client accepts
+/// `application/problem+json`, and in the tagged `{"error", "details"}`
+/// format otherwise. The format is made available to error responses through
+/// [`ErrorFormat::current`].
+#[derive(Clone, Debug, Default)]
+pub struct ErrorFormatLayer {}
+
+impl ErrorFormatLayer {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<S> Layer<S> for ErrorFormatLayer {
+    type Service = ErrorFormatService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ErrorFormatService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ErrorFormatService<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for ErrorFormatService<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let format = if accepts_problem_json(req.headers()) {
+            ErrorFormat::Problem {
+                instance: req.uri().path().to_string(),
+            }
+        } else {
+            ErrorFormat::Tagged
+        };
+
+        Box::pin(CURRENT_ERROR_FORMAT.scope(format, self.inner.call(req)))
+    }
+}
+
+/// Whether one of the media ranges in the `Accept` headers is
+/// `application/problem+json` with a non-zero quality.
+fn accepts_problem_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let rejected = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+
+            media_type.eq_ignore_ascii_case(PROBLEM_JSON) && !rejected
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::GetUserError;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use http::header::CONTENT_TYPE;
+    use tower::ServiceExt;
+
+    #[test]
+    fn accept_problem_json() {
+        let tests = vec![
+            (vec!["application/problem+json"], true),
+            (
+                vec!["application/json, application/problem+json;q=0.5"],
+                true,
+            ),
+            (vec!["application/json", "Application/Problem+JSON"], true),
+            (vec!["application/problem+json;q=0"], false),
+            (vec!["application/json"], false),
+            (vec![], false),
+        ];
+
+        for (accept, expected) in tests {
+            let mut headers = HeaderMap::new();
+            for value in &accept {
+                headers.append(ACCEPT, value.parse().unwrap());
+            }
+
+            assert_eq!(expected, accepts_problem_json(&headers), "{accept:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_are_rendered_as_problem_details() {
+        let app = Router::new()
+            .route(
+                "/users/:username",
+                get(|| async {
+                    Err::<(), _>(GetUserError::UserNotFound {
+                        username: "jane".to_string(),
+                    })
+                }),
+            )
+            .layer(ErrorFormatLayer::new());
+        let req = Request::builder()
+            .uri("/users/jane")
+            .header(ACCEPT, PROBLEM_JSON)
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(PROBLEM_JSON, response.headers()[CONTENT_TYPE]);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "type": "urn:user-service:problem:UserNotFound",
+                "title": "User not found",
+                "status": 404,
+                "detail": "user was not found: jane",
+                "instance": "/users/jane",
+                "username": "jane",
+            }),
+            body
+        );
+    }
+}
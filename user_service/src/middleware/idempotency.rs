@@ -0,0 +1,266 @@
+use crate::auth::Principal;
+use crate::extract::JsonConfig;
+use crate::idempotency::{Begin, IdempotencyCache, RequestKey, StoredResponse};
+use crate::models::{
+    IdempotencyError, JsonBodyError, IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER,
+};
+use crate::tenant::TenantId;
+use axum::body::{Body, Bytes};
+use axum::response::{IntoResponse, Response};
+use axum::RequestExt;
+use http::request::Parts;
+use http::{HeaderName, HeaderValue, Request};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+use tracing::debug;
+
+/// Layer that makes requests with an `Idempotency-Key` header safe to retry.
+///
+/// The response to the first request with a key is stored, and replayed to
+/// every retry with the same key and body, marked with an
+/// `Idempotent-Replayed` header. Reusing a key for a different body is
+/// rejected with 422, and a retry while the first request is still being
+/// handled with 409. Server errors are not stored, so those requests can be
+/// retried for real. Requests without the header are passed through.
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    cache: Arc<IdempotencyCache>,
+    max_key_length: usize,
+}
+
+impl IdempotencyLayer {
+    pub fn new(cache: Arc<IdempotencyCache>, max_key_length: usize) -> Self {
+        Self {
+            cache,
+            max_key_length,
+        }
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = Idempotency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Idempotency {
+            inner,
+            cache: self.cache.clone(),
+            max_key_length: self.max_key_length,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Idempotency<S> {
+    inner: S,
+    cache: Arc<IdempotencyCache>,
+    max_key_length: usize,
+}
+
+impl<S> Service<Request<Body>> for Idempotency<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let Some(idempotency_key) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+            return Box::pin(self.inner.call(req));
+        };
+        let Some(idempotency_key) = valid_key(idempotency_key, self.max_key_length) else {
+            let response = IdempotencyError::InvalidKey {
+                max_length: self.max_key_length,
+            }
+            .into_response();
+            return Box::pin(async move { Ok(response) });
+        };
+
+        let key = RequestKey {
+            tenant: req.extensions().get::<TenantId>().cloned(),
+            principal: req.extensions().get::<Principal>().map(|p| p.0.clone()),
+            method: req.method().clone(),
+            path: req.uri().path().to_string(),
+            idempotency_key,
+        };
+
+        // The service that was polled ready has to handle the request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let (parts, body) = match buffer(req).await {
+                Ok(buffered) => buffered,
+                Err(err) => return Ok(err.into_response()),
+            };
+
+            let pending = match cache.begin(key, &body, Instant::now()) {
+                Begin::Started(pending) => pending,
+                Begin::Replay(stored) => {
+                    debug!("Replaying the response to an idempotent request");
+                    return Ok(replay(stored));
+                }
+                Begin::InProgress => return Ok(IdempotencyError::RequestInProgress.into_response()),
+                Begin::Mismatch => return Ok(IdempotencyError::KeyReused.into_response()),
+            };
+
+            let response = inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await?;
+            if response.status().is_server_error() {
+                // Dropping the pending request releases the key.
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(_) => {
+                    return Ok(Response::from_parts(
+                        parts,
+                        axum::body::boxed(Body::empty()),
+                    ))
+                }
+            };
+            pending.complete(
+                StoredResponse {
+                    status: parts.status,
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                },
+                Instant::now(),
+            );
+
+            Ok(Response::from_parts(
+                parts,
+                axum::body::boxed(Body::from(body)),
+            ))
+        })
+    }
+}
+
+/// Idempotency keys are opaque strings of visible ASCII characters, like a
+/// UUID.
+fn valid_key(value: &HeaderValue, max_length: usize) -> Option<String> {
+    let key = value.to_str().ok()?;
+    let valid =
+        !key.is_empty() && key.len() <= max_length && key.bytes().all(|b| b.is_ascii_graphic());
+
+    valid.then(|| key.to_string())
+}
+
+/// Read the whole request body, within the default body limit.
+async fn buffer(req: Request<Body>) -> Result<(Parts, Bytes), JsonBodyError> {
+    let limit = req
+        .extensions()
+        .get::<JsonConfig>()
+        .map(|config| config.max_body_size)
+        .unwrap_or_default();
+    let too_large = |err: axum::BoxError| {
+        if err.is::<http_body::LengthLimitError>() {
+            JsonBodyError::PayloadTooLarge { limit }
+        } else {
+            JsonBodyError::UnreadableBody
+        }
+    };
+
+    match req.with_limited_body() {
+        Ok(req) => {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await.map_err(too_large)?;
+            Ok((parts, body))
+        }
+        Err(req) => {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body)
+                .await
+                .map_err(|_| JsonBodyError::UnreadableBody)?;
+            Ok((parts, body))
+        }
+    }
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let mut response = Response::new(axum::body::boxed(Body::from(stored.body)));
+    *response.status_mut() = stored.status;
+    *response.headers_mut() = stored.headers;
+    response.headers_mut().insert(
+        HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER),
+        HeaderValue::from_static("true"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::IdempotencyConfig;
+    use axum::routing::post;
+    use axum::Router;
+    use http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn request(key: Option<&str>, body: &'static str) -> Request<Body> {
+        let mut builder = Request::builder().method("POST").uri("/users");
+        if let Some(key) = key {
+            builder = builder.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    fn app(calls: Arc<AtomicUsize>) -> Router {
+        let cache = Arc::new(IdempotencyCache::new(&IdempotencyConfig::default()));
+        Router::new().route(
+            "/users",
+            post(move || async move {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                (StatusCode::CREATED, format!("call {call}"))
+            })
+            .layer(IdempotencyLayer::new(cache, 8)),
+        )
+    }
+
+    async fn body(response: Response) -> Bytes {
+        hyper::body::to_bytes(response.into_body()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_get_the_original_response() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone());
+
+        let first = app.clone().oneshot(request(Some("k1"), "a")).await.unwrap();
+        let retry = app.clone().oneshot(request(Some("k1"), "a")).await.unwrap();
+        let reused = app.clone().oneshot(request(Some("k1"), "b")).await.unwrap();
+        let without_key = app.oneshot(request(None, "a")).await.unwrap();
+
+        assert_eq!(StatusCode::CREATED, retry.status());
+        assert!(!first.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER));
+        assert_eq!("true", retry.headers()[IDEMPOTENT_REPLAYED_HEADER]);
+        assert_eq!(body(first).await, body(retry).await);
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, reused.status());
+        assert_eq!("call 1", body(without_key).await);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_are_rejected() {
+        let app = app(Arc::default());
+
+        for key in ["", "too-long-key", "white space"] {
+            let response = app.clone().oneshot(request(Some(key), "a")).await.unwrap();
+
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{key:?}");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::LimitsConfig;
+use crate::metrics::{Metrics, ShedReason};
+use crate::models::LoadError;
+use crate::tenant::TenantId;
+use axum::extract::MatchedPath;
+use axum::response::{IntoResponse, Response};
+use http::header::RETRY_AFTER;
+use http::{HeaderValue, Request};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+use tracing::warn;
+
+#[derive(Clone, Copy, Debug)]
+struct Limits {
+    max_in_flight: Option<usize>,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Config {
+    global: Limits,
+    routes: HashMap<String, Limits>,
+    retry_after: u64,
+}
+
+/// Layer that keeps the server from being overloaded.
+///
+/// The number of requests in flight is limited for the whole server and per
+/// route. Requests over either limit are shed right away with 503 and a
+/// `Retry-After` header, instead of being queued until the server catches up.
+/// Requests that take longer than their timeout are aborted with 503.
+///
+/// Requests in flight, shed requests and timeouts are recorded in the
+/// metrics, as are the statuses of all handled requests. Everything but the
+/// requests in flight is counted per tenant, which is known if this is
+/// wrapped by the [`AuthLayer`].
+///
+/// [`AuthLayer`]: crate::middleware::auth::AuthLayer
+#[derive(Clone)]
+pub struct LoadShedLayer {
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+}
+
+impl LoadShedLayer {
+    pub fn new(limits: &LimitsConfig, metrics: Arc<Metrics>) -> Self {
+        let max_in_flight = |max: usize| (max > 0).then_some(max);
+        let routes = limits
+            .routes
+            .iter()
+            .map(|route| {
+                let limits = Limits {
+                    max_in_flight: max_in_flight(route.max_in_flight),
+                    timeout: route.request_timeout.unwrap_or(limits.request_timeout),
+                };
+                (route.path.clone(), limits)
+            })
+            .collect();
+
+        let config = Config {
+            global: Limits {
+                max_in_flight: max_in_flight(limits.max_in_flight),
+                timeout: limits.request_timeout,
+            },
+            routes,
+            retry_after: limits.retry_after.as_secs().max(1),
+        };
+
+        Self {
+            config: Arc::new(config),
+            metrics,
+        }
+    }
+}
+
+impl<S> Layer<S> for LoadShedLayer {
+    type Service = LoadShed<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoadShed {
+            inner,
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LoadShed<S> {
+    inner: S,
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> LoadShed<S> {
+    fn shed(&self, reason: ShedReason, route: &str, tenant: &str) -> Response {
+        self.metrics.record_shed(reason, route, tenant);
+        warn!(
+            route,
+            tenant,
+            ?reason,
+            "Server is overloaded, shedding request"
+        );
+
+        let retry_after = self.config.retry_after;
+        let mut response = LoadError::Overloaded { retry_after }.into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        response
+    }
+}
+
+impl<S, B> Service<Request<B>> for LoadShed<S>
+where
+    S: Service<Request<B>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_default();
+        let tenant = req
+            .extensions()
+            .get::<TenantId>()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let route_limits = self.config.routes.get(&route).copied();
+
+        // Both guards are held until the response is ready, so the request
+        // counts as in flight until then.
+        let Some(global) = self
+            .metrics
+            .in_flight()
+            .try_acquire(self.config.global.max_in_flight)
+        else {
+            let response = self.shed(ShedReason::GlobalLimit, &route, &tenant);
+            return Box::pin(async move { Ok(response) });
+        };
+        let Some(route_guard) = self
+            .metrics
+            .route_in_flight(&route)
+            .try_acquire(route_limits.and_then(|limits| limits.max_in_flight))
+        else {
+            let response = self.shed(ShedReason::RouteLimit, &route, &tenant);
+            return Box::pin(async move { Ok(response) });
+        };
+
+        let timeout = route_limits.map_or(self.config.global.timeout, |limits| limits.timeout);
+        let metrics = self.metrics.clone();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let result = tokio::time::timeout(timeout, fut).await;
+            drop((route_guard, global));
+
+            let response = match result {
+                Ok(response) => response?,
+                Err(_) => {
+                    metrics.record_timeout(&route, &tenant);
+                    warn!(route, tenant, ?timeout, "Request timed out");
+                    LoadError::TimedOut.into_response()
+                }
+            };
+            metrics.record_request(&tenant, &route, response.status().as_u16());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RouteLimitsConfig;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use http::StatusCode;
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_route_limit_are_shed() {
+        let limits = LimitsConfig {
+            routes: vec![RouteLimitsConfig {
+                path: "/slow".to_string(),
+                max_in_flight: 1,
+                request_timeout: None,
+            }],
+            ..Default::default()
+        };
+        let metrics = Arc::new(Metrics::default());
+        let (release, released) = oneshot::channel::<()>();
+        let released = Arc::new(tokio::sync::Mutex::new(Some(released)));
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(move || async move {
+                    if let Some(released) = released.lock().await.take() {
+                        let _ = released.await;
+                    }
+                    "done"
+                }),
+            )
+            .layer(LoadShedLayer::new(&limits, metrics.clone()));
+
+        let in_flight = tokio::spawn(app.clone().oneshot(request("/slow")));
+        while metrics.route_in_flight("/slow").get() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let response = app.clone().oneshot(request("/slow")).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert_eq!("1", response.headers()[RETRY_AFTER]);
+
+        release.send(()).unwrap();
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(0, metrics.in_flight().get());
+    }
+
+    #[tokio::test]
+    async fn slow_requests_time_out() {
+        let limits = LimitsConfig {
+            request_timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let metrics = Arc::new(Metrics::default());
+        let app = Router::new()
+            .route("/slow", get(std::future::pending::<()>))
+            .layer(LoadShedLayer::new(&limits, metrics.clone()));
+
+        let response = app.oneshot(request("/slow")).await.unwrap();
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let error: LoadError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(LoadError::TimedOut, error);
+        assert!(metrics
+            .render()
+            .contains("user_service_requests_timed_out_total{route=\"/slow\",tenant=\"\"} 1"));
+    }
+}
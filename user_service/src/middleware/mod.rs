@@ -0,0 +1,8 @@
+pub mod access_log;
+pub mod auth;
+pub mod cors;
+pub mod error_format;
+pub mod idempotency;
+pub mod load_shed;
+pub mod rate_limit;
+pub mod request_id;
@@ -0,0 +1,236 @@
+use crate::models::RateLimitError;
+use crate::rate_limit::{ClientKey, Decision};
+use crate::reload::{Settings, SharedSettings};
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::extract::MatchedPath;
+use axum::response::{IntoResponse, Response};
+use http::header::{AUTHORIZATION, RETRY_AFTER};
+use http::{HeaderMap, HeaderName, HeaderValue, Request};
+use hyper::server::conn::AddrStream;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio_rustls::server::TlsStream;
+use tower::{Layer, Service};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+const RATELIMIT_RESET: &str = "ratelimit-reset";
+const RATELIMIT_POLICY: &str = "ratelimit-policy";
+
+/// Address of the peer of a connection, for both plain and TLS connections.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub SocketAddr);
+
+impl Connected<&AddrStream> for ClientAddr {
+    fn connect_info(target: &AddrStream) -> Self {
+        ClientAddr(target.remote_addr())
+    }
+}
+
+impl Connected<&TlsStream<AddrStream>> for ClientAddr {
+    fn connect_info(target: &TlsStream<AddrStream>) -> Self {
+        ClientAddr(target.get_ref().0.remote_addr())
+    }
+}
+
+/// Layer that limits the rate of requests per client.
+///
+/// Requests are counted against the principal of their API key, or against
+/// the address of the client for anonymous requests and requests with an
+/// unknown key. Every response carries the `RateLimit-*` headers describing
+/// the limit of its route; requests over the limit are rejected with 429 and a
+/// `Retry-After` header.
+///
+/// This layer has to wrap the authentication layer, so requests that fail to
+/// authenticate are limited as well. The limits are part of the reloadable
+/// settings, so they are looked up for every request.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    settings: SharedSettings,
+}
+
+impl RateLimitLayer {
+    pub fn new(settings: SharedSettings) -> Self {
+        Self { settings }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            settings: self.settings.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    settings: SharedSettings,
+}
+
+impl<S> RateLimit<S> {
+    fn client_key<B>(&self, settings: &Settings, req: &Request<B>) -> ClientKey {
+        let authorization = req
+            .headers()
+            .get(AUTHORIZATION)
+            .map(|value| value.to_str().unwrap_or_default());
+        let credentials = settings.authenticator.authenticate(authorization);
+        if let Ok(Some(credentials)) = credentials {
+            return ClientKey::Principal(credentials.principal.0);
+        }
+
+        ClientKey::Ip(client_ip(settings, req))
+    }
+}
+
+/// The address of the client that sent a request, taking trusted proxies
+/// into account.
+pub fn client_ip<B>(settings: &Settings, req: &Request<B>) -> IpAddr {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<ClientAddr>>()
+        .map_or(IpAddr::from(Ipv4Addr::UNSPECIFIED), |info| info.0 .0.ip());
+    let forwarded_for: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+
+    settings.rate_limiter.client_ip(peer, &forwarded_for)
+}
+
+impl<S, B> Service<Request<B>> for RateLimit<S>
+where
+    S: Service<Request<B>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let settings = self.settings.load();
+        let client = self.client_key(&settings, &req);
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str);
+        let Some(decision) =
+            settings
+                .rate_limiter
+                .check(req.method(), route, client, Instant::now())
+        else {
+            return Box::pin(self.inner.call(req));
+        };
+
+        if !decision.allowed {
+            let retry_after = ceil_secs(decision.retry_after);
+            let mut response = RateLimitError::TooManyRequests { retry_after }.into_response();
+            insert_headers(response.headers_mut(), &decision);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let mut response = fut.await?;
+            insert_headers(response.headers_mut(), &decision);
+            Ok(response)
+        })
+    }
+}
+
+/// Add the `RateLimit-*` headers from the IETF draft "RateLimit header fields
+/// for HTTP".
+fn insert_headers(headers: &mut HeaderMap, decision: &Decision) {
+    let quota = decision.quota;
+    let policy = format!("{};w={}", quota.requests, ceil_secs(quota.period));
+
+    headers.insert(
+        HeaderName::from_static(RATELIMIT_LIMIT),
+        HeaderValue::from(quota.requests),
+    );
+    headers.insert(
+        HeaderName::from_static(RATELIMIT_REMAINING),
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        HeaderName::from_static(RATELIMIT_RESET),
+        HeaderValue::from(ceil_secs(decision.reset)),
+    );
+    if let Ok(policy) = HeaderValue::from_str(&policy) {
+        headers.insert(HeaderName::from_static(RATELIMIT_POLICY), policy);
+    }
+}
+
+/// The headers use whole seconds. Rounding up makes sure a client that waits
+/// as long as it is told is not rejected again.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{Config, RateLimitConfig};
+    use arc_swap::ArcSwap;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use http::StatusCode;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let config = Config {
+            rate_limit: RateLimitConfig {
+                enabled: true,
+                requests: 1,
+                period: Duration::from_secs(30),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(RateLimitLayer::new(settings))
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected() {
+        let app = app();
+        let request = || Request::builder().uri("/").body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("1", response.headers()[RATELIMIT_LIMIT]);
+        assert_eq!("0", response.headers()[RATELIMIT_REMAINING]);
+        assert_eq!("1;w=30", response.headers()[RATELIMIT_POLICY]);
+
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+        assert_eq!("30", response.headers()[RETRY_AFTER]);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let error: RateLimitError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(RateLimitError::TooManyRequests { retry_after: 30 }, error);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::models::{RequestId, CURRENT_REQUEST_ID, REQUEST_ID_HEADER};
+use axum::response::Response;
+use http::{HeaderValue, Request};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{field, info_span, Instrument};
+use uuid::Uuid;
+
+/// Request ids supplied by clients that are longer than this are replaced
+/// with a generated one.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Layer that assigns a request id to every request.
+///
+/// The id is taken from the `X-Request-Id` header if the client supplied a
+/// usable one, otherwise a UUIDv7 is generated. The id is stored in the
+/// request extensions, recorded on a span wrapping the request, made
+/// available to error responses through [`RequestId::current`] and echoed in
+/// the response headers.
+#[derive(Clone, Debug, Default)]
+pub struct RequestIdLayer {}
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for RequestIdService<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| is_valid_request_id(value))
+            .map(ToString::to_string)
+            .unwrap_or_else(|| Uuid::now_v7().to_string());
+
+        let header_value =
+            HeaderValue::from_str(&request_id).expect("request ids are valid header values");
+        req.headers_mut()
+            .insert(REQUEST_ID_HEADER, header_value.clone());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        // The tenant is recorded once the request is authenticated.
+        let span = info_span!("request", request_id = %request_id, tenant = field::Empty);
+        let fut = span.in_scope(|| self.inner.call(req));
+
+        Box::pin(
+            CURRENT_REQUEST_ID
+                .scope(RequestId(request_id), async move {
+                    let mut response = fut.await?;
+                    response
+                        .headers_mut()
+                        .insert(REQUEST_ID_HEADER, header_value);
+                    Ok(response)
+                })
+                .instrument(span),
+        )
+    }
+}
+
+/// Only accept request ids that are reasonably short and consist of visible
+/// ASCII characters, so they can safely be logged and echoed back.
+fn is_valid_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LENGTH
+        && value.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|| async { RequestId::current().map(|id| id.0).unwrap_or_default() }),
+            )
+            .layer(RequestIdLayer::new())
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed() {
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app().oneshot(req).await.unwrap();
+
+        assert_eq!("abc-123", response.headers()[REQUEST_ID_HEADER]);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&b"abc-123"[..], &body[..]);
+    }
+
+    #[tokio::test]
+    async fn request_id_is_generated() {
+        let tests = vec![None, Some(""), Some("has spaces")];
+
+        for header in tests {
+            let mut req = Request::builder().uri("/");
+            if let Some(header) = header {
+                req = req.header(REQUEST_ID_HEADER, header);
+            }
+
+            let response = app()
+                .oneshot(req.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+            assert!(Uuid::parse_str(request_id).is_ok(), "{request_id}");
+        }
+    }
+}
@@ -1,8 +1,181 @@
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use http::header::CONTENT_TYPE;
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::SystemTime;
 use thiserror::Error;
+use uuid::Uuid;
+
+/// Name of the header that carries the id of a request, both on requests and
+/// on responses.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Identifier of a single request, used to correlate logs, traces and error
+/// responses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    /// The id of the request that is currently being handled.
+    pub static CURRENT_REQUEST_ID: RequestId;
+}
+
+impl RequestId {
+    /// Returns the id of the request that is currently being handled, if any.
+    pub fn current() -> Option<RequestId> {
+        CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+    }
+}
+
+/// Media type of RFC 7807 problem details.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Prefix of the `type` of problem details, followed by the error code.
+const PROBLEM_TYPE_PREFIX: &str = "urn:user-service:problem:";
+
+/// The format error responses are rendered in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `{"error", "details"}`, the same format the errors are deserialized
+    /// from by the client.
+    #[default]
+    Tagged,
+
+    /// RFC 7807 problem details, for the request at `instance`.
+    Problem { instance: String },
+}
+
+tokio::task_local! {
+    /// The format the client of the current request wants errors in.
+    pub static CURRENT_ERROR_FORMAT: ErrorFormat;
+}
+
+impl ErrorFormat {
+    /// Returns the error format of the request that is currently being
+    /// handled, or the default format.
+    pub fn current() -> ErrorFormat {
+        CURRENT_ERROR_FORMAT
+            .try_with(Clone::clone)
+            .unwrap_or_default()
+    }
+}
+
+/// The body of every error response: the error itself, with the id of the
+/// request that caused it.
+#[derive(Serialize)]
+struct ErrorBody<'a, E> {
+    #[serde(flatten)]
+    error: &'a E,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// An error rendered as RFC 7807 problem details. The details of the error
+/// and the request id are added as extension members.
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    instance: String,
+
+    #[serde(flatten)]
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    fn new<E>(status_code: StatusCode, error: &E, instance: String) -> Self
+    where
+        E: Serialize + fmt::Display,
+    {
+        let (code, details) = match serde_json::to_value(error) {
+            Ok(Value::Object(mut fields)) => (
+                fields.remove("error").unwrap_or_default(),
+                fields.remove("details").unwrap_or_default(),
+            ),
+            _ => (Value::Null, Value::Null),
+        };
+        let code = code.as_str().unwrap_or("Unknown");
+
+        let mut extensions = Map::new();
+        match details {
+            Value::Null => {}
+            Value::Object(fields)
+                if fields
+                    .keys()
+                    .all(|key| !Self::MEMBERS.contains(&key.as_str())) =>
+            {
+                extensions.extend(fields)
+            }
+            details => {
+                extensions.insert("details".to_string(), details);
+            }
+        }
+        if let Some(request_id) = RequestId::current() {
+            extensions.insert("request_id".to_string(), Value::String(request_id.0));
+        }
+
+        Self {
+            problem_type: format!("{PROBLEM_TYPE_PREFIX}{code}"),
+            title: title(code),
+            status: status_code.as_u16(),
+            detail: error.to_string(),
+            instance,
+            extensions,
+        }
+    }
+
+    /// The members defined by the RFC, which extension members must not
+    /// replace.
+    const MEMBERS: [&'static str; 5] = ["type", "title", "status", "detail", "instance"];
+}
+
+/// Turn an error code like `UserNotFound` into a title like `User not found`.
+fn title(code: &str) -> String {
+    let mut title = String::with_capacity(code.len() + 4);
+    for (i, c) in code.chars().enumerate() {
+        if i == 0 {
+            title.push(c);
+        } else if c.is_uppercase() {
+            title.push(' ');
+            title.extend(c.to_lowercase());
+        } else {
+            title.push(c);
+        }
+    }
+    title
+}
+
+/// Create an error response with the given status code, including the id of
+/// the current request in the body. The error is rendered in the format
+/// requested by the client.
+fn error_response<E>(status_code: StatusCode, error: &E) -> Response
+where
+    E: Serialize + fmt::Display,
+{
+    if let ErrorFormat::Problem { instance } = ErrorFormat::current() {
+        let problem = Problem::new(status_code, error, instance);
+        return (status_code, [(CONTENT_TYPE, PROBLEM_JSON)], Json(problem)).into_response();
+    }
+
+    let body = ErrorBody {
+        error,
+        request_id: RequestId::current().map(|id| id.0),
+    };
+
+    (status_code, Json(body)).into_response()
+}
+
+/// Name of the header that carries the tenant of a request, unless the
+/// server is configured to use another header.
+pub const TENANT_HEADER: &str = "x-tenant-id";
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
 #[serde(tag = "error", content = "details")]
@@ -14,18 +187,666 @@ pub enum AuthError {
     /// This occurs if the credentials provided are not allowed to do the specified action.
     #[error("unauthorized")]
     Unauthorized,
+
+    /// This occurs if the principal of the request is a user that was
+    /// suspended or locked.
+    #[error("account is {status}")]
+    InactiveAccount { status: UserStatus },
+
+    /// This occurs if the tenant of a request cannot be determined, because
+    /// the request does not name one.
+    #[error("request does not name a tenant")]
+    MissingTenant,
+
+    /// This occurs if the tenant named by a request is not a valid tenant id,
+    /// or not one of the tenants the server serves.
+    #[error("tenant is invalid: {reason}")]
+    InvalidTenant { reason: String },
+
+    /// This occurs if the API key of a request belongs to another tenant
+    /// than the one the request is made for.
+    #[error("API key does not belong to the tenant")]
+    TenantNotAllowed,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            AuthError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            AuthError::Unauthorized
+            | AuthError::InactiveAccount { .. }
+            | AuthError::TenantNotAllowed => StatusCode::FORBIDDEN,
+            AuthError::MissingTenant | AuthError::InvalidTenant { .. } => StatusCode::BAD_REQUEST,
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum JsonBodyError {
+    /// This occurs if the request body is not sent as JSON.
+    #[error("expected a request body with content type application/json")]
+    UnsupportedMediaType,
+
+    /// This occurs if the request body is larger than the server accepts.
+    #[error("request body is larger than {limit} bytes")]
+    PayloadTooLarge { limit: usize },
+
+    /// This occurs if the request body could not be received.
+    #[error("unable to read the request body")]
+    UnreadableBody,
+
+    /// This occurs if the request body is not valid JSON.
+    #[error("request body is not valid JSON: {message}")]
+    MalformedJson { message: String },
+
+    /// This occurs if a required field is missing from the request body.
+    #[error("missing field {path}")]
+    MissingField { path: String },
+
+    /// This occurs if the request body contains a field that is not expected,
+    /// and unknown fields are not allowed.
+    #[error("unknown field {path}")]
+    UnknownField { path: String },
+
+    /// This occurs if a field has the wrong type or an invalid value.
+    #[error("invalid value for {path}: {message}")]
+    InvalidValue { path: String, message: String },
+}
+
+impl IntoResponse for JsonBodyError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            JsonBodyError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonBodyError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            JsonBodyError::UnreadableBody | JsonBodyError::MalformedJson { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+            JsonBodyError::MissingField { .. }
+            | JsonBodyError::UnknownField { .. }
+            | JsonBodyError::InvalidValue { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum RateLimitError {
+    /// This occurs if a client makes more requests than its rate limit allows.
+    #[error("too many requests, retry after {retry_after} seconds")]
+    TooManyRequests { retry_after: u64 },
+}
+
+impl IntoResponse for RateLimitError {
+    fn into_response(self) -> Response {
+        error_response(StatusCode::TOO_MANY_REQUESTS, &self)
+    }
+}
+
+/// Name of the header that makes a request idempotent.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Name of the header that marks a response as a replay of the response to an
+/// earlier request with the same idempotency key.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum IdempotencyError {
+    /// This occurs if the `Idempotency-Key` header is empty, too long or
+    /// contains characters other than visible ASCII.
+    #[error("idempotency key has to be 1 to {max_length} visible ASCII characters")]
+    InvalidKey { max_length: usize },
+
+    /// This occurs if an idempotency key is used again for a request with a
+    /// different body.
+    #[error("idempotency key was already used for a different request")]
+    KeyReused,
+
+    /// This occurs if the first request with an idempotency key is still
+    /// being handled. The request can be retried once it completed.
+    #[error("a request with this idempotency key is still in progress")]
+    RequestInProgress,
+}
+
+impl IntoResponse for IdempotencyError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            IdempotencyError::InvalidKey { .. } => StatusCode::BAD_REQUEST,
+            IdempotencyError::KeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            IdempotencyError::RequestInProgress => StatusCode::CONFLICT,
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum LoadError {
+    /// This occurs if the server is handling as many requests as it can, the
+    /// request can be retried after the given number of seconds.
+    #[error("server is overloaded, retry after {retry_after} seconds")]
+    Overloaded { retry_after: u64 },
+
+    /// This occurs if the request took longer than its timeout.
+    #[error("request timed out")]
+    TimedOut,
+}
+
+impl IntoResponse for LoadError {
+    fn into_response(self) -> Response {
+        error_response(StatusCode::SERVICE_UNAVAILABLE, &self)
+    }
+}
+
+/// A user as it is stored and returned by the API.
+///
+/// Fields are only ever added to this type, and clients ignore fields they
+/// do not know, so older clients keep working against newer servers.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct User {
+    /// Stable identifier of the user, which never changes, even if the user
+    /// is renamed.
+    pub id: Uuid,
+
+    /// The username as it was registered.
     pub username: String,
     pub name: String,
+    pub email: Option<String>,
+
+    #[serde(with = "humantime_serde")]
+    pub created_at: SystemTime,
+
+    #[serde(with = "humantime_serde")]
+    pub updated_at: SystemTime,
+
+    /// Incremented on every change of the user, starting at 1.
+    pub version: u64,
+
+    pub status: UserStatus,
+
+    /// Extra profile data, like a locale or a department, checked against the
+    /// schema of the deployment.
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+/// The custom attributes of a user, a JSON object.
+pub type Attributes = Map<String, Value>;
+
+/// Query parameters that select the users with an attribute, like
+/// `attributes.department=eng`.
+const ATTRIBUTE_PARAMETER: &str = "attributes.";
+
+/// Which users to list. Every attribute of the filter has to be set on a user
+/// to the given value: string attributes as they are, others in their JSON
+/// form, like `42` or `true`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserFilter {
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl UserFilter {
+    /// The filter of the query parameters of a list request, or why they do
+    /// not describe one. Parameters that do not filter attributes are left
+    /// to others.
+    pub fn from_query(parameters: &[(String, String)]) -> Result<Self, String> {
+        let mut attributes = BTreeMap::new();
+        for (name, value) in parameters {
+            let Some(attribute) = name.strip_prefix(ATTRIBUTE_PARAMETER) else {
+                continue;
+            };
+            if attribute.is_empty() {
+                return Err(format!("{name} does not name an attribute"));
+            }
+            if attributes
+                .insert(attribute.to_string(), value.clone())
+                .is_some()
+            {
+                return Err(format!("{name} is given more than once"));
+            }
+        }
+
+        Ok(Self { attributes })
+    }
+
+    /// The query parameters of the filter.
+    pub fn to_query(&self) -> Vec<(String, String)> {
+        self.attributes
+            .iter()
+            .map(|(attribute, value)| (format!("{ATTRIBUTE_PARAMETER}{attribute}"), value.clone()))
+            .collect()
+    }
+
+    pub fn matches(&self, user: &User) -> bool {
+        self.attributes
+            .iter()
+            .all(|(attribute, wanted)| match user.attributes.get(attribute) {
+                Some(Value::String(value)) => value == wanted,
+                Some(value) => {
+                    serde_json::from_str::<Value>(wanted).is_ok_and(|wanted| wanted == *value)
+                }
+                None => false,
+            })
+    }
+}
+
+/// The lifecycle state of a user. Only active users can make requests.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    #[default]
+    Active,
+
+    /// Suspended by an admin, until the user is reactivated.
+    Suspended,
+
+    /// Locked after too many failed logins, or by an admin.
+    Locked,
+
+    /// Deleted users are kept for reference, they never change again.
+    Deleted,
+}
+
+impl UserStatus {
+    /// Whether a user with this status can be changed to `next`. Suspended
+    /// and locked users can only be reactivated, or, if locked, suspended.
+    /// Users cannot be deleted through a status change.
+    pub fn can_become(self, next: UserStatus) -> bool {
+        use UserStatus::*;
+
+        matches!(
+            (self, next),
+            (Active, Suspended)
+                | (Active, Locked)
+                | (Suspended, Active)
+                | (Locked, Active | Suspended)
+        )
+    }
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            UserStatus::Active => "active",
+            UserStatus::Suspended => "suspended",
+            UserStatus::Locked => "locked",
+            UserStatus::Deleted => "deleted",
+        };
+        f.write_str(status)
+    }
+}
+
+/// A requested change of the status of a user.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct StatusChange {
+    pub status: UserStatus,
+
+    /// Why the status is changed, kept with the transition.
+    pub reason: String,
+}
+
+/// A change of the status of a user, as it is recorded.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct StatusTransition {
+    pub from: UserStatus,
+    pub to: UserStatus,
+    pub reason: String,
+
+    /// The principal that made the change, or `system` for changes the
+    /// service made itself, like locking a user after failed logins.
+    pub actor: String,
+
+    #[serde(with = "humantime_serde")]
+    pub at: SystemTime,
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct NewUser {
     pub username: String,
     pub name: String,
+
+    #[serde(default)]
+    pub email: Option<String>,
+
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+/// Changes to a user. Fields that are left out are not changed.
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct UserUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// The new email address, or `null` to remove it.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub email: Option<Option<String>>,
+
+    /// The new attributes, which replace all attributes of the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Attributes>,
+}
+
+/// A new username for a user.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct UsernameChange {
+    pub username: String,
+}
+
+/// A group of users. The roles of a group are granted to all its members.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Group {
+    pub id: Uuid,
+
+    /// Unique name of the group, like `support`.
+    pub name: String,
+    pub description: Option<String>,
+    pub roles: Vec<Role>,
+
+    #[serde(with = "humantime_serde")]
+    pub created_at: SystemTime,
+
+    #[serde(with = "humantime_serde")]
+    pub updated_at: SystemTime,
+
+    /// Incremented on every change of the group, but not when members are
+    /// added or removed.
+    pub version: u64,
+}
+
+/// A role that the authorization policy checks before a change is made.
+/// Everyone who is allowed to make requests can read users and groups.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Allowed to do everything.
+    Admin,
+
+    /// Allowed to create and change users.
+    UserAdmin,
+
+    /// Allowed to create, change and delete groups, and to manage their
+    /// members.
+    GroupAdmin,
+
+    /// Allowed to read the audit log.
+    Auditor,
+}
+
+impl Role {
+    /// Whether this role allows what `required` allows.
+    pub fn grants(self, required: Role) -> bool {
+        self == Role::Admin || self == required
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let role = match self {
+            Role::Admin => "admin",
+            Role::UserAdmin => "user_admin",
+            Role::GroupAdmin => "group_admin",
+            Role::Auditor => "auditor",
+        };
+        f.write_str(role)
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct NewGroup {
+    pub name: String,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub roles: Vec<Role>,
+}
+
+/// Changes to a group. Fields that are left out are not changed.
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct GroupUpdate {
+    /// The new description, or `null` to remove it.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub description: Option<Option<String>>,
+
+    /// The new roles, which replace all roles of the group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<Role>>,
+}
+
+/// A member of a group.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct GroupMember {
+    pub id: Uuid,
+
+    /// The current username of the member.
+    pub username: String,
+
+    #[serde(with = "humantime_serde")]
+    pub added_at: SystemTime,
+}
+
+/// One page of a list. The next page is requested with `next_cursor` as the
+/// `cursor` query parameter; it is missing on the last page.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Something that happened to the users and groups of a tenant, as it is
+/// recorded in the audit log. Events are only ever appended to the log, never
+/// changed or removed.
+///
+/// Every event includes the hash of the event before it, so the events form
+/// a chain: changing, removing or inserting an event breaks every link after
+/// it.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct AuditEvent {
+    pub id: Uuid,
+
+    #[serde(with = "humantime_serde")]
+    pub at: SystemTime,
+
+    /// The principal that made the request, or `anonymous` if the request
+    /// was not authenticated.
+    pub actor: String,
+    pub action: AuditAction,
+
+    /// The name of the user or group the action was applied to, as it was
+    /// when the action happened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+
+    /// The id of the user or group the action was applied to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_id: Option<Uuid>,
+
+    /// The fields of the target that changed, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub changes: BTreeMap<String, FieldChange>,
+
+    /// Why a request was rejected, for authentication events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// The id of the request that caused the event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    /// The `hash` of the event before this one, or 64 zeros for the first
+    /// event of the log.
+    #[serde(default)]
+    pub prev_hash: String,
+
+    /// The hex encoded SHA-256 of the JSON form of this event without the
+    /// hash itself. It is assigned when the event is appended to the log.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+/// A signed statement that the audit log of a tenant ended with a certain
+/// event at a certain time. The server signs checkpoints periodically, so an
+/// audit log cannot be rewritten without it, even by recomputing all hashes.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct AuditCheckpoint {
+    pub tenant: String,
+
+    /// The last event of the log when the checkpoint was signed.
+    pub event_id: Uuid,
+
+    /// The hash of that event.
+    pub hash: String,
+
+    #[serde(with = "humantime_serde")]
+    pub at: SystemTime,
+
+    /// The base64 encoded Ed25519 signature of the other fields.
+    pub signature: String,
+}
+
+/// The value of a field before and after a change. Fields that did not exist
+/// before or do not exist after it are `null`.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct FieldChange {
+    pub before: Value,
+    pub after: Value,
+}
+
+/// What an [`AuditEvent`] records.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+pub enum AuditAction {
+    #[serde(rename = "user.created")]
+    UserCreated,
+
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+
+    #[serde(rename = "user.renamed")]
+    UserRenamed,
+
+    #[serde(rename = "user.status_changed")]
+    UserStatusChanged,
+
+    /// A failed login was reported, which may have locked the user.
+    #[serde(rename = "user.login_failed")]
+    UserLoginFailed,
+
+    #[serde(rename = "group.created")]
+    GroupCreated,
+
+    #[serde(rename = "group.updated")]
+    GroupUpdated,
+
+    #[serde(rename = "group.deleted")]
+    GroupDeleted,
+
+    #[serde(rename = "group.member_added")]
+    GroupMemberAdded,
+
+    #[serde(rename = "group.member_removed")]
+    GroupMemberRemoved,
+
+    /// A request was rejected because its API key is unknown, or it has none
+    /// while authentication is required.
+    #[serde(rename = "auth.failed")]
+    AuthFailed,
+
+    /// An authenticated request was rejected: its principal is inactive,
+    /// lacks the required role, or its API key belongs to another tenant.
+    #[serde(rename = "auth.denied")]
+    AuthDenied,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            AuditAction::UserCreated => "user.created",
+            AuditAction::UserUpdated => "user.updated",
+            AuditAction::UserRenamed => "user.renamed",
+            AuditAction::UserStatusChanged => "user.status_changed",
+            AuditAction::UserLoginFailed => "user.login_failed",
+            AuditAction::GroupCreated => "group.created",
+            AuditAction::GroupUpdated => "group.updated",
+            AuditAction::GroupDeleted => "group.deleted",
+            AuditAction::GroupMemberAdded => "group.member_added",
+            AuditAction::GroupMemberRemoved => "group.member_removed",
+            AuditAction::AuthFailed => "auth.failed",
+            AuditAction::AuthDenied => "auth.denied",
+        };
+        f.write_str(action)
+    }
+}
+
+/// Which events of the audit log to return. Every criterion that is set has
+/// to match; `since` is inclusive, `until` exclusive.
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct AuditFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<AuditAction>,
+
+    /// The name of the user or group the events are about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub since: Option<SystemTime>,
+
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub until: Option<SystemTime>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.actor
+            .as_ref()
+            .is_none_or(|actor| *actor == event.actor)
+            && self.action.is_none_or(|action| action == event.action)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| event.target.as_ref() == Some(target))
+            && self.since.is_none_or(|since| event.at >= since)
+            && self.until.is_none_or(|until| event.at < until)
+    }
+}
+
+/// Deserialize a field that is present, even if it is `null`, as `Some`. Only
+/// fields that are left out are `None`.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
@@ -39,6 +860,11 @@ pub enum HandlerError<E> {
     #[error("unauthorized")]
     Unauthorized,
 
+    /// This occurs if the store is unable to handle the request, the request
+    /// may be retried later.
+    #[error("store is unavailable")]
+    StoreUnavailable,
+
     #[error(transparent)]
     ServiceError(E),
 }
@@ -53,23 +879,28 @@ impl<E> From<AuthError> for HandlerError<E> {
     fn from(err: AuthError) -> Self {
         match err {
             AuthError::Unauthenticated => HandlerError::Unauthenticated,
-            AuthError::Unauthorized => HandlerError::Unauthorized,
+            AuthError::Unauthorized
+            | AuthError::InactiveAccount { .. }
+            | AuthError::MissingTenant
+            | AuthError::InvalidTenant { .. }
+            | AuthError::TenantNotAllowed => HandlerError::Unauthorized,
         }
     }
 }
 
 impl<E> IntoResponse for HandlerError<E>
 where
-    E: IntoResponse + Serialize,
+    E: IntoResponse + Serialize + fmt::Display,
 {
-    fn into_response(self) -> axum::response::Response {
+    fn into_response(self) -> Response {
         let status_code = match self {
             HandlerError::Unauthenticated => StatusCode::UNAUTHORIZED,
             HandlerError::Unauthorized => StatusCode::FORBIDDEN,
+            HandlerError::StoreUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             HandlerError::ServiceError(service_err) => return service_err.into_response(),
         };
 
-        (status_code, Json(self)).into_response()
+        error_response(status_code, &self)
     }
 }
 
@@ -78,53 +909,295 @@ where
 pub enum GetUserError {
     #[error("user was not found: {username}")]
     UserNotFound { username: String },
+
+    #[error("user was not found: {id}")]
+    UserIdNotFound { id: Uuid },
 }
 
 impl IntoResponse for GetUserError {
-    fn into_response(self) -> axum::response::Response {
+    fn into_response(self) -> Response {
         let status_code = match self {
-            GetUserError::UserNotFound { .. } => StatusCode::NOT_FOUND,
+            GetUserError::UserNotFound { .. } | GetUserError::UserIdNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
         };
 
-        (status_code, Json(self)).into_response()
+        error_response(status_code, &self)
     }
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
 #[serde(tag = "error", content = "details")]
 pub enum CreateUserError {
+    /// This occurs if another user has the same username, ignoring case, or
+    /// a username that looks the same.
     #[error("username already exists")]
     UsernameAlreadyExists,
 
-    #[error("invalid username: {0:?}")]
-    InvalidUsername(InvalidUsernameReason),
-
-    #[error("invalid name: {0:?}")]
-    InvalidName(InvalidNameReason),
+    /// This occurs if one or more fields of the new user are invalid. Every
+    /// violation is reported, not just the first one.
+    #[error("invalid user: {}", describe_violations(.violations))]
+    InvalidNewUser {
+        violations: Vec<InvalidNewUserReason>,
+    },
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
+/// A single field of a new user that is invalid.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct InvalidNewUserReason {
+    /// Path of the field in the request body, like `username`.
     pub field: String,
-    pub reason: String,
+
+    pub reason: InvalidFieldReason,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-pub enum InvalidUsernameReason {
-    TooShort,
-    TooLong,
-    InvalidCharacters,
+impl fmt::Display for InvalidNewUserReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.field, self.reason)
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-pub enum InvalidNameReason {
+fn describe_violations(violations: &[InvalidNewUserReason]) -> String {
+    violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Why a field is invalid.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum InvalidFieldReason {
     TooShort,
     TooLong,
     InvalidCharacters,
+
+    /// The value does not have the expected format, like an email address
+    /// without a domain.
+    InvalidFormat,
+
+    /// A required field is missing.
+    Missing,
+
+    /// The field is not expected at all.
+    Unexpected,
+
+    /// The username is reserved, or looks like a reserved username.
+    Reserved,
+
+    /// The username mixes characters of several scripts, like Latin and
+    /// Cyrillic, which is a common way to imitate another username.
+    MixedScripts,
 }
 
 impl IntoResponse for CreateUserError {
-    fn into_response(self) -> axum::response::Response {
-        (StatusCode::BAD_REQUEST, Json(self)).into_response()
+    fn into_response(self) -> Response {
+        error_response(StatusCode::BAD_REQUEST, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum UpdateUserError {
+    #[error("user was not found: {username}")]
+    UserNotFound { username: String },
+
+    /// This occurs if the request has an `If-Match` header that does not
+    /// match the current entity tag of the user, because the user was
+    /// changed since the client last read it.
+    #[error("user was modified, its current entity tag is {etag}")]
+    PreconditionFailed { etag: String },
+
+    /// This occurs if one or more of the changed fields are invalid.
+    #[error("invalid update: {}", describe_violations(.violations))]
+    InvalidUserUpdate {
+        violations: Vec<InvalidNewUserReason>,
+    },
+}
+
+impl IntoResponse for UpdateUserError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            UpdateUserError::UserNotFound { .. } => StatusCode::NOT_FOUND,
+            UpdateUserError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            UpdateUserError::InvalidUserUpdate { .. } => StatusCode::BAD_REQUEST,
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum RenameUserError {
+    #[error("user was not found: {username}")]
+    UserNotFound { username: String },
+
+    /// This occurs if the request has an `If-Match` header that does not
+    /// match the current entity tag of the user.
+    #[error("user was modified, its current entity tag is {etag}")]
+    PreconditionFailed { etag: String },
+
+    /// This occurs if another user has the new username, ignoring case, or a
+    /// username that looks the same. Old usernames of renamed users are kept
+    /// for a while, and cannot be taken by others until they are released.
+    #[error("username already exists")]
+    UsernameAlreadyExists,
+
+    /// This occurs if the new username is invalid.
+    #[error("invalid username: {}", describe_violations(.violations))]
+    InvalidUsername {
+        violations: Vec<InvalidNewUserReason>,
+    },
+}
+
+impl IntoResponse for RenameUserError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            RenameUserError::UserNotFound { .. } => StatusCode::NOT_FOUND,
+            RenameUserError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            RenameUserError::UsernameAlreadyExists => StatusCode::CONFLICT,
+            RenameUserError::InvalidUsername { .. } => StatusCode::BAD_REQUEST,
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum ChangeStatusError {
+    #[error("user was not found: {username}")]
+    UserNotFound { username: String },
+
+    /// This occurs if the request has an `If-Match` header that does not
+    /// match the current entity tag of the user.
+    #[error("user was modified, its current entity tag is {etag}")]
+    PreconditionFailed { etag: String },
+
+    /// This occurs if the user cannot change from its current status to the
+    /// requested one, like reactivating a user that is already active.
+    #[error("user cannot change from {from} to {to}")]
+    InvalidTransition { from: UserStatus, to: UserStatus },
+
+    /// This occurs if the reason for the change is missing or too long.
+    #[error("invalid status change: {}", describe_violations(.violations))]
+    InvalidStatusChange {
+        violations: Vec<InvalidNewUserReason>,
+    },
+}
+
+impl IntoResponse for ChangeStatusError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            ChangeStatusError::UserNotFound { .. } => StatusCode::NOT_FOUND,
+            ChangeStatusError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            ChangeStatusError::InvalidTransition { .. } => StatusCode::CONFLICT,
+            ChangeStatusError::InvalidStatusChange { .. } => StatusCode::BAD_REQUEST,
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+/// Errors of the group endpoints.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum GroupError {
+    #[error("group was not found: {name}")]
+    GroupNotFound { name: String },
+
+    /// This occurs if a user that is added to a group, or whose groups are
+    /// listed, does not exist.
+    #[error("user was not found: {username}")]
+    UserNotFound { username: String },
+
+    #[error("group already exists")]
+    GroupAlreadyExists,
+
+    /// This occurs if the request has an `If-Match` header that does not
+    /// match the current entity tag of the group.
+    #[error("group was modified, its current entity tag is {etag}")]
+    PreconditionFailed { etag: String },
+
+    /// This occurs if one or more fields of a new or changed group are
+    /// invalid.
+    #[error("invalid group: {}", describe_violations(.violations))]
+    InvalidGroup {
+        violations: Vec<InvalidNewUserReason>,
+    },
+
+    /// This occurs if the `cursor` of a list request was not returned by the
+    /// previous page, or the `limit` is out of range.
+    #[error("invalid page: {reason}")]
+    InvalidPage { reason: String },
+}
+
+impl IntoResponse for GroupError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            GroupError::GroupNotFound { .. } | GroupError::UserNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
+            GroupError::GroupAlreadyExists => StatusCode::CONFLICT,
+            GroupError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            GroupError::InvalidGroup { .. } | GroupError::InvalidPage { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+        };
+
+        error_response(status_code, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum AuditError {
+    /// This occurs if the `cursor` of a list request was not returned by the
+    /// previous page, or the `limit` is out of range.
+    #[error("invalid page: {reason}")]
+    InvalidPage { reason: String },
+}
+
+impl IntoResponse for AuditError {
+    fn into_response(self) -> Response {
+        error_response(StatusCode::BAD_REQUEST, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum ListUsersError {
+    /// This occurs if the `cursor` of a list request was not returned by the
+    /// previous page, or the `limit` is out of range.
+    #[error("invalid page: {reason}")]
+    InvalidPage { reason: String },
+
+    /// This occurs if an attribute filter in the query is malformed.
+    #[error("invalid filter: {reason}")]
+    InvalidFilter { reason: String },
+}
+
+impl IntoResponse for ListUsersError {
+    fn into_response(self) -> Response {
+        error_response(StatusCode::BAD_REQUEST, &self)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Error)]
+#[serde(tag = "error", content = "details")]
+pub enum ReadinessError {
+    /// The server is shutting down and should not receive new requests.
+    #[error("server is draining")]
+    Draining,
+
+    /// The store cannot serve requests at the moment.
+    #[error("store is not ready: {reason}")]
+    StoreNotReady { reason: String },
+}
+
+impl IntoResponse for ReadinessError {
+    fn into_response(self) -> Response {
+        error_response(StatusCode::SERVICE_UNAVAILABLE, &self)
     }
 }
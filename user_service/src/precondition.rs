@@ -0,0 +1,97 @@
+use crate::models::User;
+use http::header::{IF_MATCH, IF_NONE_MATCH};
+use http::{HeaderMap, HeaderName};
+use uuid::Uuid;
+
+/// The entity tag of a user. It changes whenever the user is changed, since
+/// it is derived from the version, and differs between users, since it also
+/// contains the id.
+pub fn user_etag(user: &User) -> String {
+    etag(user.id, user.version)
+}
+
+/// The entity tag of the given version of a user.
+pub fn etag(id: Uuid, version: u64) -> String {
+    format!("\"{}.{}\"", id.simple(), version)
+}
+
+/// Whether the `If-Match` header allows changing a resource with the given
+/// entity tag. Requests without the header are unconditional.
+pub fn if_match(headers: &HeaderMap, etag: &str) -> bool {
+    if !headers.contains_key(IF_MATCH) {
+        return true;
+    }
+
+    // Only strong comparison is allowed for `If-Match`, weak tags never match.
+    listed_etags(headers, IF_MATCH).any(|tag| tag == "*" || tag == etag)
+}
+
+/// Whether the `If-None-Match` header lists the given entity tag, so the
+/// client already has the current representation.
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    listed_etags(headers, IF_NONE_MATCH)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn listed_etags(headers: &HeaderMap, name: HeaderName) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(name: HeaderName, values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(name.clone(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn if_match_uses_strong_comparison() {
+        let tests = vec![
+            (vec![], true),
+            (vec!["\"a.1\""], true),
+            (vec!["\"a.0\", \"a.1\""], true),
+            (vec!["*"], true),
+            (vec!["\"a.0\""], false),
+            (vec!["W/\"a.1\""], false),
+        ];
+
+        for (values, expected) in tests {
+            assert_eq!(
+                expected,
+                if_match(&headers(IF_MATCH, &values), "\"a.1\""),
+                "{values:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        let tests = vec![
+            (vec![], false),
+            (vec!["\"a.1\""], true),
+            (vec!["W/\"a.1\""], true),
+            (vec!["\"a.0\"", "\"a.1\""], true),
+            (vec!["*"], true),
+            (vec!["\"a.0\""], false),
+        ];
+
+        for (values, expected) in tests {
+            assert_eq!(
+                expected,
+                if_none_match(&headers(IF_NONE_MATCH, &values), "\"a.1\""),
+                "{values:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use crate::config::RateLimitConfig;
+use http::Method;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often buckets that are full again are removed, so clients that stopped
+/// sending requests do not take up memory forever.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of requests allowed in a period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    /// The number of tokens that are added to a bucket per second.
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+/// Who a request is counted against.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    /// An authenticated principal, regardless of where its requests come
+    /// from.
+    Principal(String),
+
+    /// An anonymous client, identified by its address.
+    Ip(IpAddr),
+}
+
+/// The outcome of checking a request against its limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub quota: Quota,
+
+    /// Requests that can still be made right away.
+    pub remaining: u32,
+
+    /// Time until the full quota is available again.
+    pub reset: Duration,
+
+    /// Time until the next request is allowed, zero if it is allowed now.
+    pub retry_after: Duration,
+}
+
+#[derive(Clone, Debug)]
+struct RouteQuota {
+    path: String,
+    method: Option<Method>,
+    quota: Quota,
+}
+
+/// Key of a bucket: the index of the route limit, or `None` for the default
+/// limit, and the client.
+type BucketKey = (Option<usize>, ClientKey);
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(quota: Quota, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(quota.requests),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, quota: Quota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * quota.rate()).min(f64::from(quota.requests));
+        self.updated = now;
+    }
+
+    fn is_full(&self, quota: Quota) -> bool {
+        self.tokens >= f64::from(quota.requests)
+    }
+
+    fn take(&mut self, quota: Quota, now: Instant) -> Decision {
+        self.refill(quota, now);
+
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+
+        let rate = quota.rate();
+        let retry_after = if allowed {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / rate)
+        };
+
+        Decision {
+            allowed,
+            quota,
+            remaining: self.tokens.floor() as u32,
+            reset: Duration::from_secs_f64((f64::from(quota.requests) - self.tokens) / rate),
+            retry_after,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    last_pruned: Instant,
+}
+
+/// Token bucket rate limiter.
+///
+/// Every client gets a bucket per route limit, plus one bucket that is shared
+/// by all routes without a limit of their own. A bucket holds up to
+/// `requests` tokens and is refilled evenly over the `period`, so clients can
+/// send bursts of requests as long as they stay within the average rate.
+#[derive(Debug)]
+pub struct RateLimiter {
+    enabled: bool,
+    trusted_proxies: Vec<IpNet>,
+    default: Quota,
+    routes: Vec<RouteQuota>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// Create the rate limiter from the validated configuration.
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let routes = config
+            .routes
+            .iter()
+            .map(|route| RouteQuota {
+                path: route.path.clone(),
+                method: route
+                    .method
+                    .as_ref()
+                    .and_then(|method| Method::from_bytes(method.as_bytes()).ok()),
+                quota: Quota {
+                    requests: route.requests,
+                    period: route.period,
+                },
+            })
+            .collect();
+
+        Self {
+            enabled: config.enabled,
+            trusted_proxies: config.trusted_proxies.clone(),
+            default: Quota {
+                requests: config.requests,
+                period: config.period,
+            },
+            routes,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Count a request by `client` to `route` against its limit. Returns
+    /// `None` if rate limiting is disabled.
+    pub fn check(
+        &self,
+        method: &Method,
+        route: Option<&str>,
+        client: ClientKey,
+        now: Instant,
+    ) -> Option<Decision> {
+        if !self.enabled {
+            return None;
+        }
+
+        let index = route.and_then(|route| {
+            self.routes.iter().position(|limit| {
+                limit.path == route && limit.method.as_ref().is_none_or(|m| m == method)
+            })
+        });
+        let quota = self.quota(index);
+
+        let mut buckets = self.buckets.lock().expect("rate limiter lock is poisoned");
+        if now.saturating_duration_since(buckets.last_pruned) >= PRUNE_INTERVAL {
+            self.prune(&mut buckets, now);
+        }
+
+        let decision = buckets
+            .buckets
+            .entry((index, client))
+            .or_insert_with(|| Bucket::full(quota, now))
+            .take(quota, now);
+
+        Some(decision)
+    }
+
+    /// Determine the address of the client that sent a request.
+    ///
+    /// `X-Forwarded-For` is only used if the request comes from a trusted
+    /// proxy. Addresses are appended to it by every proxy, so it is read from
+    /// right to left and the first address that is not a trusted proxy is the
+    /// client. Everything to the left of it could have been sent by the client
+    /// itself.
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: &[&str]) -> IpAddr {
+        let mut client = peer;
+        if !self.is_trusted(client) {
+            return client;
+        }
+
+        let hops = forwarded_for
+            .iter()
+            .rev()
+            .flat_map(|header| header.rsplit(','));
+        for hop in hops {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+
+            client = hop;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+
+        client
+    }
+
+    fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&addr))
+    }
+
+    fn quota(&self, index: Option<usize>) -> Quota {
+        index.map_or(self.default, |index| self.routes[index].quota)
+    }
+
+    fn prune(&self, buckets: &mut Buckets, now: Instant) {
+        buckets.buckets.retain(|(index, _), bucket| {
+            let quota = self.quota(*index);
+            bucket.refill(quota, now);
+            !bucket.is_full(quota)
+        });
+        buckets.last_pruned = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RouteRateLimitConfig;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            enabled: true,
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            requests: 2,
+            period: Duration::from_secs(10),
+            routes: vec![RouteRateLimitConfig {
+                path: "/users".to_string(),
+                method: Some("POST".to_string()),
+                requests: 1,
+                period: Duration::from_secs(60),
+            }],
+        })
+    }
+
+    fn client() -> ClientKey {
+        ClientKey::Principal("ci".to_string())
+    }
+
+    #[test]
+    fn bucket_is_refilled_over_the_period() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let check = |now| {
+            limiter
+                .check(&Method::GET, Some("/users/:user_name"), client(), now)
+                .unwrap()
+        };
+
+        assert_eq!(1, check(start).remaining);
+        assert_eq!(0, check(start).remaining);
+
+        let rejected = check(start);
+        assert!(!rejected.allowed);
+        assert_eq!(Duration::from_secs(5), rejected.retry_after);
+        assert_eq!(Duration::from_secs(10), rejected.reset);
+
+        assert!(check(start + Duration::from_secs(5)).allowed);
+    }
+
+    #[test]
+    fn routes_have_their_own_limits() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        let create = limiter.check(&Method::POST, Some("/users"), client(), now);
+        assert_eq!(1, create.unwrap().quota.requests);
+        assert!(
+            !limiter
+                .check(&Method::POST, Some("/users"), client(), now)
+                .unwrap()
+                .allowed
+        );
+
+        // Other methods and other clients are not affected.
+        let list = limiter.check(&Method::GET, Some("/users"), client(), now);
+        assert!(list.unwrap().allowed);
+        let other = ClientKey::Principal("other".to_string());
+        assert!(
+            limiter
+                .check(&Method::POST, Some("/users"), other, now)
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn disabled_limiter_does_not_limit() {
+        let limiter = RateLimiter::new(&RateLimitConfig::default());
+
+        assert_eq!(
+            None,
+            limiter.check(&Method::GET, None, client(), Instant::now())
+        );
+    }
+
+    #[test]
+    fn client_ip_honors_trusted_proxies() {
+        let limiter = limiter();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        let tests = vec![
+            ("203.0.113.1", vec!["198.51.100.1"], "203.0.113.1"),
+            ("10.0.0.1", vec![], "10.0.0.1"),
+            ("10.0.0.1", vec!["198.51.100.1"], "198.51.100.1"),
+            (
+                "10.0.0.1",
+                vec!["1.1.1.1, 198.51.100.1, 10.0.0.2"],
+                "198.51.100.1",
+            ),
+            ("10.0.0.1", vec!["1.1.1.1", "198.51.100.1"], "198.51.100.1"),
+            ("10.0.0.1", vec!["garbage, 10.0.0.2"], "10.0.0.2"),
+        ];
+
+        for (peer, forwarded_for, expected) in tests {
+            assert_eq!(
+                ip(expected),
+                limiter.client_ip(ip(peer), &forwarded_for),
+                "{peer} {forwarded_for:?}"
+            );
+        }
+    }
+}
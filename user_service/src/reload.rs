@@ -0,0 +1,299 @@
+use crate::auth::{Authenticator, Authorizer};
+use crate::config::Config;
+use crate::middleware::cors::OriginMatcher;
+use crate::rate_limit::RateLimiter;
+use crate::validation::Validator;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Loads the configuration from the same sources as on startup: the
+/// configuration file, environment variables and flags.
+pub type ConfigLoader = Box<dyn Fn() -> Result<Config> + Send + Sync>;
+
+/// Handle to replace the log filter of the running subscriber.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// The settings that can be changed while the server is running.
+///
+/// These are swapped as a whole on a reload, so a request never sees a mix of
+/// old and new settings.
+#[derive(Debug)]
+pub struct Settings {
+    pub authenticator: Authenticator,
+    pub authorizer: Authorizer,
+    pub cors_origins: OriginMatcher,
+    pub validator: Validator,
+    pub rename_grace_period: Duration,
+    pub max_failed_logins: u32,
+
+    /// Shared with the settings before a reload if the rate limits did not
+    /// change, so clients keep their remaining quota.
+    pub rate_limiter: Arc<RateLimiter>,
+}
+
+impl Settings {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            authenticator: Authenticator::new(&config.auth),
+            authorizer: Authorizer::new(&config.auth),
+            cors_origins: OriginMatcher::new(&config.cors.allowed_origins),
+            validator: Validator::new(&config.validation),
+            rename_grace_period: config.renames.grace_period,
+            max_failed_logins: config.lockout.max_failed_logins,
+            rate_limiter: Arc::new(RateLimiter::new(&config.rate_limit)),
+        }
+    }
+}
+
+/// Settings shared between the reloader and the middleware that use them.
+pub type SharedSettings = Arc<ArcSwap<Settings>>;
+
+/// Reloads the configuration while the server is running.
+///
+/// Only the settings in [`Settings`] and the log filter are reloaded.
+/// Changes to other settings, like the listen address, require a restart;
+/// they are reported and ignored.
+pub struct Reloader {
+    load: ConfigLoader,
+    current: Mutex<Config>,
+    settings: SharedSettings,
+    log_filter: Option<LogFilterHandle>,
+}
+
+impl Reloader {
+    pub fn new(load: ConfigLoader, config: Config, log_filter: Option<LogFilterHandle>) -> Self {
+        let settings = Arc::new(ArcSwap::from_pointee(Settings::new(&config)));
+        Self {
+            load,
+            current: Mutex::new(config),
+            settings,
+            log_filter,
+        }
+    }
+
+    /// The configuration that is currently in use.
+    pub fn config(&self) -> Config {
+        self.current
+            .lock()
+            .expect("config lock is poisoned")
+            .clone()
+    }
+
+    pub fn settings(&self) -> SharedSettings {
+        self.settings.clone()
+    }
+
+    /// Load the configuration again and apply the settings that can be
+    /// changed at runtime. If the new configuration is invalid, nothing is
+    /// changed.
+    pub fn reload(&self) -> Result<()> {
+        let loaded = (self.load)()?;
+        loaded.validate()?;
+
+        let mut current = self.current.lock().expect("config lock is poisoned");
+        let mut next = current.clone();
+        apply_reloadable(&mut next, &loaded);
+
+        let log_filter = EnvFilter::try_new(&next.telemetry.log_filter)
+            .context("invalid telemetry.log_filter")?;
+
+        for change in diff(&next, &loaded) {
+            warn!(%change, "Configuration change requires a restart, ignoring it");
+        }
+
+        // Apply the settings even if the diff is empty, since changed secrets
+        // are redacted and do not show up in it.
+        let changes = diff(&current, &next);
+        if let Some(handle) = &self.log_filter {
+            handle
+                .reload(log_filter)
+                .context("unable to replace the log filter")?;
+        }
+        let mut settings = Settings::new(&next);
+        if next.rate_limit == current.rate_limit {
+            settings.rate_limiter = self.settings.load().rate_limiter.clone();
+        }
+        self.settings.store(Arc::new(settings));
+        *current = next;
+
+        for change in changes {
+            info!(%change, "Configuration changed");
+        }
+        info!("Configuration reloaded");
+
+        Ok(())
+    }
+
+    /// Reload the configuration, logging the outcome instead of returning it.
+    pub fn reload_and_log(&self) {
+        if let Err(err) = self.reload() {
+            error!("Unable to reload configuration, keeping the current one: {err:#}");
+        }
+    }
+
+    /// Reload the configuration whenever the file at `path` is modified,
+    /// checking every `interval`.
+    pub async fn watch(self: Arc<Self>, path: PathBuf, interval: Duration) {
+        let modified = |path: &PathBuf| -> Option<SystemTime> {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        };
+
+        let mut last_modified = modified(&path);
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let modified = modified(&path);
+            if modified != last_modified {
+                debug!(path = %path.display(), "Configuration file changed");
+                last_modified = modified;
+                self.reload_and_log();
+            }
+        }
+    }
+}
+
+/// Copy the settings that can be changed at runtime from `loaded`.
+fn apply_reloadable(config: &mut Config, loaded: &Config) {
+    config.telemetry.log_filter = loaded.telemetry.log_filter.clone();
+    config.auth = loaded.auth.clone();
+    config.cors.allowed_origins = loaded.cors.allowed_origins.clone();
+    config.validation = loaded.validation.clone();
+    config.renames = loaded.renames.clone();
+    config.lockout = loaded.lockout.clone();
+    config.rate_limit = loaded.rate_limit.clone();
+}
+
+/// List the settings that differ between two configurations, as
+/// `path: old -> new`. Secrets are redacted on both sides.
+fn diff(old: &Config, new: &Config) -> Vec<String> {
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+
+    let mut changes = Vec::new();
+    diff_values("", &old, &new, &mut changes);
+    changes
+}
+
+fn diff_values(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            let mut keys: Vec<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff_values(
+                    &path,
+                    old_fields.get(key).unwrap_or(&Value::Null),
+                    new_fields.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        (old, new) if old != new => changes.push(format!("{path}: {old} -> {new}")),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ApiKeyConfig, Secret};
+    use crate::rate_limit::ClientKey;
+    use http::Method;
+    use std::time::Instant;
+
+    fn reloader(load: impl Fn() -> Result<Config> + Send + Sync + 'static) -> Reloader {
+        Reloader::new(Box::new(load), Config::default(), None)
+    }
+
+    #[test]
+    fn diff_lists_changed_settings() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.telemetry.log_filter = "debug".to_string();
+        new.cors.allowed_origins = vec!["https://example.com".to_string()];
+
+        let changes = diff(&old, &new);
+
+        assert_eq!(
+            vec![
+                r#"cors.allowed_origins: [] -> ["https://example.com"]"#.to_string(),
+                r#"telemetry.log_filter: "error" -> "debug""#.to_string(),
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn reload_applies_reloadable_settings() {
+        let reloader = reloader(|| {
+            let mut config = Config::default();
+            config.auth.api_keys.push(ApiKeyConfig {
+                principal: "ci".to_string(),
+                key: Secret::new("s3cr3t"),
+                roles: Vec::new(),
+                tenant: None,
+            });
+            config.listener.address = "0.0.0.0:8080".parse().unwrap();
+            config.rate_limit.enabled = true;
+            Ok(config)
+        });
+
+        reloader.reload().unwrap();
+
+        let settings = reloader.settings().load();
+        assert!(settings
+            .authenticator
+            .authenticate(Some("Bearer s3cr3t"))
+            .is_ok());
+        assert!(settings
+            .rate_limiter
+            .check(
+                &Method::GET,
+                None,
+                ClientKey::Principal("ci".to_string()),
+                Instant::now()
+            )
+            .is_some());
+
+        // Reloading unchanged rate limits keeps the remaining quotas.
+        reloader.reload().unwrap();
+        assert!(Arc::ptr_eq(
+            &settings.rate_limiter,
+            &reloader.settings().load().rate_limiter
+        ));
+        assert_eq!(
+            Config::default().listener.address,
+            reloader.config().listener.address,
+            "structural settings are not reloaded"
+        );
+    }
+
+    #[test]
+    fn invalid_reload_keeps_current_settings() {
+        let reloader = reloader(|| {
+            let mut config = Config::default();
+            config.telemetry.log_filter = "[invalid".to_string();
+            config.cors.allowed_origins = vec!["https://example.com".to_string()];
+            Ok(config)
+        });
+
+        assert!(reloader.reload().is_err());
+
+        let origin = http::HeaderValue::from_static("https://example.com");
+        assert!(!reloader.settings().load().cors_origins.matches(&origin));
+    }
+}
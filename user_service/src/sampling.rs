@@ -0,0 +1,230 @@
+use clap::ValueEnum;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::Sampler;
+use opentelemetry::trace::{Status, TraceId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+
+/// The strategy used to decide which traces are exported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SamplingStrategy {
+    /// Export every trace.
+    Always,
+
+    /// Never export any trace.
+    Never,
+
+    /// Export a fraction of the traces, based on the trace id.
+    Ratio,
+
+    /// Follow the decision of the parent span, or use the ratio for root
+    /// spans.
+    ParentBased,
+
+    /// Export every trace that contains an error, and a fraction of the
+    /// successful traces.
+    ///
+    /// Whether a trace failed is decided per exported batch, so spans of a
+    /// failed trace that were exported in an earlier batch than the error
+    /// are only kept if the trace is within the ratio.
+    Rules,
+}
+
+impl SamplingStrategy {
+    /// Create the sampler that is used when spans are started.
+    ///
+    /// The `Rules` strategy cannot know whether a request will fail when its
+    /// span is started, so it samples everything here and leaves the decision
+    /// to [`RuleBasedExporter`].
+    pub fn sampler(self, ratio: f64) -> Sampler {
+        match self {
+            SamplingStrategy::Always => Sampler::AlwaysOn,
+            SamplingStrategy::Never => Sampler::AlwaysOff,
+            SamplingStrategy::Ratio => Sampler::TraceIdRatioBased(ratio),
+            SamplingStrategy::ParentBased => {
+                Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+            }
+            SamplingStrategy::Rules => Sampler::AlwaysOn,
+        }
+    }
+}
+
+/// Parse a sampling ratio, which has to be between 0.0 and 1.0 (inclusive).
+pub fn parse_ratio(input: &str) -> Result<f64, String> {
+    let ratio: f64 = input
+        .parse()
+        .map_err(|_| format!("`{input}` is not a number"))?;
+
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(format!("`{input}` is not between 0.0 and 1.0"));
+    }
+
+    Ok(ratio)
+}
+
+/// A span exporter that exports all traces that contain an error, and only a
+/// fraction of the successful traces.
+///
+/// A span is considered an error when its status is set to error, which
+/// happens for every handler that returns a `HandlerError`. All spans in the
+/// same batch that belong to a failed trace are kept as well, so the error
+/// does not show up without its surrounding spans.
+///
+/// Spans are not buffered until their trace ends: spans of a failed trace
+/// that ended up in an earlier batch than the error, like children that
+/// finished before their failing parent was exported, were already filtered
+/// by the ratio.
+#[derive(Debug)]
+pub struct RuleBasedExporter<E> {
+    inner: E,
+    ratio: f64,
+}
+
+impl<E> RuleBasedExporter<E> {
+    pub fn new(inner: E, ratio: f64) -> Self {
+        Self { inner, ratio }
+    }
+
+    fn filter(&self, batch: Vec<SpanData>) -> Vec<SpanData> {
+        let failed_traces: HashSet<TraceId> = batch
+            .iter()
+            .filter(|span| matches!(span.status, Status::Error { .. }))
+            .map(|span| span.span_context.trace_id())
+            .collect();
+
+        batch
+            .into_iter()
+            .filter(|span| {
+                let trace_id = span.span_context.trace_id();
+                failed_traces.contains(&trace_id) || sampled_by_ratio(self.ratio, trace_id)
+            })
+            .collect()
+    }
+}
+
+impl<E> SpanExporter for RuleBasedExporter<E>
+where
+    E: SpanExporter,
+{
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let batch = self.filter(batch);
+        if batch.is_empty() {
+            return Box::pin(async { Ok(()) });
+        }
+
+        self.inner.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.inner.force_flush()
+    }
+}
+
+/// Decide whether a trace is sampled, using the same algorithm as the
+/// `TraceIdRatioBased` sampler. This keeps the decision consistent for all
+/// spans of a single trace.
+fn sampled_by_ratio(ratio: f64, trace_id: TraceId) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+
+    let upper_bound = (ratio.max(0.0) * (1u64 << 63) as f64) as u64;
+    let bytes = trace_id.to_bytes();
+    let low = u64::from_be_bytes(bytes[8..].try_into().expect("trace id has 16 bytes"));
+
+    (low >> 1) < upper_bound
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opentelemetry::sdk::trace::{EvictedHashMap, EvictedQueue};
+    use opentelemetry::sdk::{InstrumentationLibrary, Resource};
+    use opentelemetry::trace::{SpanContext, SpanId, SpanKind, TraceFlags, TraceState};
+    use std::borrow::Cow;
+    use std::time::SystemTime;
+
+    fn span(trace_id: &str, span_id: u64, status: Status) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_hex(trace_id).unwrap(),
+                SpanId::from_bytes(span_id.to_be_bytes()),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::INVALID,
+            span_kind: SpanKind::Server,
+            name: Cow::Borrowed("request"),
+            start_time: SystemTime::now(),
+            end_time: SystemTime::now(),
+            attributes: EvictedHashMap::new(16, 16),
+            events: EvictedQueue::new(16),
+            links: EvictedQueue::new(16),
+            status,
+            resource: Cow::Owned(Resource::empty()),
+            instrumentation_lib: InstrumentationLibrary::new("test", None, None),
+        }
+    }
+
+    #[test]
+    fn parse_ratio_bounds() {
+        assert_eq!(Ok(0.0), parse_ratio("0"));
+        assert_eq!(Ok(0.25), parse_ratio("0.25"));
+        assert_eq!(Ok(1.0), parse_ratio("1.0"));
+        assert!(parse_ratio("-0.1").is_err());
+        assert!(parse_ratio("1.1").is_err());
+        assert!(parse_ratio("half").is_err());
+    }
+
+    #[test]
+    fn failed_traces_are_kept_and_the_rest_sampled() {
+        // Outside the ratio, but with an error in one of its spans.
+        let failed = "0000000000000000fffffffffffffffe";
+        let sampled = "ffffffffffffffff0000000000000001";
+        let dropped = "0000000000000000fffffffffffffff0";
+        let batch = vec![
+            span(failed, 1, Status::Unset),
+            span(failed, 2, Status::error("store unavailable")),
+            span(failed, 3, Status::Ok),
+            span(sampled, 4, Status::Ok),
+            span(dropped, 5, Status::Ok),
+        ];
+
+        let exporter = RuleBasedExporter::new((), 0.5);
+        let kept: Vec<u64> = exporter
+            .filter(batch)
+            .iter()
+            .map(|span| u64::from_be_bytes(span.span_context.span_id().to_bytes()))
+            .collect();
+
+        assert_eq!(vec![1, 2, 3, 4], kept);
+    }
+
+    #[test]
+    fn sampled_by_ratio_extremes() {
+        let trace_id = TraceId::from_hex("58406520a006649127e371903a2de979").unwrap();
+
+        assert!(sampled_by_ratio(1.0, trace_id));
+        assert!(!sampled_by_ratio(0.0, trace_id));
+    }
+
+    #[test]
+    fn sampled_by_ratio_uses_lower_half_of_trace_id() {
+        let low = TraceId::from_hex("ffffffffffffffff0000000000000001").unwrap();
+        let high = TraceId::from_hex("0000000000000000fffffffffffffffe").unwrap();
+
+        assert!(sampled_by_ratio(0.5, low));
+        assert!(!sampled_by_ratio(0.5, high));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::audit::RecentDenials;
+use crate::db::Store;
+use crate::metrics::Metrics;
+use crate::reload::SharedSettings;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// State shared by all handlers.
+#[derive(Clone)]
+pub struct AppState {
+    pub store: Arc<dyn Store>,
+    pub lifecycle: Lifecycle,
+    pub metrics: Arc<Metrics>,
+    pub settings: SharedSettings,
+
+    /// The rejected requests that were recorded in the audit log recently,
+    /// shared by everything that records them.
+    pub denials: Arc<RecentDenials>,
+}
+
+impl AppState {
+    pub fn new(store: Arc<dyn Store>, settings: SharedSettings) -> Self {
+        Self {
+            store,
+            lifecycle: Lifecycle::default(),
+            metrics: Arc::default(),
+            settings,
+            denials: Arc::new(RecentDenials::new()),
+        }
+    }
+}
+
+/// Tracks whether the server is shutting down.
+///
+/// Once draining has started the server still handles requests, but it
+/// reports itself as not ready so load balancers stop sending new traffic.
+#[derive(Clone, Debug, Default)]
+pub struct Lifecycle {
+    draining: Arc<AtomicBool>,
+}
+
+impl Lifecycle {
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,336 @@
+use crate::config::{TenancyConfig, TenantSource};
+use crate::db::TenantStore;
+use crate::models::AuthError;
+use crate::state::AppState;
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use http::header::HOST;
+use http::request::Parts;
+use http::{HeaderName, Request};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// The tenant requests are made for if tenancy is not used.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Maximum length of a tenant id, the maximum length of a DNS label, so
+/// every tenant id can be used as a subdomain.
+const MAX_TENANT_LENGTH: usize = 63;
+
+/// Identifier of a tenant: 1 to 63 lowercase ASCII letters, digits and
+/// hyphens, not starting or ending with a hyphen.
+///
+/// Once a request has been authenticated its tenant is stored in the request
+/// and response extensions, like its principal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TenantId(String);
+
+impl TenantId {
+    /// Parse a tenant id, explaining why it is invalid if it is.
+    pub fn parse(id: impl Into<String>) -> Result<Self, String> {
+        let id = id.into();
+        if id.is_empty() || id.len() > MAX_TENANT_LENGTH {
+            return Err(format!(
+                "tenant has to be between 1 and {MAX_TENANT_LENGTH} characters long"
+            ));
+        }
+        if !id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        {
+            return Err(
+                "tenant may only contain lowercase letters, digits and hyphens".to_string(),
+            );
+        }
+        if id.starts_with('-') || id.ends_with('-') {
+            return Err("tenant may not start or end with a hyphen".to_string());
+        }
+
+        Ok(Self(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for TenantId {
+    fn default() -> Self {
+        Self(DEFAULT_TENANT.to_string())
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for TenantId {
+    type Error = String;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::parse(id)
+    }
+}
+
+impl From<TenantId> for String {
+    fn from(id: TenantId) -> Self {
+        id.0
+    }
+}
+
+/// Determines the tenant of a request, as configured in [`TenancyConfig`].
+#[derive(Clone, Debug)]
+pub struct TenantResolver {
+    source: TenantSource,
+    header: HeaderName,
+    base_domain: String,
+    default_tenant: TenantId,
+    allowed: HashSet<TenantId>,
+}
+
+impl TenantResolver {
+    /// Create a resolver from a configuration that has been validated.
+    pub fn new(config: &TenancyConfig) -> Self {
+        Self {
+            source: config.source,
+            header: HeaderName::try_from(config.header.as_str())
+                .expect("tenancy.header is validated"),
+            base_domain: format!(".{}", config.base_domain.as_deref().unwrap_or_default()),
+            default_tenant: config.default_tenant.clone(),
+            allowed: config.allowed_tenants.iter().cloned().collect(),
+        }
+    }
+
+    /// The tenant a request is made for. `key_tenant` is the tenant the API
+    /// key of the request belongs to, if it belongs to one: such keys are
+    /// only accepted for requests to their own tenant.
+    pub fn resolve<B>(
+        &self,
+        req: &Request<B>,
+        key_tenant: Option<&TenantId>,
+    ) -> Result<TenantId, AuthError> {
+        let tenant = match self.source {
+            TenantSource::None => self.default_tenant.clone(),
+            TenantSource::Header => {
+                let value = req
+                    .headers()
+                    .get(&self.header)
+                    .ok_or(AuthError::MissingTenant)?;
+                let value = value.to_str().map_err(|_| AuthError::InvalidTenant {
+                    reason: "tenant is not valid ASCII".to_string(),
+                })?;
+                parse(value.trim())?
+            }
+            TenantSource::Subdomain => parse(self.subdomain(req)?)?,
+            TenantSource::ApiKey => key_tenant.cloned().ok_or(AuthError::MissingTenant)?,
+        };
+
+        if !self.allowed.is_empty() && !self.allowed.contains(&tenant) {
+            return Err(AuthError::InvalidTenant {
+                reason: format!("{tenant} is not a known tenant"),
+            });
+        }
+        if key_tenant.is_some_and(|key_tenant| *key_tenant != tenant) {
+            return Err(AuthError::TenantNotAllowed);
+        }
+
+        Ok(tenant)
+    }
+
+    /// The label in front of the base domain in the host of the request.
+    /// HTTP/2 requests carry the host in their URI instead of a header.
+    fn subdomain<'a, B>(&self, req: &'a Request<B>) -> Result<&'a str, AuthError> {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| req.uri().host())
+            .ok_or(AuthError::MissingTenant)?;
+        let host = host.rsplit_once(':').map_or(host, |(host, _port)| host);
+
+        let len = host.len();
+        let suffix = self.base_domain.len();
+        if len <= suffix || !host[len - suffix..].eq_ignore_ascii_case(&self.base_domain) {
+            return Err(AuthError::MissingTenant);
+        }
+
+        Ok(&host[..len - suffix])
+    }
+}
+
+fn parse(tenant: &str) -> Result<TenantId, AuthError> {
+    TenantId::parse(tenant.to_ascii_lowercase())
+        .map_err(|reason| AuthError::InvalidTenant { reason })
+}
+
+/// The part of the store that belongs to the tenant of a request.
+///
+/// Handlers only get to users and groups through this, so they cannot read
+/// or change the data of any tenant but the one [`AuthLayer`] determined for
+/// the request.
+///
+/// [`AuthLayer`]: crate::middleware::auth::AuthLayer
+#[derive(Clone)]
+pub struct Tenant {
+    pub id: TenantId,
+    pub store: Arc<dyn TenantStore>,
+}
+
+impl Tenant {
+    pub fn new(state: &AppState, id: &TenantId) -> Self {
+        Self {
+            id: id.clone(),
+            store: state.store.tenant(id),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AuthError> {
+        let id = parts
+            .extensions
+            .get::<TenantId>()
+            .ok_or(AuthError::MissingTenant)?;
+
+        Ok(Self::new(state, id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tenant(id: &str) -> TenantId {
+        TenantId::parse(id).unwrap()
+    }
+
+    fn resolver(source: TenantSource) -> TenantResolver {
+        TenantResolver::new(&TenancyConfig {
+            source,
+            base_domain: Some("users.example.com".to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn request(header: Option<(HeaderName, &str)>) -> Request<()> {
+        let mut builder = Request::builder().uri("/users/jane");
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn parse_tenant_ids() {
+        for valid in ["acme", "a", "acme-2", &"a".repeat(63)] {
+            assert!(TenantId::parse(valid).is_ok(), "{valid}");
+        }
+        for invalid in [
+            "",
+            "Acme",
+            "acme.com",
+            "-acme",
+            "acme-",
+            "ac me",
+            &"a".repeat(64),
+        ] {
+            assert!(TenantId::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn resolve_tenants() {
+        use TenantSource::*;
+        let tenant_header = HeaderName::from_static("x-tenant-id");
+        let acme = tenant("acme");
+        let tests = vec![
+            (
+                None,
+                request(Option::None),
+                Option::None,
+                Ok(TenantId::default()),
+            ),
+            (
+                Header,
+                request(Some((tenant_header.clone(), "ACME"))),
+                Option::None,
+                Ok(acme.clone()),
+            ),
+            (
+                Header,
+                request(Option::None),
+                Option::None,
+                Err(AuthError::MissingTenant),
+            ),
+            (
+                Subdomain,
+                request(Some((HOST, "acme.Users.example.com:3000"))),
+                Option::None,
+                Ok(acme.clone()),
+            ),
+            (
+                Subdomain,
+                request(Some((HOST, "users.example.com"))),
+                Option::None,
+                Err(AuthError::MissingTenant),
+            ),
+            (
+                Subdomain,
+                request(Some((HOST, "a.acme.users.example.com"))),
+                Option::None,
+                Err(AuthError::InvalidTenant {
+                    reason: "tenant may only contain lowercase letters, digits and hyphens"
+                        .to_string(),
+                }),
+            ),
+            (ApiKey, request(Option::None), Some(&acme), Ok(acme.clone())),
+            (
+                ApiKey,
+                request(Option::None),
+                Option::None,
+                Err(AuthError::MissingTenant),
+            ),
+            (
+                Header,
+                request(Some((tenant_header, "globex"))),
+                Some(&acme),
+                Err(AuthError::TenantNotAllowed),
+            ),
+        ];
+
+        for (source, request, key_tenant, expected) in tests {
+            let actual = resolver(source).resolve(&request, key_tenant);
+
+            assert_eq!(expected, actual, "{source:?} {:?}", request.headers());
+        }
+    }
+
+    #[test]
+    fn only_allowed_tenants_are_resolved() {
+        let resolver = TenantResolver::new(&TenancyConfig {
+            source: TenantSource::Header,
+            allowed_tenants: vec![tenant("acme")],
+            ..Default::default()
+        });
+        let header = |value| Some((HeaderName::from_static("x-tenant-id"), value));
+
+        assert_eq!(
+            Ok(tenant("acme")),
+            resolver.resolve(&request(header("acme")), None)
+        );
+        assert_eq!(
+            Err(AuthError::InvalidTenant {
+                reason: "globex is not a known tenant".to_string()
+            }),
+            resolver.resolve(&request(header("globex")), None)
+        );
+    }
+}
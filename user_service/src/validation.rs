@@ -0,0 +1,753 @@
+use crate::config::{NameValidationConfig, UsernameValidationConfig, ValidationConfig};
+use crate::db::UsernameKeys;
+use crate::models::{
+    Attributes, GroupUpdate, InvalidFieldReason, InvalidNewUserReason, NewGroup, NewUser,
+    StatusChange, UserUpdate, UsernameChange,
+};
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::paths::JSONPointer;
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Maximum length of an email address, in bytes, as limited by SMTP.
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Maximum length of the reason for a status change, in characters.
+const MAX_REASON_LENGTH: usize = 500;
+
+/// Maximum length of a group name, in characters.
+const MAX_GROUP_NAME_LENGTH: usize = 64;
+
+/// Maximum length of the description of a group, in characters.
+const MAX_DESCRIPTION_LENGTH: usize = 500;
+
+/// A class of characters that usernames may consist of.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterClass {
+    /// `a` to `z`.
+    AsciiLowercase,
+
+    /// `A` to `Z`.
+    AsciiUppercase,
+
+    /// `0` to `9`.
+    AsciiDigit,
+
+    /// Letters of any script.
+    Letter,
+
+    /// Digits and other numeric characters of any script.
+    Number,
+}
+
+impl CharacterClass {
+    fn contains(self, c: char) -> bool {
+        match self {
+            CharacterClass::AsciiLowercase => c.is_ascii_lowercase(),
+            CharacterClass::AsciiUppercase => c.is_ascii_uppercase(),
+            CharacterClass::AsciiDigit => c.is_ascii_digit(),
+            CharacterClass::Letter => c.is_alphabetic(),
+            CharacterClass::Number => c.is_numeric(),
+        }
+    }
+}
+
+/// The canonical form of a username: NFKC normalized and case folded, so
+/// that `Alice`, `alice`, `ＡＬＩＣＥ` and the composed and decomposed forms
+/// of `Zoë` are all the same username.
+pub fn canonical_username(username: &str) -> String {
+    let normalized: String = username.nfkc().collect();
+    // Case folding can produce characters that are not normalized, like the
+    // decomposed form of a folded `İ`.
+    caseless::default_case_fold_str(&normalized)
+        .nfkc()
+        .collect()
+}
+
+/// Checks new users against the rules in the `[validation]` configuration.
+#[derive(Debug)]
+pub struct Validator {
+    username: UsernameValidationConfig,
+    name: NameValidationConfig,
+
+    /// The unique keys of the reserved usernames.
+    reserved: HashSet<String>,
+
+    /// The schema that attributes have to match, if any.
+    attributes: Option<JSONSchema>,
+}
+
+impl Validator {
+    pub fn new(config: &ValidationConfig) -> Self {
+        let mut validator = Self {
+            username: config.username.clone(),
+            name: config.name.clone(),
+            reserved: HashSet::new(),
+            attributes: config.attributes.schema.as_ref().map(|schema| {
+                JSONSchema::compile(schema)
+                    .expect("the attributes schema is checked when the configuration is loaded")
+            }),
+        };
+        validator.reserved = config
+            .username
+            .reserved
+            .iter()
+            .map(|username| validator.username_keys(username).unique)
+            .collect();
+
+        validator
+    }
+
+    /// Check every field of a new user, returning all violations at once so
+    /// the client can fix them in a single round trip.
+    pub fn validate_new_user(&self, new_user: &NewUser) -> Result<(), Vec<InvalidNewUserReason>> {
+        let fields = collect_violations([
+            ("username", self.validate_username(&new_user.username)),
+            ("name", self.validate_name(&new_user.name)),
+            ("email", validate_email(new_user.email.as_deref())),
+        ]);
+
+        merge_violations(fields, self.validate_attributes(&new_user.attributes))
+    }
+
+    /// Check the changed fields of an update with the same rules as for new
+    /// users.
+    pub fn validate_user_update(
+        &self,
+        update: &UserUpdate,
+    ) -> Result<(), Vec<InvalidNewUserReason>> {
+        let name = update.name.as_deref().map(|name| self.validate_name(name));
+        let email = update
+            .email
+            .as_ref()
+            .map(|email| validate_email(email.as_deref()));
+
+        let fields = collect_violations([
+            ("name", name.unwrap_or_default()),
+            ("email", email.unwrap_or_default()),
+        ]);
+
+        match &update.attributes {
+            Some(attributes) => merge_violations(fields, self.validate_attributes(attributes)),
+            None => fields,
+        }
+    }
+
+    /// Check a new username with the same rules as for new users.
+    pub fn validate_username_change(
+        &self,
+        change: &UsernameChange,
+    ) -> Result<(), Vec<InvalidNewUserReason>> {
+        collect_violations([("username", self.validate_username(&change.username))])
+    }
+
+    /// Check that a status change is explained: its reason is kept with the
+    /// transition, for whoever has to understand it later.
+    pub fn validate_status_change(
+        &self,
+        change: &StatusChange,
+    ) -> Result<(), Vec<InvalidNewUserReason>> {
+        collect_violations([("reason", validate_reason(&change.reason))])
+    }
+
+    /// Check a new group. Group names appear in URLs and are compared as they
+    /// are, so unlike usernames they are limited to lowercase ASCII.
+    pub fn validate_new_group(
+        &self,
+        new_group: &NewGroup,
+    ) -> Result<(), Vec<InvalidNewUserReason>> {
+        collect_violations([
+            ("name", validate_group_name(&new_group.name)),
+            (
+                "description",
+                validate_description(new_group.description.as_deref()),
+            ),
+        ])
+    }
+
+    /// Check the changed fields of a group with the same rules as for new
+    /// groups.
+    pub fn validate_group_update(
+        &self,
+        update: &GroupUpdate,
+    ) -> Result<(), Vec<InvalidNewUserReason>> {
+        let description = update
+            .description
+            .as_ref()
+            .map(|description| validate_description(description.as_deref()));
+
+        collect_violations([("description", description.unwrap_or_default())])
+    }
+
+    /// The keys a user with this username is stored under. Usernames with
+    /// the same [canonical form](canonical_username) have the same keys, and
+    /// if confusables are rejected, so do usernames that look the same.
+    pub fn username_keys(&self, username: &str) -> UsernameKeys {
+        let lookup = canonical_username(username);
+        let unique = if self.username.reject_confusables {
+            skeleton(&lookup).collect()
+        } else {
+            lookup.clone()
+        };
+
+        UsernameKeys { lookup, unique }
+    }
+
+    /// Usernames are checked in their composed form, so a letter and its
+    /// combining accents count as one character whichever way the client
+    /// encoded it.
+    fn validate_username(&self, username: &str) -> Vec<InvalidFieldReason> {
+        let rules = &self.username;
+        let mut reasons = Vec::new();
+        let username: String = username.nfc().collect();
+        let username = username.as_str();
+
+        let length = username.chars().count();
+        if length < rules.min_length {
+            reasons.push(InvalidFieldReason::TooShort);
+        } else if length > rules.max_length {
+            reasons.push(InvalidFieldReason::TooLong);
+        }
+
+        let allowed = |c: char| {
+            rules.allowed_symbols.contains(c)
+                || rules
+                    .allowed_characters
+                    .iter()
+                    .any(|class| class.contains(c))
+        };
+        if !username.chars().all(allowed) {
+            reasons.push(InvalidFieldReason::InvalidCharacters);
+        }
+
+        if rules.reject_confusables && !username.is_single_script() {
+            reasons.push(InvalidFieldReason::MixedScripts);
+        }
+
+        if !username.is_empty() && self.reserved.contains(&self.username_keys(username).unique) {
+            reasons.push(InvalidFieldReason::Reserved);
+        }
+
+        reasons
+    }
+
+    /// Names are free text, measured in user-perceived characters so that
+    /// accents and emoji count as one.
+    fn validate_name(&self, name: &str) -> Vec<InvalidFieldReason> {
+        let rules = &self.name;
+        let mut reasons = Vec::new();
+
+        // Surrounding whitespace counts for neither bound.
+        let length = name.trim().graphemes(true).count();
+        if length < rules.min_length {
+            reasons.push(InvalidFieldReason::TooShort);
+        } else if length > rules.max_length {
+            reasons.push(InvalidFieldReason::TooLong);
+        }
+
+        if name.chars().any(char::is_control) {
+            reasons.push(InvalidFieldReason::InvalidCharacters);
+        }
+
+        reasons
+    }
+
+    /// Attributes are checked against the schema of the deployment. Every
+    /// error of the schema is reported on the path of the attribute it is
+    /// about, like `attributes.locale`.
+    fn validate_attributes(
+        &self,
+        attributes: &Attributes,
+    ) -> Result<(), Vec<InvalidNewUserReason>> {
+        let Some(schema) = &self.attributes else {
+            return Ok(());
+        };
+
+        let attributes = Value::Object(attributes.clone());
+        let Err(errors) = schema.validate(&attributes) else {
+            return Ok(());
+        };
+
+        let mut violations = Vec::new();
+        for error in errors {
+            let field = attribute_path(&error.instance_path);
+            let (fields, reason) = match error.kind {
+                ValidationErrorKind::Required { property } => {
+                    let property = property.as_str().unwrap_or_default();
+                    (
+                        vec![format!("{field}.{property}")],
+                        InvalidFieldReason::Missing,
+                    )
+                }
+                ValidationErrorKind::AdditionalProperties { unexpected } => (
+                    unexpected
+                        .iter()
+                        .map(|property| format!("{field}.{property}"))
+                        .collect(),
+                    InvalidFieldReason::Unexpected,
+                ),
+                ValidationErrorKind::MinLength { .. }
+                | ValidationErrorKind::MinItems { .. }
+                | ValidationErrorKind::MinProperties { .. } => {
+                    (vec![field], InvalidFieldReason::TooShort)
+                }
+                ValidationErrorKind::MaxLength { .. }
+                | ValidationErrorKind::MaxItems { .. }
+                | ValidationErrorKind::MaxProperties { .. } => {
+                    (vec![field], InvalidFieldReason::TooLong)
+                }
+                _ => (vec![field], InvalidFieldReason::InvalidFormat),
+            };
+            violations.extend(
+                fields
+                    .into_iter()
+                    .map(|field| InvalidNewUserReason { field, reason }),
+            );
+        }
+
+        Err(violations)
+    }
+}
+
+/// The path of an attribute in the request body, like `attributes.tags.0`.
+fn attribute_path(pointer: &JSONPointer) -> String {
+    std::iter::once("attributes".to_string())
+        .chain(pointer.clone().into_vec())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn merge_violations(
+    first: Result<(), Vec<InvalidNewUserReason>>,
+    second: Result<(), Vec<InvalidNewUserReason>>,
+) -> Result<(), Vec<InvalidNewUserReason>> {
+    match (first, second) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(violations), Ok(())) | (Ok(()), Err(violations)) => Err(violations),
+        (Err(mut first), Err(second)) => {
+            first.extend(second);
+            Err(first)
+        }
+    }
+}
+
+fn collect_violations<const N: usize>(
+    fields: [(&str, Vec<InvalidFieldReason>); N],
+) -> Result<(), Vec<InvalidNewUserReason>> {
+    let violations: Vec<InvalidNewUserReason> = fields
+        .into_iter()
+        .flat_map(|(field, reasons)| {
+            reasons.into_iter().map(move |reason| InvalidNewUserReason {
+                field: field.to_string(),
+                reason,
+            })
+        })
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+fn validate_reason(reason: &str) -> Vec<InvalidFieldReason> {
+    let mut reasons = Vec::new();
+    if reason.trim().is_empty() {
+        reasons.push(InvalidFieldReason::TooShort);
+    } else if reason.chars().count() > MAX_REASON_LENGTH {
+        reasons.push(InvalidFieldReason::TooLong);
+    }
+
+    if reason.chars().any(char::is_control) {
+        reasons.push(InvalidFieldReason::InvalidCharacters);
+    }
+
+    reasons
+}
+
+fn validate_group_name(name: &str) -> Vec<InvalidFieldReason> {
+    let mut reasons = Vec::new();
+    if name.is_empty() {
+        reasons.push(InvalidFieldReason::TooShort);
+    } else if name.len() > MAX_GROUP_NAME_LENGTH {
+        reasons.push(InvalidFieldReason::TooLong);
+    }
+
+    let allowed = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c);
+    if !name.chars().all(allowed) {
+        reasons.push(InvalidFieldReason::InvalidCharacters);
+    }
+
+    reasons
+}
+
+fn validate_description(description: Option<&str>) -> Vec<InvalidFieldReason> {
+    let Some(description) = description else {
+        return Vec::new();
+    };
+
+    let mut reasons = Vec::new();
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        reasons.push(InvalidFieldReason::TooLong);
+    }
+    if description.chars().any(char::is_control) {
+        reasons.push(InvalidFieldReason::InvalidCharacters);
+    }
+
+    reasons
+}
+
+/// Email addresses are only checked for their rough shape, `local@domain`,
+/// whether they exist can only be verified by sending mail to them.
+fn validate_email(email: Option<&str>) -> Vec<InvalidFieldReason> {
+    let Some(email) = email else {
+        return Vec::new();
+    };
+
+    let mut reasons = Vec::new();
+    if email.len() > MAX_EMAIL_LENGTH {
+        reasons.push(InvalidFieldReason::TooLong);
+    }
+
+    let well_formed = email.split_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && domain.contains('.')
+            && domain.split('.').all(|label| !label.is_empty())
+            && !domain.contains('@')
+    });
+    if !well_formed {
+        reasons.push(InvalidFieldReason::InvalidFormat);
+    }
+
+    if email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        reasons.push(InvalidFieldReason::InvalidCharacters);
+    }
+
+    reasons
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn violation(field: &str, reason: InvalidFieldReason) -> InvalidNewUserReason {
+        InvalidNewUserReason {
+            field: field.to_string(),
+            reason,
+        }
+    }
+
+    fn validate(validator: &Validator, username: &str, name: &str) -> Vec<InvalidNewUserReason> {
+        let new_user = NewUser {
+            username: username.to_string(),
+            name: name.to_string(),
+            email: None,
+            attributes: Attributes::new(),
+        };
+
+        validator
+            .validate_new_user(&new_user)
+            .err()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn all_violations_are_reported() {
+        let validator = Validator::new(&ValidationConfig::default());
+        let tests = vec![
+            ("jane", "Jane Doe", vec![]),
+            // Twenty characters, padded with whitespace.
+            ("jane", "  Jane Doe-Smithsonian  ", vec![]),
+            (
+                "a_username_that_is_too_long",
+                "",
+                vec![
+                    violation("username", InvalidFieldReason::TooLong),
+                    violation("name", InvalidFieldReason::TooShort),
+                ],
+            ),
+            (
+                "jane doe!",
+                "Jane\nDoe",
+                vec![
+                    violation("username", InvalidFieldReason::InvalidCharacters),
+                    violation("name", InvalidFieldReason::InvalidCharacters),
+                ],
+            ),
+            (
+                "Admin",
+                "Jane",
+                vec![violation("username", InvalidFieldReason::Reserved)],
+            ),
+            (
+                // "rn" looks like "m".
+                "adrnin",
+                "Jane",
+                vec![violation("username", InvalidFieldReason::Reserved)],
+            ),
+            (
+                // The first letter is a Cyrillic "а".
+                "\u{430}dmin",
+                "Jane",
+                vec![
+                    violation("username", InvalidFieldReason::InvalidCharacters),
+                    violation("username", InvalidFieldReason::MixedScripts),
+                    violation("username", InvalidFieldReason::Reserved),
+                ],
+            ),
+        ];
+
+        for (username, name, expected) in tests {
+            let violations = validate(&validator, username, name);
+
+            assert_eq!(expected, violations, "{username:?} {name:?}");
+        }
+    }
+
+    #[test]
+    fn rules_are_configurable() {
+        let mut config = ValidationConfig::default();
+        config.username.allowed_characters = vec![CharacterClass::Letter];
+        config.username.allowed_symbols = String::new();
+        config.username.reserved = vec!["staff".to_string()];
+        config.username.reject_confusables = false;
+        config.name.max_length = 3;
+        let validator = Validator::new(&config);
+
+        assert!(validate(&validator, "Jürgen", "Jü").is_empty());
+        assert!(validate(&validator, "admin", "Jan").is_empty());
+        assert_eq!(
+            vec![
+                violation("username", InvalidFieldReason::InvalidCharacters),
+                violation("name", InvalidFieldReason::TooLong),
+            ],
+            validate(&validator, "jane_doe", "Jane")
+        );
+        assert_eq!(
+            vec![violation("username", InvalidFieldReason::Reserved)],
+            validate(&validator, "STAFF", "Jan")
+        );
+    }
+
+    #[test]
+    fn only_changed_fields_are_validated() {
+        let validator = Validator::new(&ValidationConfig::default());
+        let tests = vec![
+            (UserUpdate::default(), vec![]),
+            (
+                UserUpdate {
+                    name: Some("".to_string()),
+                    email: Some(Some("jane".to_string())),
+                    attributes: None,
+                },
+                vec![
+                    violation("name", InvalidFieldReason::TooShort),
+                    violation("email", InvalidFieldReason::InvalidFormat),
+                ],
+            ),
+            (
+                UserUpdate {
+                    name: None,
+                    email: Some(None),
+                    attributes: None,
+                },
+                vec![],
+            ),
+        ];
+
+        for (update, expected) in tests {
+            let violations = validator
+                .validate_user_update(&update)
+                .err()
+                .unwrap_or_default();
+
+            assert_eq!(expected, violations, "{update:?}");
+        }
+    }
+
+    #[test]
+    fn attributes_are_checked_against_the_schema() {
+        let mut config = ValidationConfig::default();
+        config.attributes.schema = Some(serde_json::json!({
+            "type": "object",
+            "required": ["locale"],
+            "additionalProperties": false,
+            "properties": {
+                "locale": { "type": "string", "pattern": "^[a-z]{2}(-[A-Z]{2})?$" },
+                "department": { "type": "string", "maxLength": 5 },
+                "tags": { "type": "array", "items": { "type": "string" } },
+            },
+        }));
+        let validator = Validator::new(&config);
+        let attributes = |value: Value| match value {
+            Value::Object(attributes) => attributes,
+            _ => unreachable!(),
+        };
+        let tests = vec![
+            (
+                serde_json::json!({ "locale": "en-GB", "tags": ["a"] }),
+                vec![],
+            ),
+            (
+                serde_json::json!({ "department": "engineering", "team": "core" }),
+                vec![
+                    violation("attributes.locale", InvalidFieldReason::Missing),
+                    violation("attributes.team", InvalidFieldReason::Unexpected),
+                    violation("attributes.department", InvalidFieldReason::TooLong),
+                ],
+            ),
+            (
+                serde_json::json!({ "locale": "english", "tags": ["a", 1] }),
+                vec![
+                    violation("attributes.locale", InvalidFieldReason::InvalidFormat),
+                    violation("attributes.tags.1", InvalidFieldReason::InvalidFormat),
+                ],
+            ),
+        ];
+
+        for (value, mut expected) in tests {
+            let update = UserUpdate {
+                attributes: Some(attributes(value.clone())),
+                ..UserUpdate::default()
+            };
+            let mut violations = validator
+                .validate_user_update(&update)
+                .err()
+                .unwrap_or_default();
+            violations.sort_by(|a, b| a.field.cmp(&b.field));
+            expected.sort_by(|a, b| a.field.cmp(&b.field));
+
+            assert_eq!(expected, violations, "{value}");
+        }
+    }
+
+    #[test]
+    fn email_addresses() {
+        let tests = vec![
+            (None, vec![]),
+            (Some("jane@example.com"), vec![]),
+            (Some("jane"), vec![InvalidFieldReason::InvalidFormat]),
+            (
+                Some("jane@localhost"),
+                vec![InvalidFieldReason::InvalidFormat],
+            ),
+            (
+                Some("@example.com"),
+                vec![InvalidFieldReason::InvalidFormat],
+            ),
+            (
+                Some("jane@example..com"),
+                vec![InvalidFieldReason::InvalidFormat],
+            ),
+            (
+                Some("jane doe@example.com"),
+                vec![InvalidFieldReason::InvalidCharacters],
+            ),
+        ];
+
+        for (email, expected) in tests {
+            assert_eq!(expected, validate_email(email), "{email:?}");
+        }
+    }
+
+    #[test]
+    fn equivalent_usernames_have_the_same_canonical_form() {
+        let tests = vec![
+            ("Alice", "alice"),
+            ("ＡＬＩＣＥ", "alice"),
+            ("Zo\u{eb}", "zo\u{eb}"),
+            ("Zoe\u{308}", "zo\u{eb}"),
+            ("STRASSE", "strasse"),
+            ("Stra\u{df}e", "strasse"),
+        ];
+
+        for (username, expected) in tests {
+            assert_eq!(expected, canonical_username(username), "{username:?}");
+        }
+    }
+
+    #[test]
+    fn decomposed_usernames_are_validated_like_composed_ones() {
+        let mut config = ValidationConfig::default();
+        config.username.allowed_characters = vec![CharacterClass::Letter];
+        config.username.max_length = 3;
+        let validator = Validator::new(&config);
+
+        assert!(validate(&validator, "Zo\u{eb}", "Zo\u{eb}").is_empty());
+        assert!(validate(&validator, "Zoe\u{308}", "Zo\u{eb}").is_empty());
+    }
+
+    #[test]
+    fn confusable_usernames_have_the_same_unique_key() {
+        let validator = Validator::new(&ValidationConfig::default());
+
+        let jane = validator.username_keys("Jane");
+        let cyrillic = validator.username_keys("J\u{430}ne");
+
+        assert_eq!("jane", jane.lookup);
+        assert_ne!(jane.lookup, cyrillic.lookup);
+        assert_eq!(jane.unique, cyrillic.unique);
+    }
+
+    proptest! {
+        #[test]
+        fn usernames_of_allowed_characters_have_no_character_violations(
+            username in "[a-zA-Z0-9_.-]{1,20}",
+        ) {
+            let validator = Validator::new(&ValidationConfig::default());
+
+            let reasons = validator.validate_username(&username);
+
+            prop_assert!(
+                reasons.iter().all(|reason| *reason == InvalidFieldReason::Reserved),
+                "{reasons:?}"
+            );
+        }
+
+        #[test]
+        fn usernames_that_differ_in_case_have_the_same_keys(username in "[a-zA-Z0-9_.-]{1,20}") {
+            let validator = Validator::new(&ValidationConfig::default());
+
+            prop_assert_eq!(
+                validator.username_keys(&username.to_lowercase()),
+                validator.username_keys(&username.to_uppercase())
+            );
+        }
+
+        #[test]
+        fn canonical_form_is_stable(username in any::<String>()) {
+            let canonical = canonical_username(&username);
+
+            prop_assert_eq!(&canonical, &canonical_username(&canonical));
+            prop_assert_eq!(&canonical, &canonical_username(&username.nfd().collect::<String>()));
+            prop_assert_eq!(&canonical, &canonical_username(&username.nfc().collect::<String>()));
+        }
+
+        #[test]
+        fn name_length_counts_graphemes(count in 1usize..=40) {
+            let validator = Validator::new(&ValidationConfig::default());
+            // An "e" with a combining acute accent is one grapheme of two
+            // characters.
+            let name = "e\u{301}".repeat(count);
+
+            let reasons = validator.validate_name(&name);
+
+            prop_assert_eq!(count > 20, reasons.contains(&InvalidFieldReason::TooLong));
+        }
+
+        #[test]
+        fn validation_never_panics(username in any::<String>(), name in any::<String>()) {
+            let validator = Validator::new(&ValidationConfig::default());
+
+            let _ = validate(&validator, &username, &name);
+        }
+    }
+}